@@ -0,0 +1,137 @@
+//! Throughput benchmarks for rendering a whole book.
+//!
+//! Run them with `cargo bench`. Besides the dummy book used by the
+//! integration tests, there's a synthetic book of roughly 2000 pages, which
+//! is closer to the size of the books where memory and wall-clock time
+//! actually become a problem.
+
+#[macro_use]
+extern crate criterion;
+extern crate mdbook;
+extern crate mdbook_epub;
+extern crate tempdir;
+
+use criterion::{Criterion, Throughput};
+use mdbook::book::BookItem;
+use mdbook::renderer::RenderContext;
+use mdbook::MDBook;
+use mdbook_epub::Generator;
+use std::fmt::Write;
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+use tempdir::TempDir;
+
+/// How many chapters the synthetic book has.
+const CHAPTERS: usize = 400;
+/// How many sections each synthetic chapter has, at roughly a page each.
+const SECTIONS: usize = 5;
+
+fn load(root: &Path, destination: &Path) -> RenderContext {
+    let md = MDBook::load(root).unwrap();
+
+    RenderContext::new(
+        md.root.clone(),
+        md.book.clone(),
+        md.config.clone(),
+        destination.to_path_buf(),
+    )
+}
+
+/// The total size of the book's markdown, for reporting throughput.
+fn source_bytes(ctx: &RenderContext) -> u64 {
+    ctx.book
+        .iter()
+        .filter_map(|item| match *item {
+            BookItem::Chapter(ref ch) => Some(ch.content.len() as u64),
+            _ => None,
+        })
+        .sum()
+}
+
+fn render(ctx: &RenderContext) -> usize {
+    let output = Cursor::new(Vec::new());
+    let report = Generator::new(ctx, output).unwrap().generate().unwrap();
+
+    report.chapters.len()
+}
+
+/// Write a book with `CHAPTERS` chapters into `root`, each using most of
+/// the markdown features a real book would.
+fn synthetic_book(root: &Path) {
+    let src = root.join("src");
+    fs::create_dir_all(&src).unwrap();
+    fs::copy(
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/dummy/src/rust-logo.png"),
+        src.join("rust-logo.png"),
+    )
+    .unwrap();
+    fs::write(
+        root.join("book.toml"),
+        "[book]\ntitle = \"Synthetic Book\"\nsrc = \"src\"\n\n\
+         [output.epub]\nabout-page = true\n",
+    )
+    .unwrap();
+
+    let mut summary = String::from("# Summary\n\n");
+    for chapter in 1..=CHAPTERS {
+        writeln!(summary, "- [Chapter {0}](chapter_{0}.md)", chapter).unwrap();
+        fs::write(
+            src.join(format!("chapter_{}.md", chapter)),
+            chapter_text(chapter),
+        )
+        .unwrap();
+    }
+    fs::write(src.join("SUMMARY.md"), summary).unwrap();
+}
+
+fn chapter_text(chapter: usize) -> String {
+    let mut text = format!("# Chapter {}\n\n", chapter);
+
+    for section in 1..=SECTIONS {
+        writeln!(text, "## Section {}.{}\n", chapter, section).unwrap();
+        for _ in 0..6 {
+            text.push_str(
+                "Lorem ipsum dolor sit amet, *consectetur* adipiscing elit, sed do \
+                 eiusmod tempor incididunt ut labore et dolore magna aliqua. Ut enim \
+                 ad minim veniam, quis nostrud `exercitation` ullamco laboris nisi ut \
+                 aliquip ex ea commodo consequat. Duis aute irure dolor in \
+                 [reprehenderit](https://example.com/) in voluptate velit esse cillum \
+                 dolore eu fugiat nulla pariatur.[^note]\n\n",
+            );
+        }
+        text.push_str("- Excepteur sint occaecat\n- cupidatat non proident\n- sunt in culpa\n\n");
+        text.push_str("```rust\nfn main() {\n    println!(\"Hello, World!\");\n}\n```\n\n");
+        text.push_str("| Name | Value |\n|------|-------|\n| one | 1 |\n| two | 2 |\n\n");
+    }
+
+    text.push_str("![The Rust logo](rust-logo.png)\n\n");
+    text.push_str("[^note]: Qui officia deserunt mollit anim id est laborum.\n");
+    text
+}
+
+fn dummy_book(c: &mut Criterion) {
+    let temp = TempDir::new("mdbook-epub").unwrap();
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/dummy");
+    let ctx = load(&root, temp.path());
+
+    let mut group = c.benchmark_group("dummy book");
+    group.throughput(Throughput::Bytes(source_bytes(&ctx)));
+    group.bench_function("render", |b| b.iter(|| render(&ctx)));
+    group.finish();
+}
+
+fn large_book(c: &mut Criterion) {
+    let temp = TempDir::new("mdbook-epub").unwrap();
+    synthetic_book(temp.path());
+    let ctx = load(temp.path(), &temp.path().join("book"));
+
+    let mut group = c.benchmark_group("large book");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(source_bytes(&ctx)));
+    group.bench_function("render", |b| b.iter(|| render(&ctx)));
+    group.finish();
+}
+
+criterion_group!(benches, dummy_book, large_book);
+criterion_main!(benches);
@@ -2,15 +2,13 @@
 //! compiled against and injecting it into the compiled crate for use with the
 //! `env!()` macro.
 
-extern crate cargo;
+extern crate serde_json;
 
+use serde_json::Value;
 use std::env;
 use std::error::Error;
 use std::path::Path;
-
-use cargo::core::Workspace;
-use cargo::ops;
-use cargo::util::Config;
+use std::process::Command;
 
 fn main() {
     let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
@@ -22,19 +20,35 @@ fn main() {
     println!("cargo:rerun-if-changed=Cargo.lock");
 }
 
-/// Find the version of the provided dependency as reported by `cargo`.
+/// Find the version of the provided dependency as reported by
+/// `cargo metadata`.
 fn find_dependency_version<P: AsRef<Path>>(
     manifest_dir: P,
     dep: &str,
-) -> Result<String, Box<Error>> {
-    let config = Config::default()?;
-
+) -> Result<String, Box<dyn Error>> {
+    let cargo = env::var("CARGO").unwrap_or_else(|_| String::from("cargo"));
     let manifest = manifest_dir.as_ref().join("Cargo.toml");
-    let ws = Workspace::new(&manifest, &config)?;
-
-    let (_, resolve) = ops::resolve_ws(&ws)?;
-
-    let mdbook = resolve.query(dep)?;
 
-    Ok(mdbook.version().to_string())
+    let output = Command::new(cargo)
+        .arg("metadata")
+        .arg("--format-version=1")
+        .arg("--manifest-path")
+        .arg(&manifest)
+        .output()?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).into_owned().into());
+    }
+
+    let metadata: Value = serde_json::from_slice(&output.stdout)?;
+    let version = metadata["packages"]
+        .as_array()
+        .and_then(|packages| {
+            packages
+                .iter()
+                .find(|package| package["name"] == dep)
+                .and_then(|package| package["version"].as_str())
+        })
+        .ok_or_else(|| format!("{} isn't a dependency", dep))?;
+
+    Ok(version.to_string())
 }
@@ -0,0 +1,478 @@
+//! An EPUB Accessibility 1.1 conformance report, written alongside the book.
+//!
+//! Only the criteria the builder can actually check are reported. Anything
+//! which needs a human to look at it (e.g. whether scripts are usable with a
+//! keyboard) is marked as needing manual review, and conformance is never
+//! claimed on the book's behalf.
+
+use chrono::Utc;
+use failure::{Error, ResultExt};
+use handlebars::Handlebars;
+use mdbook::renderer::RenderContext;
+use serde_json;
+use std::fmt::{self, Display, Formatter};
+
+use config::Config;
+use report::BuildReport;
+use utils::ResultExt as SyncResultExt;
+use DEFAULT_ACCESSIBILITY_SUMMARY;
+
+/// The accessibility metadata EPUB Accessibility 1.1 requires for a book to
+/// be discoverable.
+const DISCOVERY_PROPERTIES: &[&str] = &[
+    "schema:accessMode",
+    "schema:accessibilityFeature",
+    "schema:accessibilityHazard",
+    "schema:accessibilitySummary",
+];
+
+/// Facts about the book gathered while it's being built.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Audit {
+    /// How many images the chapters contain.
+    pub images: usize,
+    /// How many of those images have no alternative text.
+    pub images_without_alt_text: usize,
+    /// How many links point to a file or chapter which doesn't exist.
+    pub broken_links: usize,
+    /// How many print page numbers are in the page list.
+    pub page_targets: usize,
+    /// How many content documents contain scripts.
+    pub scripted_documents: usize,
+}
+
+/// The result of checking a single criterion, using the EARL outcomes.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Outcome {
+    Pass,
+    Fail,
+    /// Needs manual review.
+    CantTell,
+    Inapplicable,
+}
+
+impl Display for Outcome {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let description = match *self {
+            Outcome::Pass => "Passed",
+            Outcome::Fail => "Failed",
+            Outcome::CantTell => "Needs manual review",
+            Outcome::Inapplicable => "Not applicable",
+        };
+
+        f.write_str(description)
+    }
+}
+
+/// A single criterion and how the book did.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Check {
+    pub id: &'static str,
+    pub title: &'static str,
+    /// The specification the criterion comes from, e.g. "WCAG 2.1 1.1.1
+    /// (A)".
+    pub criterion: &'static str,
+    pub outcome: Outcome,
+    pub details: String,
+}
+
+/// Check every criterion the builder can verify.
+pub fn evaluate(config: &Config, audit: &Audit) -> Vec<Check> {
+    let missing: Vec<&str> = DISCOVERY_PROPERTIES
+        .iter()
+        .cloned()
+        .filter(|property| {
+            !config
+                .extra_opf_meta
+                .iter()
+                .any(|m| m.property == *property)
+        })
+        .collect();
+    let discovery = if missing.is_empty() {
+        (
+            Outcome::Pass,
+            "All of the required metadata is present".to_string(),
+        )
+    } else {
+        (
+            Outcome::Fail,
+            format!("Missing from extra-opf-meta: {}", missing.join(", ")),
+        )
+    };
+
+    let page_navigation = if audit.page_targets > 0 {
+        (
+            Outcome::Pass,
+            format!("The page list has {} page numbers", audit.page_targets),
+        )
+    } else {
+        (
+            Outcome::Inapplicable,
+            "The book has no print page numbers".to_string(),
+        )
+    };
+
+    let non_text = match (audit.images, audit.images_without_alt_text) {
+        (0, _) => (Outcome::Inapplicable, "The book has no images".to_string()),
+        (images, 0) => (
+            Outcome::Pass,
+            format!("All {} images have alternative text", images),
+        ),
+        (images, missing) => (
+            Outcome::Fail,
+            format!("{} of {} images have no alternative text", missing, images),
+        ),
+    };
+
+    let language = match config.language {
+        Some(ref language) => (Outcome::Pass, format!("The book is in \"{}\"", language)),
+        None => (Outcome::Fail, "book.language isn't set".to_string()),
+    };
+
+    let links = if audit.broken_links == 0 {
+        (Outcome::Pass, "Every local link resolves".to_string())
+    } else {
+        (
+            Outcome::Fail,
+            format!(
+                "{} links point to something which doesn't exist",
+                audit.broken_links
+            ),
+        )
+    };
+
+    let keyboard = if audit.scripted_documents == 0 {
+        (Outcome::Inapplicable, "The book has no scripts".to_string())
+    } else {
+        (
+            Outcome::CantTell,
+            format!(
+                "{} documents contain scripts which need to be checked by hand",
+                audit.scripted_documents
+            ),
+        )
+    };
+
+    vec![
+        check(
+            "epub-discovery",
+            "Accessibility metadata",
+            "EPUB Accessibility 1.1, 5.1",
+            discovery,
+        ),
+        check(
+            "epub-page-navigation",
+            "Page navigation",
+            "EPUB Accessibility 1.1, 4.1",
+            page_navigation,
+        ),
+        check(
+            "wcag-non-text-content",
+            "Non-text content",
+            "WCAG 2.1 1.1.1 (A)",
+            non_text,
+        ),
+        check("wcag-keyboard", "Keyboard", "WCAG 2.1 2.1.1 (A)", keyboard),
+        check(
+            "wcag-page-titled",
+            "Page titled",
+            "WCAG 2.1 2.4.2 (A)",
+            (
+                Outcome::Pass,
+                "Every document is titled after its chapter".to_string(),
+            ),
+        ),
+        check(
+            "wcag-multiple-ways",
+            "Multiple ways",
+            "WCAG 2.1 2.4.5 (AA)",
+            (
+                Outcome::Pass,
+                "The book has a table of contents and a linear reading order".to_string(),
+            ),
+        ),
+        check(
+            "wcag-language-of-page",
+            "Language of page",
+            "WCAG 2.1 3.1.1 (A)",
+            language,
+        ),
+        check("epub-links", "Links resolve", "EPUB 3.2, 3.4", links),
+    ]
+}
+
+fn check(
+    id: &'static str,
+    title: &'static str,
+    criterion: &'static str,
+    (outcome, details): (Outcome, String),
+) -> Check {
+    Check {
+        id,
+        title,
+        criterion,
+        outcome,
+        details,
+    }
+}
+
+/// The worst outcome of any check.
+fn overall(checks: &[Check]) -> Outcome {
+    let has = |outcome| checks.iter().any(|check| check.outcome == outcome);
+
+    if has(Outcome::Fail) {
+        Outcome::Fail
+    } else if has(Outcome::CantTell) {
+        Outcome::CantTell
+    } else {
+        Outcome::Pass
+    }
+}
+
+/// Render the report as JSON, in roughly the same shape as the reports
+/// written by the DAISY Ace checker.
+pub fn render_json(
+    ctx: &RenderContext,
+    checks: &[Check],
+    report: &BuildReport,
+) -> Result<String, Error> {
+    let assertions = checks
+        .iter()
+        .map(|check| EarlAssertion {
+            kind: "earl:assertion",
+            test: EarlTest {
+                id: check.id,
+                title: check.title,
+                criterion: check.criterion,
+            },
+            result: EarlResult {
+                outcome: check.outcome,
+                description: &check.details,
+            },
+        })
+        .collect();
+
+    let earl = EarlReport {
+        kind: "earl:report",
+        title: "mdbook-epub accessibility report",
+        date: Utc::now().to_rfc3339(),
+        asserted_by: EarlAssertor {
+            name: "mdbook-epub",
+            revision: env!("CARGO_PKG_VERSION"),
+        },
+        outcome: overall(checks),
+        subject: EarlSubject {
+            title: ctx.config.book.title.as_deref(),
+            identifier: report.identifier.as_deref(),
+        },
+        assertions,
+    };
+
+    let json = serde_json::to_string_pretty(&earl).context("Unable to serialize the report")?;
+    Ok(json)
+}
+
+/// Render a human-readable summary of the report.
+pub fn render_summary(ctx: &RenderContext, checks: &[Check]) -> Result<String, Error> {
+    let count = |outcome| {
+        checks
+            .iter()
+            .filter(|check| check.outcome == outcome)
+            .count()
+    };
+    let rows: Vec<SummaryRow> = checks
+        .iter()
+        .map(|check| SummaryRow {
+            title: check.title,
+            criterion: check.criterion,
+            outcome: check.outcome.to_string(),
+            passed: check.outcome == Outcome::Pass,
+            failed: check.outcome == Outcome::Fail,
+            details: &check.details,
+        })
+        .collect();
+
+    let summary = Summary {
+        title: ctx.config.book.title.as_deref(),
+        outcome: overall(checks).to_string(),
+        passed: count(Outcome::Pass),
+        failed: count(Outcome::Fail),
+        manual: count(Outcome::CantTell),
+        inapplicable: count(Outcome::Inapplicable),
+        checks: rows,
+    };
+
+    let rendered = Handlebars::new()
+        .render_template(DEFAULT_ACCESSIBILITY_SUMMARY, &summary)
+        .sync()
+        .context("Unable to render the accessibility summary")?;
+
+    Ok(rendered)
+}
+
+#[derive(Debug, Serialize)]
+struct EarlReport<'a> {
+    #[serde(rename = "@type")]
+    kind: &'static str,
+    #[serde(rename = "dct:title")]
+    title: &'static str,
+    #[serde(rename = "dct:date")]
+    date: String,
+    #[serde(rename = "earl:assertedBy")]
+    asserted_by: EarlAssertor,
+    #[serde(rename = "earl:outcome")]
+    outcome: Outcome,
+    #[serde(rename = "earl:testSubject")]
+    subject: EarlSubject<'a>,
+    assertions: Vec<EarlAssertion<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct EarlAssertor {
+    #[serde(rename = "doap:name")]
+    name: &'static str,
+    #[serde(rename = "doap:revision")]
+    revision: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+struct EarlSubject<'a> {
+    #[serde(rename = "dct:title")]
+    title: Option<&'a str>,
+    #[serde(rename = "dct:identifier")]
+    identifier: Option<&'a str>,
+}
+
+#[derive(Debug, Serialize)]
+struct EarlAssertion<'a> {
+    #[serde(rename = "@type")]
+    kind: &'static str,
+    #[serde(rename = "earl:test")]
+    test: EarlTest,
+    #[serde(rename = "earl:result")]
+    result: EarlResult<'a>,
+}
+
+#[derive(Debug, Serialize)]
+struct EarlTest {
+    #[serde(rename = "dct:identifier")]
+    id: &'static str,
+    #[serde(rename = "dct:title")]
+    title: &'static str,
+    #[serde(rename = "dct:references")]
+    criterion: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+struct EarlResult<'a> {
+    #[serde(rename = "earl:outcome")]
+    outcome: Outcome,
+    #[serde(rename = "dct:description")]
+    description: &'a str,
+}
+
+/// The information made available to the summary template.
+#[derive(Debug, Serialize)]
+struct Summary<'a> {
+    title: Option<&'a str>,
+    outcome: String,
+    passed: usize,
+    failed: usize,
+    manual: usize,
+    inapplicable: usize,
+    checks: Vec<SummaryRow<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct SummaryRow<'a> {
+    title: &'static str,
+    criterion: &'static str,
+    /// The outcome, as a human-readable string.
+    outcome: String,
+    passed: bool,
+    failed: bool,
+    details: &'a str,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::ExtraMeta;
+    use mdbook::book::Book;
+    use mdbook::Config as MdConfig;
+
+    fn context() -> RenderContext {
+        let mut config = MdConfig::default();
+        config.book.title = Some(String::from("A Book"));
+
+        RenderContext::new("/book", Book::new(), config, "/book/book")
+    }
+
+    fn english() -> Config {
+        Config {
+            language: Some(String::from("en")),
+            ..Default::default()
+        }
+    }
+
+    fn outcome(checks: &[Check], id: &str) -> Outcome {
+        checks.iter().find(|check| check.id == id).unwrap().outcome
+    }
+
+    #[test]
+    fn images_without_alt_text_fail() {
+        let audit = Audit {
+            images: 4,
+            images_without_alt_text: 1,
+            ..Default::default()
+        };
+
+        let checks = evaluate(&english(), &audit);
+
+        assert_eq!(outcome(&checks, "wcag-non-text-content"), Outcome::Fail);
+        assert_eq!(outcome(&checks, "wcag-language-of-page"), Outcome::Pass);
+        assert_eq!(overall(&checks), Outcome::Fail);
+    }
+
+    #[test]
+    fn unverifiable_criteria_need_manual_review() {
+        let mut config = english();
+        config.extra_opf_meta = DISCOVERY_PROPERTIES
+            .iter()
+            .map(|property| ExtraMeta {
+                property: property.to_string(),
+                value: String::from("textual"),
+                id: None,
+                refines: None,
+                scheme: None,
+            })
+            .collect();
+        let audit = Audit {
+            scripted_documents: 2,
+            ..Default::default()
+        };
+
+        let checks = evaluate(&config, &audit);
+
+        assert_eq!(outcome(&checks, "epub-discovery"), Outcome::Pass);
+        assert_eq!(
+            outcome(&checks, "wcag-non-text-content"),
+            Outcome::Inapplicable
+        );
+        assert_eq!(outcome(&checks, "wcag-keyboard"), Outcome::CantTell);
+        assert_eq!(overall(&checks), Outcome::CantTell);
+    }
+
+    #[test]
+    fn json_report_uses_earl_terms() {
+        let ctx = context();
+        let checks = evaluate(&Config::default(), &Audit::default());
+
+        let got = render_json(&ctx, &checks, &BuildReport::default()).unwrap();
+
+        assert!(got.contains(r#""@type": "earl:report""#));
+        assert!(got.contains(r#""earl:outcome": "fail""#));
+        assert!(got.contains("book.language isn't set"));
+    }
+}
@@ -0,0 +1,73 @@
+//! Records which make an archived EPUB self-describing, so it can be traced
+//! back to (and regenerated from) the sources it was built from.
+
+use chrono::{DateTime, Utc};
+use mdbook::renderer::RenderContext;
+use std::path::Path;
+use std::process::Command;
+
+/// The directory (inside `OEBPS/`) the sources and build info are written
+/// to. Nothing in it is part of the spine.
+pub const SOURCES_DIR: &str = "sources";
+
+/// How and when the book was built.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BuildInfo {
+    /// The version of `mdbook-epub` which rendered the book.
+    pub mdbook_epub_version: String,
+    /// The version of `mdbook` which rendered the book.
+    pub mdbook_version: String,
+    /// When the book was built, as an RFC 3339 timestamp.
+    pub build_date: String,
+    /// The commit checked out when the book was built, if it lives in a git
+    /// repository.
+    pub git_commit: Option<String>,
+}
+
+impl BuildInfo {
+    pub fn new(ctx: &RenderContext, now: DateTime<Utc>) -> BuildInfo {
+        BuildInfo {
+            mdbook_epub_version: env!("CARGO_PKG_VERSION").to_string(),
+            mdbook_version: ctx.version.clone(),
+            build_date: now.to_rfc3339(),
+            git_commit: git_commit(&ctx.root),
+        }
+    }
+}
+
+/// Ask `git` which commit is checked out in this directory.
+fn git_commit(dir: &Path) -> Option<String> {
+    let output = Command::new("git")
+        .arg("rev-parse")
+        .arg("HEAD")
+        .current_dir(dir)
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        debug!("Unable to find the git commit for {}", dir.display());
+        return None;
+    }
+
+    let commit = String::from_utf8(output.stdout).ok()?;
+    let commit = commit.trim();
+
+    if commit.is_empty() {
+        None
+    } else {
+        Some(commit.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn missing_directories_have_no_commit() {
+        let temp = TempDir::new("mdbook-epub").unwrap();
+
+        assert_eq!(git_commit(&temp.path().join("nowhere")), None);
+    }
+}
@@ -0,0 +1,471 @@
+//! The zip archive the EPUB is written into.
+//!
+//! `epub-builder` writes files into a `Zip` implementation as they're added.
+//! Rather than using its `ZipLibrary` (which keeps the entire archive in
+//! memory until the book is finished) we stream each file straight into the
+//! output. This also gives us a place to post-process files (e.g. obfuscating
+//! fonts) without needing to unpack and repack the final document.
+//!
+//! Unless `asset-placement = "streamed"`, files are spooled and written in
+//! reading order once the book is finished (see the `spool` module).
+//!
+//! Documents which can only be rendered once the rest of the book has been
+//! (e.g. the about page) are deferred. They're added to the book in their
+//! place, but their content is written once the generator has rendered it.
+
+use epub_builder::{self, Zip};
+use failure::Error;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Debug, Display, Formatter};
+use std::io::{self, Read, Seek, Write};
+use std::mem;
+use std::path::Path;
+use std::rc::Rc;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use config::{AssetPlacement, Profile};
+use fonts;
+use links;
+use opf;
+use page_list::{self, PageTarget};
+use split::{self, Anchors};
+use spool::Spool;
+
+/// The path `epub-builder` writes the package document to.
+const PACKAGE_DOCUMENT: &str = "OEBPS/content.opf";
+/// The path `epub-builder` writes the EPUB 3 navigation document to.
+const NAV_DOCUMENT: &str = "OEBPS/nav.xhtml";
+/// The path `epub-builder` writes the EPUB 2 NCX to.
+const NCX: &str = "OEBPS/toc.ncx";
+
+/// A `Zip` implementation which streams files into the underlying writer,
+/// intercepting certain files on their way into the archive.
+pub struct Archive<W: Write + Seek> {
+    writer: ZipWriter<W>,
+    obfuscate_fonts: bool,
+    /// Fonts waiting to be obfuscated. The obfuscation key is derived from the
+    /// package's unique identifier, and we don't know that until
+    /// `epub-builder` writes the package document.
+    pending_fonts: Vec<(String, Vec<u8>)>,
+    package: Rc<RefCell<Package>>,
+    placement: AssetPlacement,
+    /// Where files are held until the archive is finished, so they can be
+    /// reordered.
+    spool: Option<Spool>,
+    /// The cover image's entry, found in the package document's manifest.
+    cover: Option<String>,
+}
+
+/// Information about the package shared between the `Generator` and the
+/// `Archive`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Package {
+    /// Print page numbers to add to the navigation document, filled in by the
+    /// `Generator` as chapters are rendered.
+    pub page_list: Vec<PageTarget>,
+    /// The package's unique identifier, filled in once `epub-builder` has
+    /// written the package document.
+    pub identifier: Option<String>,
+    /// Extra elements to add to the package document's metadata.
+    pub metadata: Vec<String>,
+    /// The `href` of every content document which contains scripts.
+    pub scripted: Vec<String>,
+    /// Which reading systems the package is for.
+    pub profile: Profile,
+    /// The `href` of the image `<meta name="cover">` should point to.
+    pub cover_meta: Option<String>,
+    /// Where the anchors in the later parts of split chapters ended up.
+    pub anchors: Anchors,
+    /// Content documents which link to anchors in other chapters, and may
+    /// need those links pointing at a later part of a split chapter.
+    pub linking_documents: HashSet<String>,
+    /// Content documents which are rendered after they're added to the book,
+    /// along with their content once it's been rendered.
+    pub deferred: HashMap<String, Option<Vec<u8>>>,
+}
+
+impl Default for Package {
+    fn default() -> Package {
+        Package {
+            page_list: Vec::new(),
+            identifier: None,
+            metadata: Vec::new(),
+            scripted: Vec::new(),
+            profile: Profile::Compat,
+            cover_meta: None,
+            anchors: Anchors::new(),
+            linking_documents: HashSet::new(),
+            deferred: HashMap::new(),
+        }
+    }
+}
+
+impl<W: Write + Seek> Archive<W> {
+    pub fn new(
+        sink: W,
+        obfuscate_fonts: bool,
+        placement: AssetPlacement,
+        package: Rc<RefCell<Package>>,
+    ) -> Result<Archive<W>, Error> {
+        let mut writer = ZipWriter::new(sink);
+
+        // the mimetype must be the first file in the archive, and it can't be
+        // compressed
+        let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+        writer.start_file("mimetype", options)?;
+        writer.write_all(b"application/epub+zip")?;
+
+        let spool = match placement {
+            AssetPlacement::Streamed => None,
+            _ => Some(Spool::new()?),
+        };
+
+        Ok(Archive {
+            writer,
+            obfuscate_fonts,
+            pending_fonts: Vec::new(),
+            package,
+            placement,
+            spool,
+            cover: None,
+        })
+    }
+
+    /// Add a file to the archive, or to the spool if it's being reordered.
+    fn add<R: Read>(&mut self, name: &str, content: R) -> epub_builder::Result<()> {
+        match self.spool {
+            Some(ref mut spool) => spool
+                .add(name, content)
+                .map_err(|e| epub_error(&format!("Unable to spool {}", name), e)),
+            None => self.write_entry(name, content),
+        }
+    }
+
+    /// Copy a file into the archive.
+    fn write_entry<R: Read>(&mut self, name: &str, mut content: R) -> epub_builder::Result<()> {
+        trace!("Writing {} to the archive", name);
+
+        self.writer
+            .start_file(name, FileOptions::default())
+            .map_err(|e| epub_error(&format!("Unable to add {}", name), e))?;
+        io::copy(&mut content, &mut self.writer)
+            .map_err(|e| epub_error(&format!("Unable to write {}", name), e))?;
+
+        Ok(())
+    }
+
+    /// Write out every pending font, obfuscated using the key derived from
+    /// this package document.
+    fn write_obfuscated_fonts(&mut self, opf: &str) -> epub_builder::Result<()> {
+        if self.pending_fonts.is_empty() {
+            return Ok(());
+        }
+
+        let identifier = fonts::package_identifier(opf)
+            .ok_or_else(|| epub_error("Unable to obfuscate fonts", "no package identifier"))?;
+        let key = fonts::key(identifier);
+
+        let pending = mem::take(&mut self.pending_fonts);
+        let mut paths = Vec::new();
+
+        for (path, mut data) in pending {
+            debug!("Obfuscating {}", path);
+            fonts::obfuscate(&key, &mut data);
+            self.add(&path, data.as_slice())?;
+            paths.push(links::encode(&path));
+        }
+
+        let encryption = fonts::encryption_xml(&paths);
+        self.add("META-INF/encryption.xml", encryption.as_bytes())
+    }
+}
+
+impl<W: Write + Seek> Zip for Archive<W> {
+    fn write_file<P: AsRef<Path>, R: Read>(
+        &mut self,
+        path: P,
+        mut content: R,
+    ) -> epub_builder::Result<()> {
+        let name = entry_name(path.as_ref());
+
+        if name == "mimetype" {
+            // we've already written this
+            return Ok(());
+        }
+
+        let document = name.trim_start_matches("OEBPS/");
+        if self.package.borrow().deferred.contains_key(document) {
+            trace!("Deferring {} until it's been rendered", name);
+            if let Some(ref mut spool) = self.spool {
+                spool.reserve(&name);
+            }
+            return Ok(());
+        }
+
+        if self.obfuscate_fonts && fonts::is_font(path.as_ref()) {
+            let mut data = Vec::new();
+            content
+                .read_to_end(&mut data)
+                .map_err(|e| epub_error(&format!("Unable to read {}", name), e))?;
+            self.pending_fonts.push((name, data));
+            return Ok(());
+        }
+
+        if name == PACKAGE_DOCUMENT {
+            let mut opf = String::new();
+            content
+                .read_to_string(&mut opf)
+                .map_err(|e| epub_error("Unable to read the package document", e))?;
+            let mut opf = opf::insert_metadata(&opf, &self.package.borrow().metadata);
+            opf = opf::add_property(&opf, &self.package.borrow().scripted, "scripted");
+            if let Some(ref href) = self.package.borrow().cover_meta {
+                opf = opf::set_cover_meta(&opf, href);
+            }
+            if self.package.borrow().profile == Profile::Epub3 {
+                opf = opf::strip_epub2(&opf);
+            }
+
+            self.package.borrow_mut().identifier =
+                fonts::package_identifier(&opf).map(String::from);
+            self.cover =
+                opf::cover_image(&opf).map(|href| entry_name(&Path::new("OEBPS").join(href)));
+
+            self.add(&name, opf.as_bytes())?;
+            return self.write_obfuscated_fonts(&opf);
+        }
+
+        if name == NCX && self.package.borrow().profile == Profile::Epub3 {
+            trace!("Leaving the NCX out of a strict EPUB 3 document");
+            return Ok(());
+        }
+
+        if name == NAV_DOCUMENT {
+            let mut nav = String::new();
+            content
+                .read_to_string(&mut nav)
+                .map_err(|e| epub_error("Unable to read the navigation document", e))?;
+
+            let nav = page_list::insert_nav(&nav, &self.package.borrow().page_list);
+            return self.add(&name, nav.as_bytes());
+        }
+
+        self.add(&name, content)
+    }
+
+    /// Finish writing the archive.
+    ///
+    /// Everything has already been streamed into the sink passed to
+    /// `Archive::new()`, so the writer `epub-builder` hands us is unused.
+    fn generate<X: Write>(&mut self, _to: X) -> epub_builder::Result<()> {
+        if !self.pending_fonts.is_empty() {
+            return Err(epub_error(
+                "Unable to obfuscate fonts",
+                "the package document was never written",
+            ));
+        }
+
+        let deferred = mem::take(&mut self.package.borrow_mut().deferred);
+        for (document, content) in deferred {
+            let name = format!("OEBPS/{}", document);
+            let content = content.ok_or_else(|| {
+                epub_error(&format!("Unable to add {}", name), "it was never rendered")
+            })?;
+            match self.spool {
+                Some(ref mut spool) => spool
+                    .fill(&name, content.as_slice())
+                    .map_err(|e| epub_error(&format!("Unable to spool {}", name), e))?,
+                None => self.write_entry(&name, content.as_slice())?,
+            }
+        }
+
+        if let Some(spool) = self.spool.take() {
+            let mut spooled = spool
+                .finish()
+                .map_err(|e| epub_error("Unable to finish the spool", e))?;
+            let placement = self.placement;
+            let cover = self.cover.take();
+            spooled.sort_by_key(|name| rank(name, placement, cover.as_deref()));
+
+            for ix in 0..spooled.len() {
+                let mut entry = spooled
+                    .read(ix)
+                    .map_err(|e| epub_error("Unable to read the spool", e))?;
+                let name = entry.name().to_string();
+
+                let document = name.trim_start_matches("OEBPS/");
+                if !self.package.borrow().linking_documents.contains(document) {
+                    // it's already compressed, so there's no need to do it again
+                    self.writer
+                        .raw_copy_file(entry)
+                        .map_err(|e| epub_error(&format!("Unable to add {}", name), e))?;
+                    continue;
+                }
+
+                let mut html = String::new();
+                entry
+                    .read_to_string(&mut html)
+                    .map_err(|e| epub_error("Unable to read the spool", e))?;
+                let html =
+                    split::link_across_chapters(&html, document, &self.package.borrow().anchors);
+                self.write_entry(&name, html.as_bytes())?;
+            }
+        }
+
+        self.writer
+            .finish()
+            .map_err(|e| epub_error("Unable to finish the archive", e))?;
+
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> Debug for Archive<W> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Archive")
+            .field("obfuscate_fonts", &self.obfuscate_fonts)
+            .field("pending_fonts", &self.pending_fonts.len())
+            .field("package", &self.package.borrow())
+            .finish()
+    }
+}
+
+/// Where an entry goes in the archive, lowest first. Everything a reading
+/// system needs before it can show the first page comes first, followed by
+/// the content documents in reading order.
+fn rank(name: &str, placement: AssetPlacement, cover: Option<&str>) -> u8 {
+    if name == "META-INF/container.xml" {
+        0
+    } else if name.starts_with("META-INF/") {
+        1
+    } else if name == PACKAGE_DOCUMENT {
+        2
+    } else if name == NAV_DOCUMENT || name == NCX {
+        3
+    } else if Some(name) == cover {
+        4
+    } else if name.ends_with(".css") || fonts::is_font(Path::new(name)) {
+        5
+    } else if name.ends_with(".xhtml") || name.ends_with(".html") {
+        7
+    } else if placement == AssetPlacement::BeforeSpine {
+        6
+    } else {
+        8
+    }
+}
+
+/// Get the name a file will have inside the zip archive (always using `/` as
+/// a separator).
+///
+/// Paths are handed to `epub-builder` percent-encoded so they can be used
+/// as-is in the package document, so they need to be decoded again here.
+fn entry_name(path: &Path) -> String {
+    links::decode(&path.display().to_string().replace('\\', "/"))
+}
+
+fn epub_error<E: Display>(msg: &str, cause: E) -> epub_builder::Error {
+    epub_builder::Error::from(format!("{}: {}", msg, cause))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{self, Cursor};
+    use zip::ZipArchive;
+
+    #[test]
+    fn mimetype_is_the_first_uncompressed_file() {
+        let mut buffer = Cursor::new(Vec::new());
+
+        {
+            let mut archive = Archive::new(
+                &mut buffer,
+                false,
+                AssetPlacement::AfterSpine,
+                Default::default(),
+            )
+            .unwrap();
+            archive
+                .write_file("OEBPS/chapter_1.html", "<h1>Chapter 1</h1>".as_bytes())
+                .unwrap();
+            archive.generate(io::sink()).unwrap();
+        }
+
+        let mut zip = ZipArchive::new(buffer).unwrap();
+
+        assert_eq!(zip.len(), 2);
+        let mimetype = zip.by_index(0).unwrap();
+        assert_eq!(mimetype.name(), "mimetype");
+        assert_eq!(mimetype.compression(), CompressionMethod::Stored);
+    }
+
+    fn entries(placement: AssetPlacement) -> Vec<String> {
+        let mut buffer = Cursor::new(Vec::new());
+        let opf = r#"<package><manifest><item id="cover" href="images/cover.png" media-type="image/png" properties="cover-image" /></manifest></package>"#;
+
+        {
+            let mut archive =
+                Archive::new(&mut buffer, false, placement, Default::default()).unwrap();
+            let files = vec![
+                ("OEBPS/images/diagram.png", "png"),
+                ("OEBPS/chapter_1.xhtml", "<h1>Chapter 1</h1>"),
+                ("OEBPS/images/cover.png", "png"),
+                ("OEBPS/chapter_2.xhtml", "<h1>Chapter 2</h1>"),
+                ("OEBPS/stylesheet.css", "body {}"),
+                ("META-INF/container.xml", "<container />"),
+                (PACKAGE_DOCUMENT, opf),
+                (NAV_DOCUMENT, "<nav />"),
+            ];
+            for (name, content) in files {
+                archive.write_file(name, content.as_bytes()).unwrap();
+            }
+            archive.generate(io::sink()).unwrap();
+        }
+
+        let mut zip = ZipArchive::new(buffer).unwrap();
+        (0..zip.len())
+            .map(|ix| zip.by_index(ix).unwrap().name().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn entries_are_written_in_reading_order() {
+        let got = entries(AssetPlacement::AfterSpine);
+
+        assert_eq!(
+            got,
+            vec![
+                "mimetype",
+                "META-INF/container.xml",
+                PACKAGE_DOCUMENT,
+                NAV_DOCUMENT,
+                "OEBPS/images/cover.png",
+                "OEBPS/stylesheet.css",
+                "OEBPS/chapter_1.xhtml",
+                "OEBPS/chapter_2.xhtml",
+                "OEBPS/images/diagram.png",
+            ]
+        );
+    }
+
+    #[test]
+    fn assets_can_go_before_the_spine() {
+        let got = entries(AssetPlacement::BeforeSpine);
+
+        let diagram = got
+            .iter()
+            .position(|name| name == "OEBPS/images/diagram.png");
+        let chapter = got.iter().position(|name| name == "OEBPS/chapter_1.xhtml");
+        assert!(diagram < chapter);
+    }
+
+    #[test]
+    fn streamed_archives_keep_the_order_files_were_written() {
+        let got = entries(AssetPlacement::Streamed);
+
+        assert_eq!(got[1], "OEBPS/images/diagram.png");
+        assert_eq!(got.last().unwrap(), NAV_DOCUMENT);
+    }
+}
@@ -0,0 +1,692 @@
+//! Citations and the generated references page.
+//!
+//! References are read from a BibTeX (`.bib`) or CSL-JSON (`.json`) file and
+//! cited with pandoc-style keys, e.g. `[@knuth1984]`, `[@knuth1984, p. 33]`
+//! or `[@knuth1984; @lamport1994]`. Citations are rendered author-date style
+//! and link to their entry on the references page, which lists everything
+//! cited in the book.
+
+use failure::{self, Error, ResultExt};
+use pulldown_cmark::{Event, Tag};
+use serde_json;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+use opf;
+
+/// A single author or editor.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Name {
+    pub family: String,
+    pub given: Option<String>,
+}
+
+/// A reference which can be cited.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub key: String,
+    pub authors: Vec<Name>,
+    pub title: Option<String>,
+    pub year: Option<String>,
+    /// The journal, book or proceedings this was published in.
+    pub container: Option<String>,
+    pub publisher: Option<String>,
+    pub url: Option<String>,
+}
+
+/// Every reference available to the book, and which ones have been cited.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Bibliography {
+    entries: BTreeMap<String, Entry>,
+    cited: BTreeSet<String>,
+}
+
+impl Bibliography {
+    /// Load a bibliography, using the file extension to tell whether it's
+    /// BibTeX or CSL-JSON.
+    pub fn load(path: &Path) -> Result<Bibliography, Error> {
+        let text = fs::read_to_string(path)
+            .with_context(|_| format!("Unable to read the bibliography, {}", path.display()))?;
+
+        let entries = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("bib") => parse_bibtex(&text),
+            Some("json") => parse_csl_json(&text),
+            _ => Err(failure::err_msg(
+                "The bibliography must be a BibTeX (.bib) or CSL-JSON (.json) file",
+            )),
+        }
+        .with_context(|_| format!("Unable to parse the bibliography, {}", path.display()))?;
+
+        debug!(
+            "Loaded {} references from {}",
+            entries.len(),
+            path.display()
+        );
+        Ok(Bibliography::new(entries))
+    }
+
+    pub fn new(entries: Vec<Entry>) -> Bibliography {
+        Bibliography {
+            entries: entries
+                .into_iter()
+                .map(|entry| (entry.key.clone(), entry))
+                .collect(),
+            cited: BTreeSet::new(),
+        }
+    }
+
+    /// Replace every citation with a link to its entry on the references
+    /// page. Citations using unknown keys are left as they are.
+    pub fn cite<'a, I>(&mut self, events: I, references_href: &str) -> Vec<Event<'a>>
+    where
+        I: IntoIterator<Item = Event<'a>>,
+    {
+        let mut out = Vec::new();
+        let mut text = String::new();
+        let mut in_code = false;
+
+        for event in events {
+            match event {
+                Event::Text(ref t) if !in_code => {
+                    text.push_str(t);
+                    continue;
+                }
+                Event::Start(Tag::CodeBlock(_)) | Event::Start(Tag::Code) => in_code = true,
+                Event::End(Tag::CodeBlock(_)) | Event::End(Tag::Code) => in_code = false,
+                _ => {}
+            }
+
+            self.flush(&mut text, &mut out, references_href);
+            out.push(event);
+        }
+
+        self.flush(&mut text, &mut out, references_href);
+        out
+    }
+
+    /// Emit the text we've seen so far, with every citation replaced.
+    fn flush<'a>(&mut self, text: &mut String, out: &mut Vec<Event<'a>>, href: &str) {
+        if text.is_empty() {
+            return;
+        }
+
+        let mut rest = &text[..];
+
+        while let Some((start, citations, end)) = self.next_citation(rest) {
+            if start > 0 {
+                out.push(Event::Text(rest[..start].to_string().into()));
+            }
+            out.push(Event::InlineHtml(
+                self.render_citation(&citations, href).into(),
+            ));
+            rest = &rest[end..];
+        }
+
+        if !rest.is_empty() {
+            out.push(Event::Text(rest.to_string().into()));
+        }
+
+        text.clear();
+    }
+
+    /// Find the next citation whose keys are all known, returning where it
+    /// starts, each key and locator, and where it ends.
+    fn next_citation<'t>(&self, text: &'t str) -> Option<(usize, Vec<Citation<'t>>, usize)> {
+        let mut offset = 0;
+
+        while let Some(ix) = text[offset..].find("[@") {
+            let start = offset + ix;
+            let end = start + text[start..].find(']')?;
+
+            offset = start + 2;
+
+            let citations = match parse_citations(&text[start + 1..end]) {
+                Some(citations) => citations,
+                None => continue,
+            };
+            let unknown: Vec<&str> = citations
+                .iter()
+                .map(|citation| citation.key)
+                .filter(|key| !self.entries.contains_key(*key))
+                .collect();
+
+            if unknown.is_empty() {
+                return Some((start, citations, end + 1));
+            }
+            for key in unknown {
+                warn!("The bibliography has no entry for \"@{}\"", key);
+            }
+        }
+
+        None
+    }
+
+    fn render_citation(&mut self, citations: &[Citation], href: &str) -> String {
+        let mut parts = Vec::new();
+
+        for citation in citations {
+            self.cited.insert(citation.key.to_string());
+            let entry = &self.entries[citation.key];
+
+            let mut label = format!("{} {}", short_authors(entry), year(entry));
+            if let Some(locator) = citation.locator {
+                label.push_str(", ");
+                label.push_str(locator);
+            }
+
+            parts.push(format!(
+                "<a class=\"citation\" href=\"{}#{}\">{}</a>",
+                href,
+                anchor(&entry.key),
+                opf::escape(&label)
+            ));
+        }
+
+        format!("({})", parts.join("; "))
+    }
+
+    /// Has anything been cited yet?
+    pub fn is_cited(&self) -> bool {
+        !self.cited.is_empty()
+    }
+
+    /// The entries which have been cited, sorted by author, year and title.
+    pub fn cited(&self) -> Vec<&Entry> {
+        let mut cited: Vec<&Entry> = self
+            .cited
+            .iter()
+            .filter_map(|key| self.entries.get(key))
+            .collect();
+        cited.sort_by_key(|entry| (short_authors(entry), year(entry), entry.title.clone()));
+        cited
+    }
+
+    /// The references page's list of every cited entry.
+    pub fn references_list(&self) -> String {
+        let mut html = String::from("<ul class=\"references\">\n");
+
+        for entry in self.cited() {
+            html.push_str(&format!(
+                "<li id=\"{}\">{}</li>\n",
+                anchor(&entry.key),
+                format_entry(entry)
+            ));
+        }
+
+        html.push_str("</ul>\n");
+        html
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Citation<'t> {
+    key: &'t str,
+    locator: Option<&'t str>,
+}
+
+/// Parse the inside of a citation, e.g. `@knuth1984, p. 33; @lamport1994`.
+fn parse_citations(text: &str) -> Option<Vec<Citation<'_>>> {
+    text.split(';')
+        .map(|citation| {
+            let citation = citation.trim();
+            if !citation.starts_with('@') {
+                return None;
+            }
+
+            let citation = &citation[1..];
+            let key_end = citation
+                .find(|c: char| c == ',' || c.is_whitespace())
+                .unwrap_or(citation.len());
+            let key = &citation[..key_end];
+            if key.is_empty() {
+                return None;
+            }
+
+            let locator = citation[key_end..].trim_start_matches(',').trim();
+            Some(Citation {
+                key,
+                locator: if locator.is_empty() {
+                    None
+                } else {
+                    Some(locator)
+                },
+            })
+        })
+        .collect()
+}
+
+/// The id of an entry on the references page. Keys may contain characters
+/// which aren't allowed in an id (e.g. `:`).
+fn anchor(key: &str) -> String {
+    let key: String = key
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    format!("ref-{}", key)
+}
+
+/// The authors as they appear in a citation, e.g. `Knuth`, `Knuth and
+/// Lamport` or `Knuth et al.`.
+fn short_authors(entry: &Entry) -> String {
+    match entry.authors.len() {
+        0 => entry.title.clone().unwrap_or_else(|| entry.key.clone()),
+        1 => entry.authors[0].family.clone(),
+        2 => format!(
+            "{} and {}",
+            entry.authors[0].family, entry.authors[1].family
+        ),
+        _ => format!("{} et al.", entry.authors[0].family),
+    }
+}
+
+fn year(entry: &Entry) -> String {
+    entry.year.clone().unwrap_or_else(|| String::from("n.d."))
+}
+
+/// Render an entry for the references page, e.g. `Knuth, Donald E. (1984).
+/// <cite>Literate Programming</cite>. The Computer Journal.`
+fn format_entry(entry: &Entry) -> String {
+    let mut html = String::new();
+
+    let authors: Vec<String> = entry
+        .authors
+        .iter()
+        .enumerate()
+        .map(|(i, name)| match (i, &name.given) {
+            (0, Some(given)) => format!("{}, {}", name.family, given),
+            (_, Some(given)) => format!("{} {}", given, name.family),
+            (_, &None) => name.family.clone(),
+        })
+        .collect();
+    if !authors.is_empty() {
+        let authors = match authors.split_last() {
+            Some((last, rest)) if !rest.is_empty() => format!("{} and {}", rest.join(", "), last),
+            _ => authors.join(""),
+        };
+        html.push_str(&opf::escape(&authors));
+        html.push(' ');
+    }
+
+    html.push_str(&format!("({}).", opf::escape(&year(entry))));
+
+    if let Some(ref title) = entry.title {
+        html.push_str(&format!(" <cite>{}</cite>.", opf::escape(title)));
+    }
+    if let Some(ref container) = entry.container {
+        html.push_str(&format!(" {}.", opf::escape(container)));
+    }
+    if let Some(ref publisher) = entry.publisher {
+        html.push_str(&format!(" {}.", opf::escape(publisher)));
+    }
+    if let Some(ref url) = entry.url {
+        html.push_str(&format!(" <a href=\"{0}\">{0}</a>", opf::escape(url)));
+    }
+
+    html
+}
+
+/// Parse the entries in a BibTeX file. `@string`, `@preamble` and `@comment`
+/// blocks are skipped, and macros aren't expanded.
+pub fn parse_bibtex(text: &str) -> Result<Vec<Entry>, Error> {
+    let mut entries = Vec::new();
+    let mut rest = text;
+
+    while let Some(at) = rest.find('@') {
+        rest = &rest[at + 1..];
+
+        let open = rest
+            .find(['{', '('])
+            .ok_or_else(|| failure::err_msg("Expected a \"{\" after the entry type"))?;
+        let kind = rest[..open].trim().to_lowercase();
+        let body_len = balanced(&rest[open..])
+            .ok_or_else(|| failure::err_msg(format!("Unterminated @{} entry", kind)))?;
+        let body = &rest[open + 1..open + body_len - 1];
+        rest = &rest[open + body_len..];
+
+        if kind == "string" || kind == "preamble" || kind == "comment" {
+            continue;
+        }
+
+        let comma = body.find(',').unwrap_or(body.len());
+        let key = body[..comma].trim();
+        if key.is_empty() {
+            return Err(failure::err_msg(format!("An @{} entry has no key", kind)));
+        }
+
+        let fields = parse_fields(&body[comma..])
+            .with_context(|_| format!("Unable to parse the fields of \"{}\"", key))?;
+        let field = |name: &str| fields.get(name).cloned();
+
+        entries.push(Entry {
+            key: key.to_string(),
+            authors: field("author")
+                .or_else(|| field("editor"))
+                .map(|authors| parse_bibtex_names(&authors))
+                .unwrap_or_default(),
+            title: field("title"),
+            year: field("year")
+                .or_else(|| field("date").map(|date| date.chars().take(4).collect())),
+            container: field("journal")
+                .or_else(|| field("journaltitle"))
+                .or_else(|| field("booktitle")),
+            publisher: field("publisher"),
+            url: field("url")
+                .or_else(|| field("doi").map(|doi| format!("https://doi.org/{}", doi))),
+        });
+    }
+
+    Ok(entries)
+}
+
+/// Parse the `, name = value` pairs after an entry's key.
+fn parse_fields(text: &str) -> Result<BTreeMap<String, String>, Error> {
+    let mut fields = BTreeMap::new();
+    let mut rest = text.trim_start_matches(',');
+
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            break;
+        }
+
+        let eq = rest
+            .find('=')
+            .ok_or_else(|| failure::err_msg(format!("Expected a \"=\" in \"{}\"", rest.trim())))?;
+        let name = rest[..eq].trim().to_lowercase();
+        rest = rest[eq + 1..].trim_start();
+
+        let (value, len) = match rest.chars().next() {
+            Some('{') => {
+                let len = balanced(rest).ok_or_else(|| {
+                    failure::err_msg(format!("Unterminated value for \"{}\"", name))
+                })?;
+                (&rest[1..len - 1], len)
+            }
+            Some('"') => {
+                let len = rest[1..].find('"').map(|ix| ix + 2).ok_or_else(|| {
+                    failure::err_msg(format!("Unterminated value for \"{}\"", name))
+                })?;
+                (&rest[1..len - 1], len)
+            }
+            _ => {
+                let len = rest.find(',').unwrap_or(rest.len());
+                (rest[..len].trim(), len)
+            }
+        };
+
+        fields.insert(name, clean_value(value));
+        rest = rest[len..].trim_start().trim_start_matches(',');
+    }
+
+    Ok(fields)
+}
+
+/// The length of the bracketed group at the start of `text`, including its
+/// closing bracket.
+fn balanced(text: &str) -> Option<usize> {
+    let (open, close) = match text.chars().next()? {
+        '(' => ('(', ')'),
+        _ => ('{', '}'),
+    };
+    let mut depth = 0;
+
+    for (ix, c) in text.char_indices() {
+        if c == open {
+            depth += 1;
+        } else if c == close {
+            depth -= 1;
+            if depth == 0 {
+                return Some(ix + 1);
+            }
+        }
+    }
+
+    None
+}
+
+/// Drop the braces used to protect capitalisation, and collapse whitespace.
+fn clean_value(value: &str) -> String {
+    value
+        .replace(['{', '}'], "")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Parse a list of names like `Knuth, Donald E. and Leslie Lamport`.
+fn parse_bibtex_names(names: &str) -> Vec<Name> {
+    names
+        .split(" and ")
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| match name.find(',') {
+            Some(comma) => Name {
+                family: name[..comma].trim().to_string(),
+                given: Some(name[comma + 1..].trim().to_string()).filter(|g| !g.is_empty()),
+            },
+            None => match name.rfind(' ') {
+                Some(space) => Name {
+                    family: name[space + 1..].to_string(),
+                    given: Some(name[..space].trim().to_string()),
+                },
+                None => Name {
+                    family: name.to_string(),
+                    given: None,
+                },
+            },
+        })
+        .collect()
+}
+
+#[derive(Debug, Deserialize)]
+struct CslItem {
+    id: serde_json::Value,
+    #[serde(default)]
+    author: Vec<CslName>,
+    #[serde(default)]
+    editor: Vec<CslName>,
+    title: Option<String>,
+    #[serde(rename = "container-title")]
+    container_title: Option<String>,
+    publisher: Option<String>,
+    #[serde(rename = "URL")]
+    url: Option<String>,
+    #[serde(rename = "DOI")]
+    doi: Option<String>,
+    issued: Option<CslDate>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CslName {
+    family: Option<String>,
+    given: Option<String>,
+    literal: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CslDate {
+    #[serde(rename = "date-parts", default)]
+    date_parts: Vec<Vec<serde_json::Value>>,
+    literal: Option<String>,
+}
+
+/// Parse the entries in a CSL-JSON file (an array of items).
+pub fn parse_csl_json(text: &str) -> Result<Vec<Entry>, Error> {
+    let items: Vec<CslItem> = serde_json::from_str(text)?;
+
+    Ok(items
+        .into_iter()
+        .map(|item| {
+            let names = if item.author.is_empty() {
+                item.editor
+            } else {
+                item.author
+            };
+
+            Entry {
+                key: match item.id {
+                    serde_json::Value::String(id) => id,
+                    other => other.to_string(),
+                },
+                authors: names
+                    .into_iter()
+                    .filter_map(|name| match name.family {
+                        Some(family) => Some(Name {
+                            family,
+                            given: name.given,
+                        }),
+                        None => name.literal.map(|family| Name {
+                            family,
+                            given: None,
+                        }),
+                    })
+                    .collect(),
+                title: item.title,
+                year: item.issued.and_then(|issued| {
+                    let year = issued
+                        .date_parts
+                        .first()
+                        .and_then(|parts| parts.first())
+                        .map(|year| match *year {
+                            serde_json::Value::String(ref year) => year.clone(),
+                            ref other => other.to_string(),
+                        });
+                    year.or(issued.literal)
+                }),
+                container: item.container_title,
+                publisher: item.publisher,
+                url: match item.url {
+                    Some(url) => Some(url),
+                    None => item.doi.map(|doi| format!("https://doi.org/{}", doi)),
+                },
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::{html, Parser};
+
+    const BIBTEX: &str = r#"
+@comment{ Some useful references }
+@string{ cj = "The Computer Journal" }
+
+@article{knuth1984,
+  author  = {Knuth, Donald E.},
+  title   = {{Literate} Programming},
+  journal = {The Computer Journal},
+  year    = 1984,
+  doi     = {10.1093/comjnl/27.2.97},
+}
+
+@book{lamport1994,
+    author = "Leslie Lamport",
+    title = "LaTeX: A Document Preparation System",
+    publisher = {Addison-Wesley},
+    year = {1994}
+}
+"#;
+
+    fn render(src: &str, bibliography: &mut Bibliography) -> String {
+        let mut buffer = String::new();
+        html::push_html(
+            &mut buffer,
+            bibliography
+                .cite(Parser::new(src), "references.xhtml")
+                .into_iter(),
+        );
+        buffer
+    }
+
+    #[test]
+    fn parse_a_bibtex_file() {
+        let got = parse_bibtex(BIBTEX).unwrap();
+
+        assert_eq!(got.len(), 2);
+        assert_eq!(
+            got[0],
+            Entry {
+                key: String::from("knuth1984"),
+                authors: vec![Name {
+                    family: String::from("Knuth"),
+                    given: Some(String::from("Donald E.")),
+                }],
+                title: Some(String::from("Literate Programming")),
+                year: Some(String::from("1984")),
+                container: Some(String::from("The Computer Journal")),
+                publisher: None,
+                url: Some(String::from("https://doi.org/10.1093/comjnl/27.2.97")),
+            }
+        );
+        assert_eq!(got[1].authors[0].family, "Lamport");
+        assert_eq!(got[1].publisher, Some(String::from("Addison-Wesley")));
+    }
+
+    #[test]
+    fn parse_a_csl_json_file() {
+        let src = r#"[{
+            "id": "knuth1984",
+            "type": "article-journal",
+            "author": [{"family": "Knuth", "given": "Donald E."}],
+            "title": "Literate Programming",
+            "container-title": "The Computer Journal",
+            "issued": {"date-parts": [[1984, 5]]}
+        }]"#;
+
+        let mut should_be = parse_bibtex(BIBTEX).unwrap().remove(0);
+        should_be.url = None;
+
+        let got = parse_csl_json(src).unwrap();
+
+        assert_eq!(got, vec![should_be]);
+    }
+
+    #[test]
+    fn citations_link_to_the_references_page() {
+        let mut bibliography = Bibliography::new(parse_bibtex(BIBTEX).unwrap());
+        let src = "As shown [@knuth1984, p. 97; @lamport1994], and [@nobody2000].";
+
+        let got = render(src, &mut bibliography);
+
+        assert!(got.contains(
+            "(<a class=\"citation\" href=\"references.xhtml#ref-knuth1984\">Knuth 1984, p. 97</a>; \
+             <a class=\"citation\" href=\"references.xhtml#ref-lamport1994\">Lamport 1994</a>)"
+        ));
+        assert!(got.contains("[@nobody2000]"));
+        assert!(bibliography.is_cited());
+    }
+
+    #[test]
+    fn citations_in_code_are_left_alone() {
+        let mut bibliography = Bibliography::new(parse_bibtex(BIBTEX).unwrap());
+        let src = "```\n[@knuth1984]\n```\n\n`[@knuth1984]`\n";
+
+        let got = render(src, &mut bibliography);
+
+        assert!(!got.contains("citation"));
+        assert!(!bibliography.is_cited());
+    }
+
+    #[test]
+    fn only_cited_entries_are_listed() {
+        let mut bibliography = Bibliography::new(parse_bibtex(BIBTEX).unwrap());
+        render("See [@lamport1994].", &mut bibliography);
+
+        let got = bibliography.references_list();
+
+        assert_eq!(
+            got,
+            "<ul class=\"references\">\n<li id=\"ref-lamport1994\">Lamport, Leslie (1994). \
+             <cite>LaTeX: A Document Preparation System</cite>. Addison-Wesley.</li>\n</ul>\n"
+        );
+    }
+}
@@ -1,5 +1,9 @@
-extern crate env_logger;
+// `structopt-derive` puts its impls inside a named constant, which newer
+// compilers warn about.
+#![allow(unknown_lints, non_local_definitions)]
+
 extern crate failure;
+extern crate indicatif;
 extern crate mdbook;
 extern crate mdbook_epub;
 extern crate pulldown_cmark;
@@ -7,18 +11,24 @@ extern crate serde_json;
 extern crate structopt;
 #[macro_use]
 extern crate structopt_derive;
+extern crate tracing_subscriber;
 
 use failure::{Error, ResultExt, SyncFailure};
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
 use mdbook::renderer::RenderContext;
 use mdbook::MDBook;
+use mdbook_epub::{Phase, Progress};
 use std::env;
 use std::io;
 use std::path::PathBuf;
 use std::process;
+use std::time::Duration;
 use structopt::StructOpt;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::EnvFilter;
 
 fn main() {
-    env_logger::init();
+    init_logging();
     let args = Args::from_args();
 
     if let Err(e) = run(&args) {
@@ -37,10 +47,20 @@ fn main() {
     }
 }
 
+/// Log to stderr, filtered by `RUST_LOG`. Closing a span logs how long it
+/// took, so `RUST_LOG=mdbook_epub=info` times every chapter.
+fn init_logging() {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .with_span_events(FmtSpan::CLOSE)
+        .with_writer(io::stderr)
+        .init();
+}
+
 fn run(args: &Args) -> Result<(), Error> {
     // get a `RenderContext`, either from stdin (because we're used as a plugin)
     // or by instrumenting MDBook directly (in standalone mode).
-    let ctx: RenderContext = if args.standalone {
+    let mut ctx: RenderContext = if args.standalone {
         let md = MDBook::load(&args.root).map_err(SyncFailure::new)?;
         let destination = md.build_dir_for("epub");
 
@@ -49,11 +69,84 @@ fn run(args: &Args) -> Result<(), Error> {
         serde_json::from_reader(io::stdin()).context("Unable to parse RenderContext")?
     };
 
-    mdbook_epub::generate(&ctx)?;
+    if args.dry_run {
+        ctx.config
+            .set("output.epub.dry-run", true)
+            .map_err(SyncFailure::new)?;
+    }
+
+    let bar = ProgressBar::new(0);
+    bar.set_style(
+        ProgressStyle::default_bar().template("{prefix:>10} {msg:20} [{bar:40}] {pos}/{len}"),
+    );
+
+    let mut timings = Vec::new();
+
+    let books = mdbook_epub::generate_with_progress(&ctx, |progress| match *progress {
+        Progress::PhaseStarted(phase) => {
+            bar.set_message(&phase.to_string());
+            bar.set_length(0);
+            bar.set_position(0);
+        }
+        Progress::ChapterRendered {
+            completed, total, ..
+        }
+        | Progress::AssetEmbedded {
+            completed, total, ..
+        } => {
+            bar.set_length(total as u64);
+            bar.set_position(completed as u64);
+        }
+        Progress::BytesWritten(bytes) => bar.set_prefix(&HumanBytes(bytes).to_string()),
+        Progress::PhaseFinished(phase, duration) => timings.push((phase, duration)),
+    })?;
+
+    bar.finish_and_clear();
+    print_timings(&timings);
+
+    for book in &books {
+        if book.dry_run {
+            println!("Dry run, {} would contain:", book.path.display());
+            println!();
+            print!("{}", book.report);
+        }
+    }
+    for book in &books {
+        mdbook_epub::check_diagnostics(&book.report)?;
+    }
 
     Ok(())
 }
 
+/// Print a table of how long each phase of the build took.
+fn print_timings(timings: &[(Phase, Duration)]) {
+    let total = timings
+        .iter()
+        .fold(Duration::from_secs(0), |total, &(_, duration)| {
+            total + duration
+        });
+    let total_millis = millis(total).max(1);
+
+    eprintln!("{:>20}  {:>10}  {:>6}", "Phase", "Duration", "Share");
+    for &(phase, duration) in timings {
+        eprintln!(
+            "{:>20}  {:>10}  {:>5}%",
+            phase.to_string(),
+            format_duration(duration),
+            millis(duration) * 100 / total_millis
+        );
+    }
+    eprintln!("{:>20}  {:>10}", "Total", format_duration(total));
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + u64::from(duration.subsec_millis())
+}
+
+fn format_duration(duration: Duration) -> String {
+    format!("{}.{:03}s", duration.as_secs(), duration.subsec_millis())
+}
+
 #[derive(Debug, Clone, StructOpt)]
 struct Args {
     #[structopt(
@@ -62,6 +155,11 @@ struct Args {
         help = "Run standalone (i.e. not as a mdbook plugin)"
     )]
     standalone: bool,
+    #[structopt(
+        long = "dry-run",
+        help = "Print what would be packaged without writing the EPUB"
+    )]
+    dry_run: bool,
     #[structopt(help = "The book to render.", parse(from_os_str), default_value = ".")]
     root: PathBuf,
 }
@@ -0,0 +1,166 @@
+//! Rendering code blocks so long lines don't run off the edge of the screen.
+
+use pulldown_cmark::{Event, Tag};
+
+use config::{CodeWrap, MarkdownConfig};
+use opf;
+
+/// Render each code block with a class saying how long lines are handled,
+/// optionally numbering every line.
+pub fn transform<'a, I>(events: I, config: &MarkdownConfig) -> Vec<Event<'a>>
+where
+    I: IntoIterator<Item = Event<'a>>,
+{
+    let mut transformed = Vec::new();
+    // the code block we're currently in, if line numbers are being added
+    let mut code: Option<String> = None;
+
+    for event in events {
+        match event {
+            Event::Start(Tag::CodeBlock(ref info)) => {
+                transformed.push(Event::Html(open_tags(info, config).into()));
+                if config.line_numbers {
+                    code = Some(String::new());
+                }
+            }
+            Event::End(Tag::CodeBlock(_)) => {
+                if let Some(text) = code.take() {
+                    transformed.push(Event::Html(numbered_lines(&text).into()));
+                }
+                transformed.push(Event::Html("</code></pre>\n".into()));
+            }
+            Event::Text(ref text) if code.is_some() => {
+                if let Some(ref mut code) = code {
+                    code.push_str(text);
+                }
+            }
+            other => transformed.push(other),
+        }
+    }
+
+    transformed
+}
+
+fn open_tags(info: &str, config: &MarkdownConfig) -> String {
+    let mut class = String::from(match config.code_wrap {
+        CodeWrap::Wrap => "wrap",
+        CodeWrap::Clip => "clip",
+    });
+    if config.line_numbers {
+        class.push_str(" line-numbers");
+    }
+
+    let lang = info
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .next()
+        .unwrap_or_default();
+
+    if lang.is_empty() {
+        format!("<pre class=\"{}\"><code>", class)
+    } else {
+        format!(
+            "<pre class=\"{}\"><code class=\"language-{}\">",
+            class,
+            opf::escape(lang)
+        )
+    }
+}
+
+fn numbered_lines(text: &str) -> String {
+    let mut html = String::new();
+
+    for (i, line) in text.lines().enumerate() {
+        html.push_str(&format!(
+            "<span class=\"line-number\" aria-hidden=\"true\">{}</span>{}\n",
+            i + 1,
+            opf::escape(line)
+        ));
+    }
+
+    html
+}
+
+/// The (1-based) line number and length of every line inside a code block
+/// which is longer than `max` characters.
+pub fn long_lines(events: &[Event], max: usize) -> Vec<(usize, usize)> {
+    let mut long = Vec::new();
+    if max == 0 {
+        return long;
+    }
+
+    let mut code: Option<String> = None;
+
+    for event in events {
+        match *event {
+            Event::Start(Tag::CodeBlock(_)) => code = Some(String::new()),
+            Event::End(Tag::CodeBlock(_)) => {
+                if let Some(text) = code.take() {
+                    for (i, line) in text.lines().enumerate() {
+                        let length = line.chars().count();
+                        if length > max {
+                            long.push((i + 1, length));
+                        }
+                    }
+                }
+            }
+            Event::Text(ref text) => {
+                if let Some(ref mut code) = code {
+                    code.push_str(text);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    long
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::{html, Parser};
+
+    const SRC: &str = "```rust,ignore\nfn main() {\n    println!(\"<hi>\");\n}\n```\n";
+
+    fn render(src: &str, config: &MarkdownConfig) -> String {
+        let mut buffer = String::new();
+        html::push_html(&mut buffer, transform(Parser::new(src), config).into_iter());
+        buffer
+    }
+
+    #[test]
+    fn code_blocks_wrap_by_default() {
+        let got = render(SRC, &MarkdownConfig::default());
+
+        assert!(got.contains("<pre class=\"wrap\"><code class=\"language-rust\">"));
+        assert!(got.contains("&lt;hi&gt;"));
+        assert!(!got.contains("line-number"));
+    }
+
+    #[test]
+    fn lines_can_be_numbered() {
+        let config = MarkdownConfig {
+            line_numbers: true,
+            code_wrap: CodeWrap::Clip,
+            ..Default::default()
+        };
+
+        let got = render(SRC, &config);
+
+        assert!(got.contains("<pre class=\"clip line-numbers\">"));
+        assert!(
+            got.contains("<span class=\"line-number\" aria-hidden=\"true\">1</span>fn main() {\n")
+        );
+        assert!(got.contains("<span class=\"line-number\" aria-hidden=\"true\">3</span>}\n"));
+        assert!(got.contains("&lt;hi&gt;"));
+    }
+
+    #[test]
+    fn find_long_lines() {
+        let events: Vec<_> = Parser::new(SRC).collect();
+
+        let got = long_lines(&events, 20);
+
+        assert_eq!(got, vec![(2, 21)]);
+    }
+}
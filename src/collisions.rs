@@ -0,0 +1,132 @@
+//! Detecting when two files would end up at the same path inside the EPUB.
+//!
+//! Zip archives happily accept duplicate entries, so without this the later
+//! file silently wins. Paths which only differ by case are treated as
+//! collisions too because they break on case-insensitive filesystems (e.g.
+//! readers on macOS).
+
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Files written by `epub-builder` itself, relative to the `OEBPS/`
+/// directory.
+const RESERVED: &[&str] = &["content.opf", "toc.ncx", "nav.xhtml", "toc.xhtml"];
+
+/// Two different files would be written to the same place.
+#[derive(Debug, Clone, PartialEq, Fail)]
+#[fail(
+    display = "{} (\"{}\") and {} (\"{}\") would be written to the same path in the EPUB",
+    first_source, first_path, second_source, second_path
+)]
+pub struct PathCollision {
+    pub first_path: String,
+    pub first_source: String,
+    pub second_path: String,
+    pub second_source: String,
+}
+
+/// Keeps track of which paths have been claimed and by whom.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathRegistry {
+    /// Maps the lowercased path to the original path and where it came from.
+    claimed: HashMap<String, (String, String)>,
+}
+
+impl PathRegistry {
+    pub fn new() -> PathRegistry {
+        let mut registry = PathRegistry {
+            claimed: HashMap::new(),
+        };
+
+        for path in RESERVED {
+            registry
+                .claim(path, "epub-builder")
+                .expect("The reserved paths are all unique");
+        }
+
+        registry
+    }
+
+    /// Claim a path, returning `false` if the same source has already
+    /// claimed it (e.g. an image referenced by several chapters).
+    pub fn claim(&mut self, path: &str, source: &str) -> Result<bool, PathCollision> {
+        let key = path.to_lowercase();
+
+        if let Some((first_path, first_source)) = self.claimed.get(&key) {
+            if first_path == path && first_source == source {
+                return Ok(false);
+            }
+
+            return Err(PathCollision {
+                first_path: first_path.clone(),
+                first_source: first_source.clone(),
+                second_path: path.to_string(),
+                second_source: source.to_string(),
+            });
+        }
+
+        self.claimed
+            .insert(key, (path.to_string(), source.to_string()));
+        Ok(true)
+    }
+}
+
+impl Default for PathRegistry {
+    fn default() -> PathRegistry {
+        PathRegistry::new()
+    }
+}
+
+/// Get a path in the form it'll be written to the archive, always using `/`
+/// as a separator.
+pub fn normalize(path: &Path) -> String {
+    path.display().to_string().replace('\\', "/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn claiming_the_same_file_twice_is_fine() {
+        let mut registry = PathRegistry::new();
+
+        assert!(registry
+            .claim("images/logo.png", "/book/src/images/logo.png")
+            .unwrap());
+        assert!(!registry
+            .claim("images/logo.png", "/book/src/images/logo.png")
+            .unwrap());
+    }
+
+    #[test]
+    fn different_sources_collide() {
+        let mut registry = PathRegistry::new();
+        registry.claim("chapter_1.html", "chapter_1.md").unwrap();
+
+        let got = registry
+            .claim("chapter_1.html", "dummy_chapter.md")
+            .unwrap_err();
+
+        assert_eq!(got.first_source, "chapter_1.md");
+        assert_eq!(got.second_source, "dummy_chapter.md");
+    }
+
+    #[test]
+    fn paths_differing_only_by_case_collide() {
+        let mut registry = PathRegistry::new();
+        registry.claim("images/Logo.png", "Logo.png").unwrap();
+
+        let got = registry.claim("images/logo.png", "logo.png").unwrap_err();
+
+        assert_eq!(got.first_path, "images/Logo.png");
+        assert_eq!(got.second_path, "images/logo.png");
+    }
+
+    #[test]
+    fn reserved_paths_are_already_claimed() {
+        let mut registry = PathRegistry::new();
+
+        assert!(registry.claim("content.opf", "content.opf").is_err());
+    }
+}
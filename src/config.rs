@@ -1,33 +1,712 @@
-use failure::Error;
+use failure::{err_msg, Error, ResultExt};
 use mdbook::renderer::RenderContext;
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::Value;
+
+use credits::Credit;
+use style;
 
 /// The configuration struct used to tweak how an EPUB document is generated.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(default, rename_all = "kebab-case")]
 pub struct Config {
+    /// A directory containing templates and a stylesheet which override the
+    /// built-in ones (default: `theme/epub`).
+    pub theme: Option<PathBuf>,
     /// A list of additional stylesheets to include in the document.
     pub additional_css: Vec<PathBuf>,
     /// Should we use the default stylesheet (default: true)?
     pub use_default_css: bool,
+    /// Which colour schemes the book's stylesheet supports (default:
+    /// `"auto"`).
+    pub color_scheme: ColorScheme,
+    /// A stylesheet to use in night mode instead of the default one.
+    pub night_css: Option<PathBuf>,
+    /// Should we generate a title page from the book's metadata (default:
+    /// true)?
+    pub title_page: bool,
+    /// A custom template to use when rendering the title page.
+    pub title_page_template: Option<PathBuf>,
+    /// A subtitle to display on the title page.
+    pub subtitle: Option<String>,
+    /// The book's edition (e.g. "Second Edition").
+    pub edition: Option<String>,
+    /// The language the book is written in (default: `book.language`).
+    pub language: Option<String>,
+    /// An image to display on the title page.
+    pub logo: Option<PathBuf>,
+    /// The book's cover image.
+    pub cover: Option<CoverConfig>,
+    /// The series this book is part of.
+    pub series: Option<SeriesConfig>,
+    /// Extra `<meta>` and `<dc:*>` elements to add to the package
+    /// document, for properties which don't have an option of their own.
+    pub extra_opf_meta: Vec<ExtraMeta>,
+    /// Should an "About this book" page with each chapter's word count and
+    /// estimated reading time be added to the front matter (default: false)?
+    pub about_page: bool,
+    /// The reading speed used to estimate reading times (default: 200).
+    pub words_per_minute: usize,
+    /// Information used to generate a copyright page. If not provided, no
+    /// copyright page is added to the book.
+    pub copyright: Option<CopyrightConfig>,
+    /// How generated pages and chapters are divided into front matter, body
+    /// matter and back matter.
+    pub spine: SpineConfig,
+    /// Which markdown extensions are enabled.
+    pub markdown: MarkdownConfig,
+    /// The maximum nesting depth of the table of contents. Chapters nested
+    /// deeper than this are still included in the book, they just won't get
+    /// an entry in the table of contents.
+    pub toc_depth: Option<usize>,
+    /// Chapters whose rendered HTML is larger than this many bytes get split
+    /// into several documents at heading boundaries (default: 260KB). Set to
+    /// `0` to never split chapters.
+    pub max_chapter_size: usize,
+    /// Should chapter titles be prefixed with their section number (e.g.
+    /// "1.2.") in page titles and the table of contents (default: true)?
+    pub section_numbers: bool,
+    /// Should each chapter's first heading get its section number too
+    /// (default: false)?
+    pub number_headings: bool,
+    /// Font files to embed in the book. They're available to stylesheets
+    /// under the `fonts/` directory.
+    pub fonts: Vec<PathBuf>,
+    /// Should embedded fonts be obfuscated using the IDPF algorithm (default:
+    /// false)? Many font licenses require this for redistribution.
+    pub obfuscate_fonts: bool,
+    /// Where images and other media go in the archive relative to the
+    /// chapters (default: `"after-spine"`).
+    pub asset_placement: AssetPlacement,
+    /// How assets are transformed before being packaged.
+    pub assets: AssetConfig,
+    /// Support for interactive chapters.
+    pub scripts: ScriptConfig,
+    /// Typographic processing applied to every chapter.
+    pub typography: TypographyConfig,
+    /// Where footnotes go (default: `"inline"`, wherever they're defined).
+    pub footnotes: FootnoteMode,
+    /// How the destination of each link to a website is shown, for readers
+    /// who can't hover over or follow links (default: `"none"`).
+    pub link_urls: LinkUrls,
+    /// A BibTeX (`.bib`) or CSL-JSON (`.json`) file of references which
+    /// chapters can cite with `[@key]`.
+    pub bibliography: Option<PathBuf>,
+    /// Local files with these extensions (e.g. `"pdf"` or `"zip"`) which are
+    /// linked to from a chapter get packaged into the book.
+    pub package_links: Vec<String>,
+    /// Which reading systems the book should be packaged for (default:
+    /// `"compat"`).
+    pub profile: Profile,
+    /// Should a JSON manifest of every chapter and asset packaged into the
+    /// book be written next to it (default: false)?
+    pub build_manifest: bool,
+    /// Write a metadata record for distribution pipelines (either `"onix"` or
+    /// `"opds"`) alongside the book.
+    pub export_metadata: Option<MetadataFormat>,
+    /// Write an EPUB Accessibility 1.1 conformance report (as JSON and
+    /// HTML) alongside the book (default: false).
+    pub accessibility_report: bool,
+    /// Design tokens (e.g. `accent-color = "#c0392b"`) declared as CSS
+    /// custom properties at the top of the stylesheet.
+    #[serde(deserialize_with = "style::tokens")]
+    pub style: BTreeMap<String, String>,
+    /// Overrides for the labels used in generated pages and navigation
+    /// (e.g. `notes = "Endnotes"`), on top of the bundled translations
+    /// picked from `book.language`.
+    pub strings: BTreeMap<String, String>,
+    /// License and attribution information for assets, keyed by their path
+    /// relative to the `src/` directory. An asset can also have a
+    /// `<file>.license.toml` next to it.
+    pub credits: BTreeMap<String, Credit>,
+    /// Should a credits page listing the license and attribution of every
+    /// credited asset be added to the back matter (default: true)?
+    pub credits_page: bool,
+    /// The structural semantics (e.g. `"preface"` or `"appendix"`) of
+    /// chapters, keyed by their path relative to the `src/` directory.
+    /// Chapters can also declare this with `epub-type` in their front matter.
+    pub chapter_types: BTreeMap<String, String>,
+    /// Translations of the book, keyed by language code. Each one is written
+    /// to its own EPUB.
+    pub translations: BTreeMap<String, TranslationConfig>,
+    /// External commands chapters are piped through before being parsed,
+    /// in order.
+    pub filters: Vec<FilterConfig>,
+    /// Checksums and signing for the finished book.
+    pub sign: SignConfig,
+    /// Include the book's markdown sources and a record of how it was built
+    /// (default: false), so archived copies can be regenerated.
+    pub embed_sources: bool,
+    /// Replace assets which are missing or can't be read with a placeholder
+    /// image instead of failing the build (default: false).
+    pub lenient: bool,
+    /// The image used in place of missing assets (default: a built-in SVG).
+    pub placeholder: Option<PathBuf>,
+    /// Treat every diagnostic (missing alt text, broken links, assets
+    /// replaced with a placeholder, etc.) as an error, failing once the
+    /// book has been written (default: false).
+    pub strict: bool,
+    /// Per-category overrides for how seriously diagnostics are taken.
+    pub diagnostics: DiagnosticsConfig,
+    /// Resolve every chapter and asset and print what would be packaged,
+    /// without writing the EPUB (default: false).
+    pub dry_run: bool,
 }
 
 impl Config {
     /// Get the `output.epub` table from the provided `book.toml` config,
     /// falling back to the default if
     pub fn from_render_context(ctx: &RenderContext) -> Result<Config, Error> {
-        match ctx.config.get("output.epub") {
-            Some(table) => table.clone().try_into().map_err(Error::from),
-            None => Ok(Config::default()),
+        let mut config: Config = match ctx.config.get("output.epub") {
+            Some(table) => table.clone().try_into()?,
+            None => Config::default(),
+        };
+        config.spine.check()?;
+        if config.language.is_none() {
+            config.language = book_language(&ctx.root)?;
         }
+
+        Ok(config)
     }
 }
 
+/// Get the `book.language` from a book's `book.toml`.
+///
+/// `mdbook` 0.2 doesn't know about `book.language` and drops it while loading
+/// the config, so it has to be read from the file itself.
+fn book_language(root: &Path) -> Result<Option<String>, Error> {
+    let path = root.join("book.toml");
+    if !path.is_file() {
+        return Ok(None);
+    }
+
+    let text =
+        fs::read_to_string(&path).with_context(|_| format!("Unable to read {}", path.display()))?;
+    let table: Value =
+        toml::from_str(&text).with_context(|_| format!("Unable to parse {}", path.display()))?;
+
+    Ok(table
+        .get("book")
+        .and_then(|book| book.get("language"))
+        .and_then(Value::as_str)
+        .map(String::from))
+}
+
 impl Default for Config {
     fn default() -> Config {
         Config {
+            theme: None,
             use_default_css: true,
+            color_scheme: ColorScheme::Auto,
+            night_css: None,
             additional_css: Vec::new(),
+            title_page: true,
+            title_page_template: None,
+            subtitle: None,
+            edition: None,
+            language: None,
+            logo: None,
+            cover: None,
+            series: None,
+            extra_opf_meta: Vec::new(),
+            about_page: false,
+            words_per_minute: 200,
+            copyright: None,
+            spine: SpineConfig::default(),
+            markdown: MarkdownConfig::default(),
+            toc_depth: None,
+            max_chapter_size: 260 * 1024,
+            section_numbers: true,
+            number_headings: false,
+            fonts: Vec::new(),
+            obfuscate_fonts: false,
+            asset_placement: AssetPlacement::AfterSpine,
+            assets: AssetConfig::default(),
+            scripts: ScriptConfig::default(),
+            typography: TypographyConfig::default(),
+            footnotes: FootnoteMode::Inline,
+            link_urls: LinkUrls::None,
+            bibliography: None,
+            package_links: Vec::new(),
+            profile: Profile::Compat,
+            build_manifest: false,
+            export_metadata: None,
+            accessibility_report: false,
+            style: BTreeMap::new(),
+            strings: BTreeMap::new(),
+            credits: BTreeMap::new(),
+            credits_page: true,
+            chapter_types: BTreeMap::new(),
+            translations: BTreeMap::new(),
+            filters: Vec::new(),
+            sign: SignConfig::default(),
+            embed_sources: false,
+            lenient: false,
+            placeholder: None,
+            strict: false,
+            diagnostics: DiagnosticsConfig::default(),
+            dry_run: false,
+        }
+    }
+}
+
+/// How seriously a kind of diagnostic is taken.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Level {
+    /// Ignore it.
+    Allow,
+    /// Log a warning.
+    Warn,
+    /// Fail the build.
+    Error,
+}
+
+/// The `output.epub.diagnostics` table. Categories which aren't listed are
+/// errors in `strict` mode, and warnings otherwise.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct DiagnosticsConfig {
+    /// Images without any alternative text.
+    pub missing_alt_text: Option<Level>,
+    /// Links to local files which don't exist.
+    pub broken_link: Option<Level>,
+    /// Chapters which are still too large after being split.
+    pub oversized_chapter: Option<Level>,
+    /// Assets which aren't EPUB core media types.
+    pub non_core_media_type: Option<Level>,
+    /// Assets which were replaced with a placeholder.
+    pub missing_asset: Option<Level>,
+    /// Elements (embeds, forms and remote scripts) which were replaced
+    /// because they can't be used in an EPUB.
+    pub forbidden_element: Option<Level>,
+}
+
+/// Where a book's footnotes are placed.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FootnoteMode {
+    /// Leave footnotes where they're defined.
+    Inline,
+    /// Collect each chapter's footnotes into a section at the end of the
+    /// chapter.
+    EndOfChapter,
+    /// Collect every footnote into a notes page at the end of the book.
+    EndOfBook,
+}
+
+/// How the destinations of external links are shown.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LinkUrls {
+    /// Only as the link's `href`.
+    None,
+    /// In parentheses after the link.
+    Inline,
+    /// As a footnote, placed according to `footnotes`.
+    Footnote,
+}
+
+/// Where assets are written in the archive. Unless they're streamed, the
+/// package document, navigation, cover, stylesheets and fonts always come
+/// first so streaming reading systems can show the first page sooner.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AssetPlacement {
+    /// After every chapter, so the first chapters arrive sooner.
+    AfterSpine,
+    /// Before the chapters, for books (e.g. comics) whose first pages can't
+    /// be shown without their images.
+    BeforeSpine,
+    /// In the order they're written, without spooling the book to a
+    /// temporary file first. The package document ends up at the end.
+    Streamed,
+}
+
+/// Which reading systems the book is packaged for.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Profile {
+    /// An EPUB 3 document which also contains an NCX and `<guide>`, so it
+    /// keeps working on older EPUB 2 reading systems.
+    Compat,
+    /// A strict EPUB 3 document, without any of the EPUB 2 fallbacks.
+    Epub3,
+}
+
+/// Whether the book should be readable in night (dark) mode.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorScheme {
+    /// Use the night stylesheet whenever the reading system asks for a dark
+    /// theme.
+    Auto,
+    /// Only ever use the normal (day) stylesheet.
+    Light,
+    /// Always use the night stylesheet.
+    Dark,
+}
+
+/// An entry in the `output.epub.translations` table.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct TranslationConfig {
+    /// The directory containing the translated chapters and `SUMMARY.md`
+    /// (default: a directory named after the language inside `book.src`).
+    pub src: Option<PathBuf>,
+    /// The translated title.
+    pub title: Option<String>,
+    /// The translated description.
+    pub description: Option<String>,
+}
+
+/// The formats a book's metadata can be exported as.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetadataFormat {
+    /// An ONIX 3.0 product record.
+    Onix,
+    /// An OPDS catalog entry.
+    Opds,
+}
+
+impl Display for MetadataFormat {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            MetadataFormat::Onix => write!(f, "ONIX"),
+            MetadataFormat::Opds => write!(f, "OPDS"),
+        }
+    }
+}
+
+/// An entry in the `output.epub.extra-opf-meta` array.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ExtraMeta {
+    /// The property, e.g. `"rendition:flow"`. Properties starting with
+    /// `dc:` are written as Dublin Core elements instead of `<meta>`.
+    pub property: String,
+    pub value: String,
+    /// An `id` for other elements to refine.
+    #[serde(default)]
+    pub id: Option<String>,
+    /// The element (e.g. `"#creator01"`) this one refines.
+    #[serde(default)]
+    pub refines: Option<String>,
+    /// The scheme the value is drawn from.
+    #[serde(default)]
+    pub scheme: Option<String>,
+}
+
+/// The `output.epub.series` table, for books which are one volume of a
+/// series.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SeriesConfig {
+    /// The series' name.
+    pub name: String,
+    /// Where this book appears in the series (e.g. `2` for the second
+    /// volume).
+    pub index: Option<f64>,
+}
+
+/// The `output.epub.cover` table.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CoverConfig {
+    /// The cover image, relative to the book's root directory.
+    pub image: PathBuf,
+    /// Generate a copy of the cover scaled down to this many pixels wide,
+    /// for library software and older reading systems. Only PNG and JPEG
+    /// covers can have a thumbnail.
+    pub thumbnail_width: Option<u32>,
+}
+
+/// The `output.epub.assets` table.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct AssetConfig {
+    /// The built-in transformers (`"resize-images"` and `"minify-svg"`) every
+    /// asset is passed through, in order.
+    pub transformers: Vec<String>,
+    /// The maximum width of a resized image, in pixels.
+    pub max_image_width: Option<u32>,
+    /// The maximum height of a resized image, in pixels.
+    pub max_image_height: Option<u32>,
+    /// The width, in pixels, used to pick a single source for `<picture>`
+    /// elements and images with a `srcset` (default: 1200).
+    pub image_width: Option<u32>,
+}
+
+/// The `output.epub.typography` table.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct TypographyConfig {
+    /// Start each chapter's opening paragraph with a drop cap (default:
+    /// false).
+    pub drop_caps: bool,
+    /// Put non-breaking spaces before `:`, `;`, `!` and `?` and inside
+    /// guillemets (default: true when `book.language` is French).
+    pub french_spacing: Option<bool>,
+    /// A file of TeX hyphenation patterns (e.g. `hyph-en-us.pat.txt`) used
+    /// to add soft hyphens to long words.
+    pub hyphenation_patterns: Option<PathBuf>,
+}
+
+/// The `output.epub.scripts` table.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct ScriptConfig {
+    /// Should chapters be allowed to contain scripts (default: false)? When
+    /// disabled, `<script>` elements are removed and only the fallback for
+    /// interactive content is kept.
+    pub enabled: bool,
+    /// The scripts chapters may use, relative to the `src/` directory. Every
+    /// one of them is bundled into the book.
+    pub allow: Vec<PathBuf>,
+    /// What to show in place of interactive content which doesn't have a
+    /// fallback of its own.
+    pub fallback: String,
+}
+
+impl Default for ScriptConfig {
+    fn default() -> ScriptConfig {
+        ScriptConfig {
+            enabled: false,
+            allow: Vec::new(),
+            fallback: String::from(
+                "This content is interactive, and needs a reading system which supports scripting.",
+            ),
+        }
+    }
+}
+
+/// An entry in the `output.epub.filters` array.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct FilterConfig {
+    /// Which chapters the filter is run on, as a glob matched against their
+    /// path relative to the `src/` directory (e.g. `"*.md"` or
+    /// `"reference/**"`).
+    pub glob: String,
+    /// The command and its arguments. The chapter's markdown is written to
+    /// its stdin, and the filtered markdown is read from its stdout.
+    pub command: Vec<String>,
+}
+
+/// The `output.epub.sign` table.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct SignConfig {
+    /// Write a SHA-256 checksum (in `sha256sum` format) next to the book
+    /// (default: false).
+    pub checksum: bool,
+    /// A command to run on the finished book, e.g. `["minisign", "-Sm",
+    /// "{epub}"]`. Any `{epub}` is replaced with the book's path, otherwise
+    /// the path is added as the last argument.
+    pub command: Vec<String>,
+}
+
+/// The `output.epub.copyright` table, used to populate the book's copyright
+/// page (colophon).
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct CopyrightConfig {
+    /// The year of publication.
+    pub year: Option<String>,
+    /// Who holds the copyright (defaults to the book's authors).
+    pub holder: Option<String>,
+    /// A short description of the license the book is released under.
+    pub license: Option<String>,
+    /// Free-form markdown appended to the copyright page.
+    pub text: Option<String>,
+    /// A custom template to use when rendering the copyright page.
+    pub template: Option<PathBuf>,
+}
+
+/// The `output.epub.spine` table.
+///
+/// Entries are either the name of a generated page (`"title-page"`,
+/// `"copyright"` or `"about"`) or the path of a top-level chapter relative
+/// to the `src/` directory. Chapters which aren't mentioned make up the body
+/// of the book, in the order they appear in `SUMMARY.md`, while generated
+/// pages which aren't mentioned are placed at the very start of the front
+/// matter.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct SpineConfig {
+    /// Pages which come before the body of the book.
+    pub front_matter: Vec<String>,
+    /// Pages which come after the body of the book.
+    pub back_matter: Vec<String>,
+}
+
+impl SpineConfig {
+    /// Make sure no page is placed more than once, since each page can only
+    /// appear in the book once.
+    pub fn check(&self) -> Result<(), Error> {
+        let mut seen: Vec<(&Path, &str)> = Vec::new();
+        let entries = self
+            .front_matter
+            .iter()
+            .map(|entry| (entry, "front-matter"))
+            .chain(self.back_matter.iter().map(|entry| (entry, "back-matter")));
+
+        for (entry, list) in entries {
+            let path = Path::new(entry.trim_start_matches("./"));
+            if let Some(&(_, first)) = seen.iter().find(|&&(seen, _)| seen == path) {
+                let msg = if first == list {
+                    format!(
+                        "\"{}\" is listed more than once in output.epub.spine.{}",
+                        entry, list
+                    )
+                } else {
+                    format!(
+                        "\"{}\" is listed in both output.epub.spine.{} and output.epub.spine.{}",
+                        entry, first, list
+                    )
+                };
+                return Err(err_msg(msg));
+            }
+            seen.push((path, list));
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for SpineConfig {
+    fn default() -> SpineConfig {
+        SpineConfig {
+            front_matter: vec![
+                String::from("title-page"),
+                String::from("copyright"),
+                String::from("about"),
+            ],
+            back_matter: Vec::new(),
         }
     }
 }
+
+/// The `output.epub.markdown` table, used to toggle the extensions to
+/// CommonMark supported by `pulldown-cmark`. Everything is enabled by
+/// default.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct MarkdownConfig {
+    /// GitHub-style tables.
+    pub tables: bool,
+    /// Footnote references (`[^1]`) and definitions.
+    pub footnotes: bool,
+    /// Striking out text with `~~tildes~~`.
+    pub strikethrough: bool,
+    /// Task list items (`- [x] done`).
+    pub tasklists: bool,
+    /// Definition lists, written as a term followed by lines starting with
+    /// `: `.
+    pub definition_lists: bool,
+    /// A line which should be replaced with a page break (default:
+    /// `<!-- pagebreak -->`). Leave it empty to disable page breaks.
+    pub page_break_marker: String,
+    /// Tables with more columns than this are considered wide (default: 5).
+    /// Use 0 to never treat tables as wide.
+    pub wide_table_columns: usize,
+    /// How wide tables are made to fit on small screens (default:
+    /// `"scale"`).
+    pub wide_tables: WideTables,
+    /// Should every line of a code block be numbered (default: false)?
+    pub line_numbers: bool,
+    /// What happens to lines of code which are too long for the screen
+    /// (default: `"wrap"`).
+    pub code_wrap: CodeWrap,
+    /// Warn about lines of code longer than this many characters. Use 0 (the
+    /// default) to never warn.
+    pub max_code_line_length: usize,
+}
+
+/// How lines of code which don't fit on the screen are displayed.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CodeWrap {
+    /// Wrap long lines onto the next line.
+    Wrap,
+    /// Cut long lines off at the edge of the screen.
+    Clip,
+}
+
+/// How tables with too many columns to fit on the screen are displayed.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WideTables {
+    /// Shrink the text so the table fits the width of the screen.
+    Scale,
+    /// Keep the text size and let the reader scroll the table sideways.
+    Scroll,
+}
+
+impl Default for MarkdownConfig {
+    fn default() -> MarkdownConfig {
+        MarkdownConfig {
+            tables: true,
+            footnotes: true,
+            strikethrough: true,
+            tasklists: true,
+            definition_lists: true,
+            page_break_marker: String::from("<!-- pagebreak -->"),
+            wide_table_columns: 5,
+            wide_tables: WideTables::Scale,
+            line_numbers: false,
+            code_wrap: CodeWrap::Wrap,
+            max_code_line_length: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn spine(front_matter: &[&str], back_matter: &[&str]) -> SpineConfig {
+        SpineConfig {
+            front_matter: front_matter.iter().map(|s| s.to_string()).collect(),
+            back_matter: back_matter.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn spine_entries_can_only_be_placed_once() {
+        assert!(SpineConfig::default().check().is_ok());
+        assert!(spine(&["title-page", "preface.md"], &["appendix.md"])
+            .check()
+            .is_ok());
+
+        let twice = spine(&["about", "about"], &[]).check().unwrap_err();
+        assert!(twice.to_string().contains("more than once"));
+        let both = spine(&["preface.md"], &["./preface.md"])
+            .check()
+            .unwrap_err();
+        assert!(both
+            .to_string()
+            .contains("both output.epub.spine.front-matter and output.epub.spine.back-matter"));
+    }
+
+    #[test]
+    fn the_language_is_read_from_book_toml() {
+        let temp = TempDir::new("mdbook-epub").unwrap();
+        assert_eq!(book_language(temp.path()).unwrap(), None);
+
+        fs::write(
+            temp.path().join("book.toml"),
+            "[book]\ntitle = \"A Book\"\nlanguage = \"fr\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            book_language(temp.path()).unwrap(),
+            Some(String::from("fr"))
+        );
+    }
+}
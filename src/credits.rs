@@ -0,0 +1,204 @@
+//! License and attribution information for assets, and the credits page
+//! listing it.
+//!
+//! Credits come from a `<file>.license.toml` next to the asset, or from the
+//! `output.epub.credits` table (keyed by the asset's path relative to the
+//! `src/` directory). The sidecar file wins when there are both.
+
+use failure::{Error, ResultExt};
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml;
+
+use opf;
+use strings::Strings;
+
+/// Who made an asset, and how it's licensed.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Credit {
+    /// The work's title (defaults to the asset's file name).
+    pub title: Option<String>,
+    pub author: Option<String>,
+    /// Where the original can be found.
+    pub source: Option<String>,
+    /// The license's name or SPDX identifier (e.g. `"CC-BY-4.0"`).
+    pub license: Option<String>,
+    /// A link to the license (default: the Creative Commons deed, for
+    /// Creative Commons licenses).
+    pub license_url: Option<String>,
+    /// Attribution text to use as-is, for licenses which dictate the
+    /// wording.
+    pub attribution: Option<String>,
+}
+
+/// Where the sidecar file for the asset at `location` would be.
+pub fn sidecar(location: &Path) -> PathBuf {
+    let mut name = location
+        .file_name()
+        .map(|name| name.to_os_string())
+        .unwrap_or_default();
+    name.push(".license.toml");
+
+    location.with_file_name(name)
+}
+
+/// Read the asset's sidecar file, if it has one.
+pub fn load_sidecar(location: &Path) -> Result<Option<Credit>, Error> {
+    let path = sidecar(location);
+    if !path.is_file() {
+        return Ok(None);
+    }
+
+    let text =
+        fs::read_to_string(&path).with_context(|_| format!("Unable to read {}", path.display()))?;
+    let credit =
+        toml::from_str(&text).with_context(|_| format!("Unable to parse {}", path.display()))?;
+
+    Ok(Some(credit))
+}
+
+/// The link for a license, using the Creative Commons deed for SPDX
+/// identifiers like `CC-BY-SA-4.0` and `CC0-1.0`.
+pub fn license_url(credit: &Credit) -> Option<String> {
+    if credit.license_url.is_some() {
+        return credit.license_url.clone();
+    }
+
+    let license = credit.license.as_ref()?.trim();
+    if license.eq_ignore_ascii_case("CC0-1.0") {
+        return Some(String::from(
+            "https://creativecommons.org/publicdomain/zero/1.0/",
+        ));
+    }
+
+    let mut parts: Vec<&str> = license.split('-').collect();
+    if parts.len() < 3 || !parts[0].eq_ignore_ascii_case("CC") {
+        return None;
+    }
+    let version = parts.pop()?;
+    let terms = parts[1..].join("-").to_lowercase();
+    let known = ["by", "by-sa", "by-nd", "by-nc", "by-nc-sa", "by-nc-nd"];
+    if !known.contains(&terms.as_str()) || version.parse::<f32>().is_err() {
+        return None;
+    }
+
+    Some(format!(
+        "https://creativecommons.org/licenses/{}/{}/",
+        terms, version
+    ))
+}
+
+/// Render the credits page's content, with one entry for each asset.
+pub fn render(credits: &[(&Path, &Credit)], strings: &Strings) -> String {
+    let mut html = format!(
+        "<h1>{}</h1>\n<ul class=\"credits\">\n",
+        opf::escape(strings.get("credits"))
+    );
+
+    for &(path, credit) in credits {
+        html.push_str(&format!("<li>{}</li>\n", entry(path, credit)));
+    }
+
+    html.push_str("</ul>\n");
+    html
+}
+
+fn entry(path: &Path, credit: &Credit) -> String {
+    if let Some(ref attribution) = credit.attribution {
+        return opf::escape(attribution);
+    }
+
+    let title = match credit.title {
+        Some(ref title) => opf::escape(title),
+        None => opf::escape(&path.file_name().unwrap_or_default().to_string_lossy()),
+    };
+    let title = match credit.source {
+        Some(ref source) => format!("<a href=\"{}\">{}</a>", opf::escape(source), title),
+        None => title,
+    };
+
+    let mut parts = vec![format!("<cite>{}</cite>", title)];
+    if let Some(ref author) = credit.author {
+        parts.push(opf::escape(author));
+    }
+    if let Some(ref license) = credit.license {
+        let license = opf::escape(license);
+        parts.push(match license_url(credit) {
+            Some(url) => format!("<a href=\"{}\">{}</a>", opf::escape(&url), license),
+            None => license,
+        });
+    }
+
+    parts.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn creative_commons_licenses_link_to_their_deed() {
+        let inputs = vec![
+            (
+                "CC-BY-4.0",
+                Some("https://creativecommons.org/licenses/by/4.0/"),
+            ),
+            (
+                "CC-BY-NC-SA-3.0",
+                Some("https://creativecommons.org/licenses/by-nc-sa/3.0/"),
+            ),
+            (
+                "CC0-1.0",
+                Some("https://creativecommons.org/publicdomain/zero/1.0/"),
+            ),
+            ("MIT", None),
+        ];
+
+        for (license, should_be) in inputs {
+            let credit = Credit {
+                license: Some(license.to_string()),
+                ..Default::default()
+            };
+            assert_eq!(license_url(&credit).as_deref(), should_be);
+        }
+    }
+
+    #[test]
+    fn entries_link_to_the_source_and_license() {
+        let credit = Credit {
+            author: Some(String::from("Jane & John")),
+            source: Some(String::from("https://example.com/photo")),
+            license: Some(String::from("CC-BY-4.0")),
+            ..Default::default()
+        };
+
+        let got = render(
+            &[(Path::new("images/photo.jpg"), &credit)],
+            &Strings::default(),
+        );
+
+        assert!(got.starts_with("<h1>Credits</h1>"));
+        assert!(got.contains(concat!(
+            r#"<li><cite><a href="https://example.com/photo">photo.jpg</a></cite>, Jane &amp; John, "#,
+            r#"<a href="https://creativecommons.org/licenses/by/4.0/">CC-BY-4.0</a></li>"#
+        )));
+    }
+
+    #[test]
+    fn credits_can_be_read_from_a_sidecar() {
+        let temp = TempDir::new("mdbook-epub").unwrap();
+        let image = temp.path().join("photo.jpg");
+        fs::write(
+            temp.path().join("photo.jpg.license.toml"),
+            "author = \"Jane\"\nlicense = \"CC0-1.0\"\n",
+        )
+        .unwrap();
+
+        let got = load_sidecar(&image).unwrap().unwrap();
+
+        assert_eq!(got.author, Some(String::from("Jane")));
+        assert_eq!(load_sidecar(&temp.path().join("other.png")).unwrap(), None);
+    }
+}
@@ -0,0 +1,195 @@
+//! Support for definition lists, which CommonMark doesn't have.
+//!
+//! We use the convention from PHP Markdown Extra and Pandoc, where each term
+//! goes on its own line, followed by one or more definitions starting with a
+//! colon.
+//!
+//! ```markdown
+//! Apple
+//! : A fruit which grows on trees.
+//! : A technology company.
+//! ```
+//!
+//! `pulldown-cmark` parses this as an ordinary paragraph, so after parsing we
+//! look for paragraphs with that shape and turn them into `<dl>` elements.
+
+use pulldown_cmark::{Event, Tag};
+
+/// Rewrite every paragraph which looks like a definition list.
+pub fn transform<'a, I>(events: I) -> Vec<Event<'a>>
+where
+    I: IntoIterator<Item = Event<'a>>,
+{
+    let mut out = Vec::new();
+    let mut in_list = false;
+    let mut events = events.into_iter();
+
+    while let Some(event) = events.next() {
+        match event {
+            Event::Start(Tag::Paragraph) => {}
+            other => {
+                if in_list {
+                    out.push(Event::Html("</dl>\n".into()));
+                    in_list = false;
+                }
+                out.push(other);
+                continue;
+            }
+        }
+
+        let paragraph: Vec<_> = events
+            .by_ref()
+            .take_while(|e| !matches!(*e, Event::End(Tag::Paragraph)))
+            .collect();
+
+        match definitions(paragraph, in_list) {
+            Ok(items) => {
+                if !in_list {
+                    out.push(Event::Html("<dl>\n".into()));
+                    in_list = true;
+                }
+                out.extend(items);
+            }
+            Err(paragraph) => {
+                if in_list {
+                    out.push(Event::Html("</dl>\n".into()));
+                    in_list = false;
+                }
+                out.push(Event::Start(Tag::Paragraph));
+                out.extend(paragraph);
+                out.push(Event::End(Tag::Paragraph));
+            }
+        }
+    }
+
+    if in_list {
+        out.push(Event::Html("</dl>\n".into()));
+    }
+
+    out
+}
+
+/// Try to interpret a paragraph's contents as terms and their definitions,
+/// handing the events back if it isn't one.
+///
+/// When we're already inside a list, a paragraph containing only definitions
+/// continues the previous term.
+fn definitions<'a>(
+    paragraph: Vec<Event<'a>>,
+    in_list: bool,
+) -> Result<Vec<Event<'a>>, Vec<Event<'a>>> {
+    let first_definition = lines(&paragraph)
+        .iter()
+        .position(|line| is_definition(line));
+
+    let first_definition = match first_definition {
+        Some(0) if !in_list => return Err(paragraph),
+        Some(ix) => ix,
+        None => return Err(paragraph),
+    };
+
+    let lines = lines(&paragraph);
+    let mut out = Vec::new();
+
+    for term in &lines[..first_definition] {
+        out.push(Event::Html("<dt>".into()));
+        out.extend(term.iter().cloned());
+        out.push(Event::Html("</dt>\n".into()));
+    }
+
+    let mut open = false;
+
+    for line in &lines[first_definition..] {
+        if is_definition(line) {
+            if open {
+                out.push(Event::Html("</dd>\n".into()));
+            }
+            out.push(Event::Html("<dd>".into()));
+            out.extend(strip_marker(line));
+            open = true;
+        } else {
+            // a lazy continuation of the previous definition
+            out.push(Event::SoftBreak);
+            out.extend(line.iter().cloned());
+        }
+    }
+
+    out.push(Event::Html("</dd>\n".into()));
+
+    Ok(out)
+}
+
+/// Split a paragraph's contents into lines.
+fn lines<'a, 'e>(paragraph: &'e [Event<'a>]) -> Vec<&'e [Event<'a>]> {
+    paragraph
+        .split(|e| matches!(*e, Event::SoftBreak | Event::HardBreak))
+        .filter(|line| !line.is_empty())
+        .collect()
+}
+
+fn is_definition(line: &[Event]) -> bool {
+    match line.first() {
+        Some(Event::Text(text)) => text.starts_with(": ") || text.starts_with(":\t"),
+        _ => false,
+    }
+}
+
+fn strip_marker<'a>(line: &[Event<'a>]) -> Vec<Event<'a>> {
+    let mut events = line.to_vec();
+
+    if let Some(&mut Event::Text(ref mut text)) = events.first_mut() {
+        let stripped = text[1..].trim_start().to_string();
+        *text = stripped.into();
+    }
+
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::{html, Parser};
+
+    fn render(src: &str) -> String {
+        let mut buffer = String::new();
+        html::push_html(&mut buffer, transform(Parser::new(src)).into_iter());
+        buffer
+    }
+
+    #[test]
+    fn terms_and_definitions() {
+        let src = "Apple\n: A fruit.\n: A *company*.\n";
+        let should_be =
+            "<dl>\n<dt>Apple</dt>\n<dd>A fruit.</dd>\n<dd>A <em>company</em>.</dd>\n</dl>\n";
+
+        let got = render(src);
+
+        assert_eq!(got, should_be);
+    }
+
+    #[test]
+    fn consecutive_paragraphs_share_a_list() {
+        let src = "Apple\n: A fruit.\n\nPear\n: Another fruit.\n\n: Still a fruit.\n";
+
+        let got = render(src);
+
+        assert_eq!(got.matches("<dl>").count(), 1);
+        assert_eq!(got.matches("<dt>").count(), 2);
+        assert_eq!(got.matches("<dd>").count(), 3);
+    }
+
+    #[test]
+    fn ordinary_paragraphs_are_left_alone() {
+        let inputs = vec![
+            "Just a paragraph\nspanning two lines.\n",
+            ": starts with a colon\n",
+            "A ratio of\n1:2\n",
+        ];
+
+        for src in inputs {
+            let got = render(src);
+            assert!(got.starts_with("<p>"), "{}", got);
+            assert!(!got.contains("<dl>"));
+        }
+    }
+}
@@ -0,0 +1,209 @@
+//! Problems with a book which don't stop it being built, and how seriously
+//! each kind is taken.
+//!
+//! Every diagnostic is a warning by default, or an error with `strict =
+//! true`. Either can be overridden for individual categories in the
+//! `output.epub.diagnostics` table. Errors are collected and only fail the
+//! build once the book has been written, so a single run reports all of
+//! them.
+
+use pulldown_cmark::{Event, Tag};
+use std::fmt::{self, Display, Formatter};
+
+use config::{Config, Level};
+use links::{self, LinkKind};
+
+/// The kinds of diagnostic which can be emitted.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Category {
+    /// An image without any alternative text.
+    MissingAltText,
+    /// A link to a local file which doesn't exist.
+    BrokenLink,
+    /// A chapter which is still larger than `max-chapter-size` after being
+    /// split.
+    OversizedChapter,
+    /// An asset which isn't one of the EPUB core media types, so reading
+    /// systems aren't required to support it.
+    NonCoreMediaType,
+    /// An asset which was replaced with a placeholder.
+    MissingAsset,
+    /// An element which isn't allowed in an EPUB (e.g. an `<iframe>`) and
+    /// was replaced with a link.
+    ForbiddenElement,
+}
+
+impl Category {
+    /// How seriously this category is taken by a particular book.
+    pub fn level(self, config: &Config) -> Level {
+        let overrides = &config.diagnostics;
+        let level = match self {
+            Category::MissingAltText => overrides.missing_alt_text,
+            Category::BrokenLink => overrides.broken_link,
+            Category::OversizedChapter => overrides.oversized_chapter,
+            Category::NonCoreMediaType => overrides.non_core_media_type,
+            Category::MissingAsset => overrides.missing_asset,
+            Category::ForbiddenElement => overrides.forbidden_element,
+        };
+
+        match level {
+            Some(level) => level,
+            None if config.strict => Level::Error,
+            None => Level::Warn,
+        }
+    }
+}
+
+impl Display for Category {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let name = match *self {
+            Category::MissingAltText => "missing-alt-text",
+            Category::BrokenLink => "broken-link",
+            Category::OversizedChapter => "oversized-chapter",
+            Category::NonCoreMediaType => "non-core-media-type",
+            Category::MissingAsset => "missing-asset",
+            Category::ForbiddenElement => "forbidden-element",
+        };
+
+        f.write_str(name)
+    }
+}
+
+/// A single problem with the book.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub category: Category,
+    pub level: Level,
+    pub message: String,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{} [{}]", self.message, self.category)
+    }
+}
+
+/// The media types every EPUB 3 reading system must support, and which can
+/// therefore be used without a fallback.
+const CORE_MEDIA_TYPES: &[&str] = &[
+    "image/gif",
+    "image/jpeg",
+    "image/png",
+    "image/svg+xml",
+    "image/webp",
+    "audio/mpeg",
+    "audio/mp4",
+    "text/css",
+    "font/ttf",
+    "font/otf",
+    "font/woff",
+    "font/woff2",
+    "application/font-sfnt",
+    "application/font-woff",
+    "application/vnd.ms-opentype",
+    "application/xhtml+xml",
+    "application/javascript",
+    "text/javascript",
+    "application/x-dtbncx+xml",
+    "application/smil+xml",
+    "application/pls+xml",
+];
+
+/// Is this one of the EPUB core media types?
+pub fn is_core_media_type(mimetype: &str) -> bool {
+    CORE_MEDIA_TYPES
+        .iter()
+        .any(|core| core.eq_ignore_ascii_case(mimetype))
+}
+
+/// The destination of every image which has no alternative text.
+pub fn images_without_alt_text(events: &[Event]) -> Vec<String> {
+    let mut missing = Vec::new();
+    // the image we're currently inside, and whether it has any alt text
+    let mut image: Option<(String, bool)> = None;
+
+    for event in events {
+        match *event {
+            Event::Start(Tag::Image(_, ref dest, _)) => image = Some((dest.to_string(), false)),
+            Event::End(Tag::Image(..)) => {
+                if let Some((dest, false)) = image.take() {
+                    missing.push(dest);
+                }
+            }
+            Event::Text(ref text) => {
+                if let Some((_, ref mut has_alt)) = image {
+                    *has_alt |= !text.trim().is_empty();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    missing
+}
+
+/// Every link to a local file or another chapter. Fragment-only links,
+/// websites and `mailto:`-style links are skipped.
+pub fn local_links(events: &[Event]) -> Vec<String> {
+    events
+        .iter()
+        .filter_map(|event| match *event {
+            Event::Start(Tag::Link(_, ref dest, _)) => Some(dest),
+            _ => None,
+        })
+        .filter(|dest| matches!(links::classify(dest), LinkKind::Chapter | LinkKind::File))
+        .filter(|dest| !links::without_fragment(dest).is_empty())
+        .map(|dest| dest.to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::DiagnosticsConfig;
+    use pulldown_cmark::Parser;
+
+    #[test]
+    fn strict_makes_everything_an_error_unless_overridden() {
+        let mut config = Config::default();
+        assert_eq!(Category::BrokenLink.level(&config), Level::Warn);
+
+        config.strict = true;
+        config.diagnostics = DiagnosticsConfig {
+            missing_alt_text: Some(Level::Warn),
+            ..Default::default()
+        };
+
+        assert_eq!(Category::BrokenLink.level(&config), Level::Error);
+        assert_eq!(Category::MissingAltText.level(&config), Level::Warn);
+    }
+
+    #[test]
+    fn find_images_without_alt_text() {
+        let src = "![](a.png) ![ ](b.png) ![A *diagram*](c.png) ![`code`](d.png)";
+        let events: Vec<_> = Parser::new(src).collect();
+
+        let got = images_without_alt_text(&events);
+
+        assert_eq!(got, vec!["a.png", "b.png"]);
+    }
+
+    #[test]
+    fn find_local_links() {
+        let src = "[a](chapter_1.md#intro) [b](#top) [c](https://example.com/) \
+                   [d](mailto:me@example.com) [e](files/data.csv) [f](tel:+1-555-0100)";
+        let events: Vec<_> = Parser::new(src).collect();
+
+        let got = local_links(&events);
+
+        assert_eq!(got, vec!["chapter_1.md#intro", "files/data.csv"]);
+    }
+
+    #[test]
+    fn pdfs_are_not_core_media_types() {
+        assert!(is_core_media_type("image/png"));
+        assert!(is_core_media_type("text/CSS"));
+        assert!(!is_core_media_type("application/pdf"));
+    }
+}
@@ -0,0 +1,238 @@
+//! Moving footnotes out of the flow of the text, into a notes section at the
+//! end of each chapter or a notes page at the end of the book.
+//!
+//! Notes are renumbered in the order they're first referenced, and each note
+//! links back to where it was referenced from.
+
+use pulldown_cmark::{html, Event, Tag};
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use opf;
+use strings::Strings;
+
+/// A footnote which has been taken out of a chapter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Note {
+    pub number: usize,
+    /// The note's rendered content.
+    pub content: String,
+}
+
+/// Remove every footnote definition from a chapter, replacing references to
+/// them with links to `{notes_href}#fn-{number}`. Notes are numbered starting
+/// from `first_number`.
+pub fn collect<'a>(
+    events: Vec<Event<'a>>,
+    first_number: usize,
+    notes_href: &str,
+) -> (Vec<Event<'a>>, Vec<Note>) {
+    let (events, mut definitions, order) = take_definitions(events);
+    let mut numbers: HashMap<String, usize> = HashMap::new();
+    let mut out = Vec::with_capacity(events.len());
+    let mut notes = Vec::new();
+
+    for event in events {
+        match event {
+            Event::FootnoteReference(ref name)
+                if definitions.contains_key(name.as_ref())
+                    || numbers.contains_key(name.as_ref()) =>
+            {
+                let name = name.to_string();
+                let (number, first) = match numbers.get(&name) {
+                    Some(&number) => (number, false),
+                    None => {
+                        let number = first_number + numbers.len();
+                        numbers.insert(name.clone(), number);
+                        (number, true)
+                    }
+                };
+
+                if first {
+                    notes.push(Note {
+                        number,
+                        content: render(definitions.remove(&name).unwrap_or_default()),
+                    });
+                }
+                out.push(Event::InlineHtml(
+                    reference(number, first, notes_href).into(),
+                ));
+            }
+            other => out.push(other),
+        }
+    }
+
+    // notes which are never referenced still go at the end
+    for name in order {
+        if let Some(definition) = definitions.remove(&name) {
+            notes.push(Note {
+                number: first_number + notes.len(),
+                content: render(definition),
+            });
+        }
+    }
+
+    (out, notes)
+}
+
+/// Render the notes section, where `backref` gives the document each note's
+/// reference is in (or `""` for the current document). Notes which are never
+/// referenced (`None`) don't get a link back.
+pub fn section<F>(notes: &[Note], heading: Option<&str>, strings: &Strings, backref: F) -> String
+where
+    F: Fn(&Note) -> Option<String>,
+{
+    let mut html = String::from("<section class=\"footnotes\" epub:type=\"footnotes\">\n");
+    if let Some(heading) = heading {
+        html.push_str(&format!("<h2>{}</h2>\n", heading));
+    }
+    html.push_str("<ol>\n");
+
+    for note in notes {
+        let link = match backref(note) {
+            Some(href) => format!(
+                "<a class=\"footnote-backref\" href=\"{}#fnref-{}\" aria-label=\"{}\">\u{21a9}</a>\n",
+                href,
+                note.number,
+                opf::escape(&strings.format(
+                    "back-to-reference",
+                    &[("number", &note.number.to_string())]
+                ))
+            ),
+            None => String::new(),
+        };
+        html.push_str(&format!(
+            "<li id=\"fn-{0}\" value=\"{0}\" epub:type=\"footnote\">\n{1}{2}</li>\n",
+            note.number, note.content, link
+        ));
+    }
+
+    html.push_str("</ol>\n</section>\n");
+    html
+}
+
+/// Pull every footnote definition out of the event stream, returning the
+/// remaining events, the definitions, and the order they were defined in.
+fn take_definitions<'a>(
+    events: Vec<Event<'a>>,
+) -> (Vec<Event<'a>>, HashMap<String, Vec<Event<'a>>>, Vec<String>) {
+    let mut rest = Vec::with_capacity(events.len());
+    let mut definitions = HashMap::new();
+    let mut order = Vec::new();
+    let mut current: Option<(String, Vec<Event<'a>>)> = None;
+
+    for event in events {
+        match event {
+            Event::Start(Tag::FootnoteDefinition(ref name)) if current.is_none() => {
+                current = Some((name.to_string(), Vec::new()));
+            }
+            Event::End(Tag::FootnoteDefinition(_)) => {
+                if let Some((name, definition)) = current.take() {
+                    order.push(name.clone());
+                    definitions.insert(name, definition);
+                }
+            }
+            other => match current {
+                Some((_, ref mut definition)) => definition.push(other),
+                None => rest.push(other),
+            },
+        }
+    }
+
+    (rest, definitions, order)
+}
+
+fn render(events: Vec<Event>) -> String {
+    let mut buffer = String::new();
+    html::push_html(&mut buffer, events.into_iter());
+    buffer
+}
+
+fn reference(number: usize, first: bool, notes_href: &str) -> String {
+    // only the first reference gets an id for the note to link back to
+    let id: Cow<str> = if first {
+        format!(" id=\"fnref-{}\"", number).into()
+    } else {
+        "".into()
+    };
+
+    format!(
+        "<sup class=\"footnote-reference\"><a epub:type=\"noteref\"{} href=\"{}#fn-{}\">{}</a></sup>",
+        id, notes_href, number, number
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::{Options, Parser};
+
+    fn parse(src: &str) -> Vec<Event<'_>> {
+        Parser::new_ext(src, Options::ENABLE_FOOTNOTES).collect()
+    }
+
+    #[test]
+    fn notes_are_renumbered_in_reference_order() {
+        let src = "Second[^b] and first[^a] and again[^b].\n\n[^a]: Note A.\n\n[^b]: Note B.\n";
+
+        let (events, notes) = collect(parse(src), 1, "");
+        let text = render(events);
+
+        assert_eq!(notes.len(), 2);
+        assert_eq!(notes[0].number, 1);
+        assert!(notes[0].content.contains("Note B."));
+        assert!(notes[1].content.contains("Note A."));
+        assert!(text.contains(r##"<a epub:type="noteref" id="fnref-1" href="#fn-1">1</a>"##));
+        assert!(text.contains(r##"<a epub:type="noteref" href="#fn-1">1</a>"##));
+        assert!(!text.contains("Note A."));
+    }
+
+    #[test]
+    fn numbering_can_continue_from_a_previous_chapter() {
+        let src = "Text[^1].\n\n[^1]: A note.\n";
+
+        let (events, notes) = collect(parse(src), 5, "../notes.xhtml");
+
+        assert_eq!(notes[0].number, 5);
+        assert!(render(events).contains(r#"href="../notes.xhtml#fn-5""#));
+    }
+
+    #[test]
+    fn notes_link_back_to_their_reference() {
+        let notes = vec![Note {
+            number: 3,
+            content: String::from("<p>A note.</p>\n"),
+        }];
+
+        let got = section(&notes, None, &Strings::default(), |_| {
+            Some(String::from("chapter_1.html"))
+        });
+
+        assert!(got.contains(r#"<li id="fn-3" value="3" epub:type="footnote">"#));
+        assert!(got.contains(r#"href="chapter_1.html#fnref-3""#));
+    }
+
+    #[test]
+    fn unreferenced_notes_dont_link_back() {
+        let src = "Text[^a].
+
+[^a]: Referenced.
+
+[^b]: Never referenced.
+";
+        let (events, notes) = collect(parse(src), 1, "");
+        let text = render(events);
+
+        let got = section(&notes, None, &Strings::default(), |note| {
+            if text.contains(&format!("id=\"fnref-{}\"", note.number)) {
+                Some(String::new())
+            } else {
+                None
+            }
+        });
+
+        assert!(got.contains(r##"href="#fnref-1""##));
+        assert!(!got.contains("fnref-2"));
+        assert_eq!(got.matches("footnote-backref").count(), 1);
+    }
+}
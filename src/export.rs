@@ -0,0 +1,155 @@
+//! Sidecar metadata records (ONIX or OPDS) written alongside the EPUB, for
+//! distribution pipelines which would otherwise need the book's details
+//! entered all over again.
+
+use chrono::{DateTime, Utc};
+use failure::{Error, ResultExt};
+use handlebars::Handlebars;
+use mdbook::renderer::RenderContext;
+use std::path::Path;
+
+use config::{Config, MetadataFormat};
+use links;
+use utils::ResultExt as SyncResultExt;
+use {DEFAULT_ONIX, DEFAULT_OPDS};
+
+/// Render the metadata record for a book in the requested format.
+pub fn render(
+    ctx: &RenderContext,
+    config: &Config,
+    format: MetadataFormat,
+    identifier: &str,
+    epub: &Path,
+) -> Result<String, Error> {
+    let record = Record::new(ctx, config, format, identifier, epub, Utc::now());
+    let template = match format {
+        MetadataFormat::Onix => DEFAULT_ONIX,
+        MetadataFormat::Opds => DEFAULT_OPDS,
+    };
+
+    let rendered = Handlebars::new()
+        .render_template(template, &record)
+        .sync()
+        .with_context(|_| format!("Unable to render the {} record", format))?;
+
+    Ok(rendered)
+}
+
+/// The information made available to the metadata templates.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct Record<'a> {
+    sender: &'static str,
+    identifier: &'a str,
+    title: &'a str,
+    subtitle: Option<&'a str>,
+    edition: Option<&'a str>,
+    authors: Vec<Author<'a>>,
+    description: Option<&'a str>,
+    language: Option<String>,
+    rights: Option<&'a str>,
+    href: String,
+    onix_date: String,
+    updated: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct Author<'a> {
+    sequence: usize,
+    name: &'a str,
+}
+
+impl<'a> Record<'a> {
+    fn new(
+        ctx: &'a RenderContext,
+        config: &'a Config,
+        format: MetadataFormat,
+        identifier: &'a str,
+        epub: &Path,
+        now: DateTime<Utc>,
+    ) -> Record<'a> {
+        let book = &ctx.config.book;
+        let language = config.language.as_ref().and_then(|lang| match format {
+            MetadataFormat::Onix => onix_language(lang),
+            MetadataFormat::Opds => Some(lang.to_string()),
+        });
+        let href = epub
+            .file_name()
+            .map(|name| links::encode(&name.to_string_lossy()))
+            .unwrap_or_default();
+
+        Record {
+            sender: "mdbook-epub",
+            identifier,
+            title: book.title.as_deref().unwrap_or_default(),
+            subtitle: config.subtitle.as_deref(),
+            edition: config.edition.as_deref(),
+            authors: book
+                .authors
+                .iter()
+                .enumerate()
+                .map(|(i, name)| Author {
+                    sequence: i + 1,
+                    name,
+                })
+                .collect(),
+            description: book.description.as_deref(),
+            language,
+            rights: config
+                .copyright
+                .as_ref()
+                .and_then(|c| c.license.as_ref())
+                .map(String::as_str),
+            href,
+            onix_date: now.format("%Y%m%dT%H%MZ").to_string(),
+            updated: now.to_rfc3339(),
+        }
+    }
+}
+
+/// ONIX wants ISO 639-2/B language codes, while `book.language` is usually a
+/// two letter ISO 639-1 code.
+fn onix_language(lang: &str) -> Option<String> {
+    let primary = lang.split(['-', '_']).next().unwrap_or(lang);
+    let primary = primary.to_lowercase();
+
+    let code = match primary.as_str() {
+        "de" => "ger",
+        "en" => "eng",
+        "es" => "spa",
+        "fr" => "fre",
+        "it" => "ita",
+        "ja" => "jpn",
+        "ko" => "kor",
+        "nl" => "dut",
+        "pl" => "pol",
+        "pt" => "por",
+        "ru" => "rus",
+        "sv" => "swe",
+        "zh" => "chi",
+        other if other.len() == 3 => other,
+        _ => return None,
+    };
+
+    Some(code.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn two_letter_languages_are_converted_for_onix() {
+        let inputs = vec![
+            ("en", Some("eng")),
+            ("en-GB", Some("eng")),
+            ("FR", Some("fre")),
+            ("tlh", Some("tlh")),
+            ("xx", None),
+        ];
+
+        for (lang, should_be) in inputs {
+            let got = onix_language(lang);
+            assert_eq!(got.as_deref(), should_be, "{}", lang);
+        }
+    }
+}
@@ -0,0 +1,153 @@
+//! External commands which chapters are piped through before they're parsed,
+//! for EPUB-specific preprocessing that doesn't warrant a full `mdbook`
+//! preprocessor.
+
+use failure::{self, Error, ResultExt};
+use glob::Pattern;
+use mdbook::book::{BookItem, Chapter};
+use mdbook::renderer::RenderContext;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+
+use config::FilterConfig;
+
+/// Run every chapter through the filters whose glob matches its path
+/// (relative to `src/`), in the order they're listed.
+pub fn apply(ctx: &RenderContext, filters: &[FilterConfig]) -> Result<RenderContext, Error> {
+    let mut patterns = Vec::new();
+    for filter in filters {
+        if filter.command.is_empty() {
+            return Err(failure::err_msg(format!(
+                "The filter for \"{}\" has no command",
+                filter.glob
+            )));
+        }
+
+        let pattern = Pattern::new(&filter.glob)
+            .with_context(|_| format!("Invalid filter glob, \"{}\"", filter.glob))?;
+        patterns.push((pattern, &filter.command));
+    }
+
+    let mut book = ctx.book.clone();
+    let mut error = None;
+
+    book.for_each_mut(|item| {
+        if error.is_some() {
+            return;
+        }
+
+        if let BookItem::Chapter(ref mut ch) = *item {
+            for &(ref pattern, command) in &patterns {
+                if !pattern.matches_path(&ch.path) {
+                    continue;
+                }
+
+                match run(command, ch, &ctx.root) {
+                    Ok(content) => ch.content = content,
+                    Err(e) => {
+                        error = Some(e);
+                        return;
+                    }
+                }
+            }
+        }
+    });
+
+    if let Some(e) = error {
+        return Err(e);
+    }
+
+    let mut filtered = RenderContext::new(&ctx.root, book, ctx.config.clone(), &ctx.destination);
+    filtered.version = ctx.version.clone();
+
+    Ok(filtered)
+}
+
+/// Pipe a chapter's content through a single filter command.
+fn run(command: &[String], ch: &Chapter, root: &Path) -> Result<String, Error> {
+    debug!("Filtering \"{}\" with {:?}", ch, command);
+
+    let mut child = Command::new(&command[0])
+        .args(&command[1..])
+        .current_dir(root)
+        .env("MDBOOK_EPUB_CHAPTER", &ch.path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|_| format!("Unable to run the filter command, {}", command[0]))?;
+
+    // write from another thread so a filter which starts writing before it's
+    // read all of its input can't deadlock us
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let content = ch.content.clone();
+    let writer = thread::spawn(move || stdin.write_all(content.as_bytes()));
+
+    let output = child
+        .wait_with_output()
+        .with_context(|_| format!("The filter command ({}) didn't finish", command[0]))?;
+    // a filter is allowed to ignore its input
+    let _ = writer.join();
+
+    if !output.status.success() {
+        return Err(failure::err_msg(format!(
+            "The filter command ({}) failed on \"{}\" with {}: {}",
+            command[0],
+            ch,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    String::from_utf8(output.stdout).map_err(|_| {
+        failure::err_msg(format!(
+            "The filter command ({}) wrote invalid UTF-8 for \"{}\"",
+            command[0], ch
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn chapter(path: &str, content: &str) -> Chapter {
+        Chapter::new(
+            "Chapter",
+            content.to_string(),
+            PathBuf::from(path),
+            Vec::new(),
+        )
+    }
+
+    fn command(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn chapters_are_piped_through_the_command() {
+        let ch = chapter("chapter_1.md", "# Hello\n");
+
+        let got = run(&command(&["tr", "a-z", "A-Z"]), &ch, Path::new(".")).unwrap();
+
+        assert_eq!(got, "# HELLO\n");
+    }
+
+    #[test]
+    fn failing_filters_are_an_error() {
+        let ch = chapter("chapter_1.md", "# Hello\n");
+
+        assert!(run(&command(&["false"]), &ch, Path::new(".")).is_err());
+    }
+
+    #[test]
+    fn globs_match_nested_chapters() {
+        let pattern = Pattern::new("*.md").unwrap();
+
+        assert!(pattern.matches_path(Path::new("part-1/chapter_1.md")));
+        assert!(!pattern.matches_path(Path::new("part-1/chapter_1.txt")));
+    }
+}
@@ -0,0 +1,158 @@
+//! Embedded fonts and the [IDPF font obfuscation algorithm].
+//!
+//! Many font licenses only allow a font to be redistributed inside an EPUB
+//! if it has been obfuscated. The obfuscation key is derived from the
+//! package's unique identifier, so the first 1040 bytes of each font get
+//! XOR'ed with the SHA-1 of that identifier and a `META-INF/encryption.xml`
+//! file tells reading systems how to undo it.
+//!
+//! [IDPF font obfuscation algorithm]: http://www.idpf.org/epub/20/spec/FontManglingSpec.html
+
+use sha1::Sha1;
+use std::path::Path;
+
+/// How many bytes at the start of a font get obfuscated.
+const OBFUSCATED_LENGTH: usize = 1040;
+
+/// The algorithm identifier used in `encryption.xml`.
+const ALGORITHM: &str = "http://www.idpf.org/2008/embedding";
+
+/// Is this file a font we know how to embed?
+pub fn is_font(path: &Path) -> bool {
+    mimetype(path).is_some()
+}
+
+/// Get the media type for a font, based on its file extension.
+pub fn mimetype(path: &Path) -> Option<&'static str> {
+    let ext = path.extension()?.to_str()?.to_lowercase();
+
+    match ext.as_str() {
+        "otf" => Some("application/vnd.ms-opentype"),
+        "ttf" => Some("application/font-sfnt"),
+        "woff" => Some("application/font-woff"),
+        "woff2" => Some("font/woff2"),
+        _ => None,
+    }
+}
+
+/// Derive the obfuscation key from the package's unique identifier.
+pub fn key(identifier: &str) -> [u8; 20] {
+    let identifier: String = identifier
+        .chars()
+        .filter(|c| !matches_whitespace(*c))
+        .collect();
+
+    let mut hasher = Sha1::new();
+    hasher.update(identifier.as_bytes());
+    hasher.digest().bytes()
+}
+
+fn matches_whitespace(c: char) -> bool {
+    c == ' ' || c == '\t' || c == '\n' || c == '\r'
+}
+
+/// Obfuscate (or de-obfuscate, the operation is symmetric) a font in place.
+pub fn obfuscate(key: &[u8; 20], data: &mut [u8]) {
+    for (i, byte) in data.iter_mut().take(OBFUSCATED_LENGTH).enumerate() {
+        *byte ^= key[i % key.len()];
+    }
+}
+
+/// Find the package's unique identifier in the rendered `content.opf`.
+pub fn package_identifier(opf: &str) -> Option<&str> {
+    let start = opf.find("<dc:identifier")?;
+    let rest = &opf[start..];
+    let open = rest.find('>')? + 1;
+    let close = rest.find("</dc:identifier>")?;
+
+    rest.get(open..close).map(str::trim)
+}
+
+/// Generate the `META-INF/encryption.xml` file listing every obfuscated
+/// font, where each path is relative to the root of the archive.
+pub fn encryption_xml<S: AsRef<str>>(paths: &[S]) -> String {
+    let mut xml = String::from(concat!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+        "\n",
+        r#"<encryption xmlns="urn:oasis:names:tc:opendocument:xmlns:container" xmlns:enc="http://www.w3.org/2001/04/xmlenc#">"#,
+        "\n",
+    ));
+
+    for path in paths {
+        xml.push_str("  <enc:EncryptedData>\n");
+        xml.push_str(&format!(
+            "    <enc:EncryptionMethod Algorithm=\"{}\"/>\n",
+            ALGORITHM
+        ));
+        xml.push_str("    <enc:CipherData>\n");
+        xml.push_str(&format!(
+            "      <enc:CipherReference URI=\"{}\"/>\n",
+            path.as_ref()
+        ));
+        xml.push_str("    </enc:CipherData>\n");
+        xml.push_str("  </enc:EncryptedData>\n");
+    }
+
+    xml.push_str("</encryption>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diagnostics;
+
+    #[test]
+    fn fonts_use_core_media_types() {
+        for name in &["a.otf", "a.ttf", "a.woff", "a.woff2"] {
+            let mimetype = mimetype(Path::new(name)).unwrap();
+
+            assert!(diagnostics::is_core_media_type(mimetype), "{}", mimetype);
+        }
+    }
+
+    #[test]
+    fn whitespace_is_ignored_when_deriving_the_key() {
+        let a = key("urn:uuid:12345678-1234");
+        let b = key(" urn:uuid:12345678-1234\n");
+
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn obfuscation_is_reversible() {
+        let key = key("urn:uuid:12345678-1234");
+        let original: Vec<u8> = (0..2000).map(|i| (i % 256) as u8).collect();
+
+        let mut data = original.clone();
+        obfuscate(&key, &mut data);
+
+        assert_ne!(data[..OBFUSCATED_LENGTH], original[..OBFUSCATED_LENGTH]);
+        assert_eq!(data[OBFUSCATED_LENGTH..], original[OBFUSCATED_LENGTH..]);
+
+        obfuscate(&key, &mut data);
+        assert_eq!(data, original);
+    }
+
+    #[test]
+    fn find_the_identifier() {
+        let opf =
+            r#"<metadata><dc:identifier id="epub-id-1">urn:uuid:1234</dc:identifier></metadata>"#;
+
+        assert_eq!(package_identifier(opf), Some("urn:uuid:1234"));
+    }
+
+    #[test]
+    fn list_fonts_in_the_encryption_file() {
+        let got = encryption_xml(&["OEBPS/fonts/Roboto.ttf"]);
+
+        assert!(got.contains(r#"<enc:CipherReference URI="OEBPS/fonts/Roboto.ttf"/>"#));
+        assert!(got.contains(ALGORITHM));
+    }
+
+    #[test]
+    fn recognise_fonts() {
+        assert!(is_font(Path::new("fonts/Roboto.TTF")));
+        assert!(!is_font(Path::new("images/logo.png")));
+    }
+}
@@ -0,0 +1,339 @@
+//! Per-chapter settings, declared in a YAML block at the very top of the
+//! chapter's markdown.
+//!
+//! ```text
+//! ---
+//! epub-toc: false
+//! epub-css: wide-tables.css
+//! epub-style:
+//!   accent-color: teal
+//! ---
+//!
+//! # My Chapter
+//! ```
+//!
+//! mdBook's `{{#title My Custom Title}}` directive is treated the same as a
+//! `title` in the front matter.
+
+use failure::{Error, ResultExt};
+use pulldown_cmark::{Event, Tag};
+use serde::{Deserialize, Deserializer};
+use serde_yaml::{self, Value};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+use style;
+
+const DELIMITER: &str = "---";
+const TITLE_DIRECTIVE: &str = "{{#title ";
+
+/// The settings a chapter may declare in its front matter.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct FrontMatter {
+    /// A title to use instead of the chapter's name from `SUMMARY.md`, in
+    /// the table of contents, the page's `<title>` and its first heading.
+    pub title: Option<String>,
+    /// Should this chapter get an entry in the table of contents (default:
+    /// true)? The chapter itself is always included in the book.
+    pub epub_toc: bool,
+    /// Extra stylesheets (relative to the chapter) which only apply to this
+    /// chapter. Either a single path or a list.
+    #[serde(deserialize_with = "one_or_many")]
+    pub epub_css: Vec<String>,
+    /// Extra markup to insert into the chapter's `<head>`.
+    pub epub_head: Option<String>,
+    /// What kind of chapter this is (e.g. `"preface"` or `"appendix"`).
+    pub epub_type: Option<String>,
+    /// Design tokens which override `output.epub.style` in this chapter.
+    #[serde(deserialize_with = "style::tokens")]
+    pub epub_style: BTreeMap<String, String>,
+}
+
+impl Default for FrontMatter {
+    fn default() -> FrontMatter {
+        FrontMatter {
+            title: None,
+            epub_toc: true,
+            epub_css: Vec::new(),
+            epub_head: None,
+            epub_type: None,
+            epub_style: BTreeMap::new(),
+        }
+    }
+}
+
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(item) => Ok(vec![item]),
+        OneOrMany::Many(items) => Ok(items),
+    }
+}
+
+/// Split a chapter's content into its front matter and the remaining
+/// markdown, with any `{{#title}}` directive removed.
+pub fn split(content: &str) -> Result<(FrontMatter, Cow<'_, str>), Error> {
+    let (mut front_matter, rest) = match find_front_matter(content) {
+        Some((yaml, rest)) => match serde_yaml::from_str(yaml) {
+            Ok(Value::Mapping(mapping)) => {
+                let front_matter = serde_yaml::from_value(Value::Mapping(mapping))
+                    .context("Unable to parse the chapter's front matter")?;
+                (front_matter, rest)
+            }
+            // a chapter can start with a horizontal rule, so anything which
+            // isn't a mapping is just markdown
+            _ => (FrontMatter::default(), content),
+        },
+        None => (FrontMatter::default(), content),
+    };
+
+    let (title, rest) = title_directive(rest);
+    if front_matter.title.is_none() {
+        front_matter.title = title;
+    }
+
+    Ok((front_matter, rest))
+}
+
+/// Replace the text of the chapter's first heading with its custom title, so
+/// the page shows the same title as the table of contents.
+pub fn retitle_first_heading<'a, I>(events: I, title: &str) -> Vec<Event<'a>>
+where
+    I: IntoIterator<Item = Event<'a>>,
+{
+    let mut retitled = Vec::new();
+    // `Some(true)` while inside the first heading, `Some(false)` after it
+    let mut in_heading = None;
+
+    for event in events {
+        match event {
+            Event::Start(Tag::Header(level)) if in_heading.is_none() => {
+                retitled.push(Event::Start(Tag::Header(level)));
+                retitled.push(Event::Text(title.to_string().into()));
+                in_heading = Some(true);
+            }
+            Event::End(Tag::Header(level)) if in_heading == Some(true) => {
+                retitled.push(Event::End(Tag::Header(level)));
+                in_heading = Some(false);
+            }
+            _ if in_heading == Some(true) => {}
+            other => retitled.push(other),
+        }
+    }
+
+    retitled
+}
+
+/// Find and remove the `{{#title}}` directive, if there is one. It only
+/// counts at the start of a line before the chapter's first heading (and
+/// outside code blocks), so examples of the directive are left alone.
+fn title_directive(text: &str) -> (Option<String>, Cow<'_, str>) {
+    let mut offset = 0;
+    let mut fence = None;
+
+    for line in text.split('\n') {
+        let start = offset;
+        offset += line.len() + 1;
+        let trimmed = line.trim_start();
+
+        if let Some(marker) = fence {
+            if trimmed.starts_with(marker) {
+                fence = None;
+            }
+            continue;
+        }
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            fence = Some(&trimmed[..3]);
+            continue;
+        }
+        if trimmed.starts_with('#') {
+            break;
+        }
+        if !line.starts_with(TITLE_DIRECTIVE) {
+            continue;
+        }
+
+        if let Some(ix) = line.find("}}") {
+            let end = start + ix + "}}".len();
+            let title = text[start + TITLE_DIRECTIVE.len()..end - "}}".len()].trim();
+            let rest = format!("{}{}", &text[..start], &text[end..]);
+
+            return (Some(title.to_string()), Cow::Owned(rest));
+        }
+    }
+
+    (None, Cow::Borrowed(text))
+}
+
+/// Find the raw front matter (excluding delimiters) and the markdown which
+/// follows it.
+fn find_front_matter(content: &str) -> Option<(&str, &str)> {
+    let mut lines = content.split('\n');
+
+    if lines.next().map(str::trim_end) != Some(DELIMITER) {
+        return None;
+    }
+
+    let start = content.find('\n')? + 1;
+    let mut offset = start;
+
+    for line in lines {
+        if line.trim_end() == DELIMITER {
+            let end = offset + line.len();
+            let rest = if end < content.len() {
+                &content[end + 1..]
+            } else {
+                ""
+            };
+
+            return Some((&content[start..offset], rest));
+        }
+
+        offset += line.len() + 1;
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::{html, Parser};
+
+    #[test]
+    fn chapters_without_front_matter_are_untouched() {
+        let src = "# Chapter 1\n\n---\n\nSome text\n";
+
+        let (front_matter, rest) = split(src).unwrap();
+
+        assert_eq!(front_matter, FrontMatter::default());
+        assert_eq!(rest, src);
+    }
+
+    #[test]
+    fn parse_front_matter() {
+        let src = "---\nepub-toc: false\n---\n# Chapter 1\n";
+
+        let (front_matter, rest) = split(src).unwrap();
+
+        assert!(!front_matter.epub_toc);
+        assert_eq!(rest, "# Chapter 1\n");
+    }
+
+    #[test]
+    fn extra_stylesheets_can_be_one_or_many() {
+        let inputs = vec![
+            ("---\nepub-css: wide.css\n---\n", vec!["wide.css"]),
+            (
+                "---\nepub-css: [a.css, b.css]\n---\n",
+                vec!["a.css", "b.css"],
+            ),
+        ];
+
+        for (src, should_be) in inputs {
+            let (front_matter, _) = split(src).unwrap();
+            assert_eq!(front_matter.epub_css, should_be);
+        }
+    }
+
+    #[test]
+    fn style_tokens_can_be_numbers() {
+        let src = "---\nepub-style:\n  accent-color: teal\n  line-height: 1.5\n---\n";
+
+        let (front_matter, _) = split(src).unwrap();
+
+        assert_eq!(front_matter.epub_style["accent-color"], "teal");
+        assert_eq!(front_matter.epub_style["line-height"], "1.5");
+    }
+
+    #[test]
+    fn titles_can_come_from_a_directive() {
+        let src = "{{#title My Custom Title}}\n# Chapter 1\n";
+
+        let (front_matter, rest) = split(src).unwrap();
+
+        assert_eq!(front_matter.title, Some(String::from("My Custom Title")));
+        assert_eq!(rest, "\n# Chapter 1\n");
+    }
+
+    #[test]
+    fn title_directives_in_code_or_after_the_heading_are_ignored() {
+        let inputs = vec![
+            "```\n{{#title In A Code Block}}\n```\n# Chapter 1\n",
+            "# Chapter 1\n\n{{#title After The Heading}}\n",
+            "Use `{{#title Inline Code}}` to set the title.\n",
+        ];
+
+        for src in inputs {
+            let (front_matter, rest) = split(src).unwrap();
+
+            assert_eq!(front_matter.title, None, "{}", src);
+            assert_eq!(rest, src);
+        }
+    }
+
+    #[test]
+    fn the_first_heading_gets_the_custom_title() {
+        let src = "Intro\n\n# Chapter *1*\n\n## Details\n";
+
+        let events = retitle_first_heading(Parser::new(src), "Getting Started");
+        let mut got = String::new();
+        html::push_html(&mut got, events.into_iter());
+
+        assert_eq!(
+            got,
+            "<p>Intro</p>\n<h1>Getting Started</h1>\n<h2>Details</h2>\n"
+        );
+    }
+
+    #[test]
+    fn front_matter_titles_take_priority() {
+        let src = "---\ntitle: From YAML\n---\n{{#title From Directive}}\n";
+
+        let (front_matter, rest) = split(src).unwrap();
+
+        assert_eq!(front_matter.title, Some(String::from("From YAML")));
+        assert_eq!(rest, "\n");
+    }
+
+    #[test]
+    fn unterminated_front_matter_is_just_markdown() {
+        let src = "---\nepub-toc: false\n";
+
+        let (front_matter, rest) = split(src).unwrap();
+
+        assert_eq!(front_matter, FrontMatter::default());
+        assert_eq!(rest, src);
+    }
+
+    #[test]
+    fn chapters_can_start_with_a_horizontal_rule() {
+        for src in &[
+            "---\n\nSome paragraph\n\n---\n\nMore text\n",
+            "---\n---\n# Chapter 1\n",
+            "---\n\n- a list\n- of items\n\n---\n",
+        ] {
+            let (front_matter, rest) = split(src).unwrap();
+
+            assert_eq!(front_matter, FrontMatter::default());
+            assert_eq!(rest, *src);
+        }
+    }
+
+    #[test]
+    fn invalid_settings_are_still_an_error() {
+        let src = "---\nepub-toc: [1, 2]\n---\n# Chapter 1\n";
+
+        assert!(split(src).is_err());
+    }
+}
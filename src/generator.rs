@@ -1,38 +1,244 @@
-use std::fs::File;
-use std::io::{Cursor, Read, Write};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read, Seek, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::thread;
 
-use epub_builder::{EpubBuilder, EpubContent, TocElement, ZipLibrary};
-use failure::{Error, ResultExt};
+use chrono::Utc;
+use epub_builder::{EpubBuilder, EpubContent, EpubVersion, ReferenceType, TocElement};
+use failure::{self, Error, ResultExt};
+use handlebars::Handlebars;
 use mdbook::book::{BookItem, Chapter};
 use mdbook::renderer::RenderContext;
-use pulldown_cmark::{html, Parser};
+use pulldown_cmark::{html, Event, Tag};
+use serde_json;
 
-use config::Config;
+use archival::{self, BuildInfo};
+use archive::{Archive, Package};
+use bibliography::Bibliography;
+use collisions::{self, PathRegistry};
+use config::{AssetPlacement, ColorScheme, Config, FootnoteMode, Level};
+use credits;
+use diagnostics::{self, Category, Diagnostic};
+use endnotes::{self, Note};
+use fonts;
+use front_matter::{self, FrontMatter};
+use links::{self, LinkKind};
+use markdown;
+use numbering;
+use opf;
+use page_list::{self, PageTarget};
+use pipeline::{self, ParseStage, Parsed, Source};
+use postprocess::{ChapterInfo, PostProcessor, PostProcessors};
+use progress::{CountingWriter, Phase, Progress, Reporter};
+use report::{AssetFailure, AssetReport, BuildReport, ChapterReport, CoverReport};
 use resources::{self, Asset};
+use responsive;
+use sanitize;
+use scripts;
+use semantics::{self, Group, Semantic};
+use split;
+use stats;
+use strings::Strings;
+use style;
+use theme::{Theme, DEFAULT_THEME_DIR};
+use transform::{AssetData, AssetTransformer, ResizeImages, Transformers};
+use typography::Typography;
 use utils::ResultExt as SyncResultExt;
-use DEFAULT_CSS;
+use DEFAULT_PLACEHOLDER;
+
+/// Where the night mode stylesheet is written.
+const NIGHT_STYLESHEET: &str = "night.css";
+
+/// Where the notes page is written when using `footnotes = "end-of-book"`.
+const NOTES_PAGE: &str = "notes.xhtml";
+
+/// Where the references page is written when the book cites a bibliography.
+const REFERENCES_PAGE: &str = "references.xhtml";
+
+/// Where the about page is written.
+const ABOUT_PAGE: &str = "about.xhtml";
+
+/// Where the credits page is written when any assets have credits.
+const CREDITS_PAGE: &str = "credits.xhtml";
+
+/// The names generated pages are referred to by in `output.epub.spine`.
+const GENERATED_PAGES: &[&str] = &["title-page", "copyright", "about"];
 
 /// The actual EPUB book renderer.
+///
+/// Chapters and assets are streamed into the writer as they're added, so the
+/// entire book never needs to be held in memory at once.
 #[derive(Debug)]
-pub struct Generator<'a> {
+pub struct Generator<'a, W: Write + Seek> {
     ctx: &'a RenderContext,
-    builder: EpubBuilder<ZipLibrary>,
+    builder: EpubBuilder<Archive<CountingWriter<W>>>,
     config: Config,
+    hbs: Handlebars,
+    theme: Theme,
+    transformers: Transformers<'a>,
+    postprocessors: PostProcessors<'a>,
+    progress: Reporter<'a>,
+    report: BuildReport,
+    paths: PathRegistry,
+    package: Rc<RefCell<Package>>,
+    /// Footnotes collected for the notes page, along with the document each
+    /// one is referenced from (if it's referenced at all).
+    endnotes: Vec<(Note, Option<String>)>,
+    bibliography: Option<Bibliography>,
+    typography: Typography,
+    /// The labels used in generated pages and navigation.
+    strings: Strings,
+    /// The path of every chapter in `SUMMARY.md`, for resolving links
+    /// between them.
+    chapters: Vec<PathBuf>,
+    /// Assets added by the caller, packaged after the ones the book
+    /// references.
+    extra_assets: Vec<Asset>,
+    /// The assets each chapter references, keyed by the chapter's path.
+    /// They're found by the parse stage, so the book is only parsed once.
+    chapter_assets: HashMap<PathBuf, Vec<Asset>>,
+    /// Each chapter's front matter, keyed by the chapter's path.
+    front_matter: HashMap<PathBuf, FrontMatter>,
 }
 
-impl<'a> Generator<'a> {
-    pub fn new(ctx: &'a RenderContext) -> Result<Generator<'a>, Error> {
-        let builder = EpubBuilder::new(ZipLibrary::new().sync()?).sync()?;
-
+impl<'a, W: Write + Seek> Generator<'a, W> {
+    pub fn new(ctx: &'a RenderContext, writer: W) -> Result<Generator<'a, W>, Error> {
         let config = Config::from_render_context(ctx)?;
 
+        let written = Rc::new(Cell::new(0));
+        let writer = CountingWriter::new(writer, Rc::clone(&written));
+        let mut package = Package {
+            profile: config.profile,
+            ..Default::default()
+        };
+        if let Some(ref series) = config.series {
+            package.metadata.extend(opf::series_metadata(series));
+        }
+        package
+            .metadata
+            .extend(opf::extra_metadata(&config.extra_opf_meta)?);
+        let package = Rc::new(RefCell::new(package));
+        // nothing is written during a dry run, so there's no point reordering it
+        let placement = if config.dry_run {
+            AssetPlacement::Streamed
+        } else {
+            config.asset_placement
+        };
+        let archive = Archive::new(
+            writer,
+            config.obfuscate_fonts,
+            placement,
+            Rc::clone(&package),
+        )?;
+        let mut builder = EpubBuilder::new(archive).sync()?;
+        builder.epub_version(EpubVersion::V30);
+
+        let mut hbs = Handlebars::new();
+        let theme_dir = match config.theme {
+            Some(ref dir) => ctx.root.join(dir),
+            None => ctx.root.join(DEFAULT_THEME_DIR),
+        };
+        let theme = Theme::load(&theme_dir)?;
+        let transformers = Transformers::from_config(&config.assets)?;
+        let typography =
+            Typography::new(&config.typography, config.language.as_deref(), &ctx.root)?;
+        let strings = Strings::new(config.language.as_deref(), &config.strings)?;
+        let bibliography = match config.bibliography {
+            Some(ref path) => Some(Bibliography::load(&ctx.root.join(path))?),
+            None => None,
+        };
+        let chapters = ctx
+            .book
+            .iter()
+            .filter_map(|item| match *item {
+                BookItem::Chapter(ref ch) => Some(links::nfc_path(&ch.path)),
+                _ => None,
+            })
+            .collect();
+
+        hbs.register_template_string("index", &theme.chapter)
+            .sync()
+            .context("Unable to parse the chapter template")?;
+        let title_page =
+            load_template(ctx, config.title_page_template.as_ref(), &theme.title_page)?;
+        hbs.register_template_string("title_page", title_page)
+            .sync()
+            .context("Unable to parse the title page template")?;
+
+        if let Some(ref copyright) = config.copyright {
+            let template = load_template(ctx, copyright.template.as_ref(), &theme.copyright_page)?;
+            hbs.register_template_string("copyright_page", template)
+                .sync()
+                .context("Unable to parse the copyright page template")?;
+        }
+        hbs.register_template_string("about_page", &theme.about_page)
+            .sync()
+            .context("Unable to parse the about page template")?;
+
         Ok(Generator {
             builder,
             ctx,
             config,
+            hbs,
+            theme,
+            transformers,
+            postprocessors: PostProcessors::default(),
+            progress: Reporter::new(written),
+            report: BuildReport::default(),
+            paths: PathRegistry::new(),
+            package,
+            endnotes: Vec::new(),
+            bibliography,
+            typography,
+            strings,
+            chapters,
+            extra_assets: Vec::new(),
+            chapter_assets: HashMap::new(),
+            front_matter: HashMap::new(),
         })
     }
 
+    /// Add a transformer which every asset is passed through before being
+    /// packaged, after the ones enabled in `output.epub.assets`.
+    pub fn with_transformer<T>(mut self, transformer: T) -> Generator<'a, W>
+    where
+        T: AssetTransformer + 'a,
+    {
+        self.transformers.push(transformer);
+        self
+    }
+
+    /// Package an extra asset which the book doesn't reference directly, e.g.
+    /// a file generated by a preprocessor. Its `filename` is where it goes
+    /// in the book, relative to the chapters.
+    pub fn with_asset(mut self, asset: Asset) -> Generator<'a, W> {
+        self.extra_assets.push(asset);
+        self
+    }
+
+    /// Add a hook which may rewrite each chapter's XHTML before it's
+    /// packaged.
+    pub fn with_postprocessor<P>(mut self, processor: P) -> Generator<'a, W>
+    where
+        P: PostProcessor + 'a,
+    {
+        self.postprocessors.push(processor);
+        self
+    }
+
+    /// Register a callback to be notified as the book is generated.
+    pub fn with_progress<F>(mut self, callback: F) -> Generator<'a, W>
+    where
+        F: FnMut(&Progress) + 'a,
+    {
+        self.progress.set_callback(callback);
+        self
+    }
+
     fn populate_metadata(&mut self) -> Result<(), Error> {
         self.builder.metadata("generator", "mdbook-epub").sync()?;
 
@@ -42,6 +248,12 @@ impl<'a> Generator<'a> {
         if let Some(desc) = self.ctx.config.book.description.clone() {
             self.builder.metadata("description", desc).sync()?;
         }
+        if let Some(lang) = self.config.language.clone() {
+            self.builder.metadata("lang", lang).sync()?;
+        }
+        self.builder
+            .metadata("toc_name", self.strings.get("table-of-contents"))
+            .sync()?;
 
         if !self.ctx.config.book.authors.is_empty() {
             self.builder
@@ -52,43 +264,560 @@ impl<'a> Generator<'a> {
         Ok(())
     }
 
-    pub fn generate<W: Write>(mut self, writer: W) -> Result<(), Error> {
+    /// Generate the book, returning a report of everything which went into
+    /// it.
+    pub fn generate(mut self) -> Result<BuildReport, Error> {
         info!("Generating the EPUB book");
 
         self.populate_metadata()?;
-        self.generate_chapters()?;
 
+        self.progress.start(Phase::Chapters);
+        self.generate_spine()?;
+
+        self.progress.start(Phase::Assets);
+        self.embed_cover()?;
         self.embed_stylesheets()?;
+        self.embed_fonts()?;
+        self.embed_scripts()?;
         self.additional_assets()?;
-        self.builder.generate(writer).sync()?;
+        self.embed_sources()?;
+        self.attach_credits()?;
+        if self.config.credits_page {
+            self.add_credits_page()?;
+        }
 
-        Ok(())
+        self.progress.start(Phase::Packaging);
+        {
+            let span = info_span!("zip");
+            let _enter = span.enter();
+            // the archive has already been streamed to our writer
+            self.builder.generate(io::sink()).sync()?;
+        }
+        {
+            let package = self.package.borrow();
+            self.report.identifier = package.identifier.clone();
+            self.report.accessibility.page_targets = package.page_list.len();
+            self.report.accessibility.scripted_documents = package.scripted.len();
+        }
+        self.progress.bytes_written();
+        self.progress.finish();
+
+        Ok(self.report)
     }
 
-    fn generate_chapters(&mut self) -> Result<(), Error> {
+    /// Add every page to the book, in reading order.
+    ///
+    /// Chapters are parsed by the parse stage (see the `pipeline` module)
+    /// while the ones before them are rendered.
+    fn generate_spine(&mut self) -> Result<(), Error> {
         debug!("Rendering Chapters");
 
-        for item in self.ctx.book.iter() {
-            if let BookItem::Chapter(ref ch) = *item {
-                // iter() gives us an iterator over every node in the tree
-                // but we only want the top level here so we can recursively
-                // visit the chapters.
-                debug!("Adding chapter \"{}\"", ch);
-                self.add_chapter(ch)?;
+        let spine = self.spine()?;
+        let mut chapters = Vec::new();
+        for page in &spine {
+            if let SpinePage::Chapter(ch, _) = *page {
+                reading_order(ch, &mut chapters);
             }
         }
 
+        // the parse stage borrows each chapter's text, so its front matter
+        // needs to be split off first
+        let mut texts = Vec::with_capacity(chapters.len());
+        for ch in &chapters {
+            let (front_matter, text) = front_matter::split(&ch.content)?;
+            self.front_matter.insert(ch.path.clone(), front_matter);
+            texts.push(text);
+        }
+        let sources = chapters
+            .iter()
+            .zip(&texts)
+            .map(|(ch, text)| Source {
+                path: &ch.path,
+                text,
+            })
+            .collect();
+
+        let src_dir = self.src_dir()?;
+        let config = self.config.clone();
+        let strings = self.strings.clone();
+        thread::scope(|scope| {
+            let mut parsed = ParseStage::start(scope, sources, |source| {
+                pipeline::parse(source, &src_dir, &config, &strings)
+            });
+
+            for page in &spine {
+                match *page {
+                    SpinePage::Generated(name) => self.add_named_page(name)?,
+                    SpinePage::Chapter(ch, matter) => {
+                        debug!("Adding chapter \"{}\"", ch);
+                        self.add_chapter(ch, matter, &mut parsed)?;
+                    }
+                }
+            }
+
+            Ok::<(), Error>(())
+        })?;
+
+        if !self.endnotes.is_empty() {
+            self.add_notes_page()?;
+        }
+        if self
+            .bibliography
+            .as_ref()
+            .is_some_and(Bibliography::is_cited)
+        {
+            self.add_references_page()?;
+        }
+        if self.package.borrow().deferred.contains_key(ABOUT_PAGE) {
+            self.render_about_page()?;
+        }
+
         Ok(())
     }
 
-    fn add_chapter(&mut self, ch: &Chapter) -> Result<(), Error> {
-        let mut buffer = String::new();
-        html::push_html(&mut buffer, Parser::new(&ch.content));
+    /// Every page in the book, in the order `output.epub.spine` puts them.
+    fn spine(&self) -> Result<Vec<SpinePage<'a>>, Error> {
+        let ctx = self.ctx;
+        let spine = &self.config.spine;
+        let placed: Vec<&String> = spine
+            .front_matter
+            .iter()
+            .chain(&spine.back_matter)
+            .collect();
+        let mut pages = Vec::new();
+
+        for page in GENERATED_PAGES {
+            if !placed.iter().any(|entry| entry == page) {
+                pages.push(SpinePage::Generated(page));
+            }
+        }
+
+        for entry in &spine.front_matter {
+            pages.push(spine_page(ctx, entry, Matter::Front)?);
+        }
+
+        for ch in top_level_chapters(ctx) {
+            if !placed.iter().any(|entry| is_chapter(entry, ch)) {
+                pages.push(SpinePage::Chapter(ch, Matter::Body));
+            }
+        }
 
-        let data = Cursor::new(Vec::from(buffer));
+        for entry in &spine.back_matter {
+            pages.push(spine_page(ctx, entry, Matter::Back)?);
+        }
+
+        Ok(pages)
+    }
+
+    /// Add one of the `GENERATED_PAGES`, if it's enabled.
+    fn add_named_page(&mut self, name: &str) -> Result<(), Error> {
+        match name {
+            "title-page" if self.config.title_page => self.add_title_page(),
+            "copyright" if self.config.copyright.is_some() => self.add_copyright_page(),
+            "about" if self.config.about_page => self.add_about_page(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Render the title page and insert it at the very start of the book.
+    fn add_title_page(&mut self) -> Result<(), Error> {
+        let rendered = match self.theme.title_page_xhtml.clone() {
+            Some(page) => {
+                debug!("Using the theme's title page");
+                page
+            }
+            None => self.render_title_page()?,
+        };
+
+        // generated pages are deliberately left without a title so they
+        // don't show up in the table of contents
+        self.claim_path("title_page.xhtml", "the title page")?;
+        let content = EpubContent::new("title_page.xhtml", Cursor::new(rendered.into_bytes()))
+            .reftype(ReferenceType::TitlePage);
+        self.builder.add_content(content).sync()?;
+        self.report.spine.push(String::from("title_page.xhtml"));
+
+        Ok(())
+    }
+
+    fn render_title_page(&mut self) -> Result<String, Error> {
+        debug!("Rendering the title page");
+
+        let logo = match self.config.logo.clone() {
+            Some(logo) => Some(self.embed_logo(&logo)?),
+            None => None,
+        };
+
+        let rendered = {
+            let book = &self.ctx.config.book;
+            let data = TitlePage {
+                title: book.title.as_deref().unwrap_or_default(),
+                subtitle: self.config.subtitle.as_deref(),
+                authors: book.authors.join(", "),
+                edition: self.config.edition.as_deref(),
+                logo,
+            };
+
+            self.hbs
+                .render("title_page", &data)
+                .sync()
+                .context("Unable to render the title page")?
+        };
+
+        Ok(rendered)
+    }
+
+    /// Render the copyright page (colophon) from the `output.epub.copyright`
+    /// table and add it to the front matter.
+    fn add_copyright_page(&mut self) -> Result<(), Error> {
+        debug!("Rendering the copyright page");
+
+        let rendered = {
+            let book = &self.ctx.config.book;
+            let copyright = match self.config.copyright {
+                Some(ref c) => c,
+                None => return Ok(()),
+            };
+
+            let notice = copyright.text.as_ref().map(|text| {
+                let mut buffer = String::new();
+                html::push_html(&mut buffer, markdown::parser(text, &self.config.markdown));
+                buffer
+            });
+
+            let data = CopyrightPage {
+                title: book.title.as_deref().unwrap_or_default(),
+                year: copyright.year.as_deref(),
+                holder: copyright
+                    .holder
+                    .clone()
+                    .unwrap_or_else(|| book.authors.join(", ")),
+                license: copyright.license.as_deref(),
+                edition: self.config.edition.as_deref(),
+                notice,
+                strings: self.strings.template_data(),
+            };
+
+            self.hbs
+                .render("copyright_page", &data)
+                .sync()
+                .context("Unable to render the copyright page")?
+        };
+
+        self.claim_path("copyright.xhtml", "the copyright page")?;
+        let content = EpubContent::new("copyright.xhtml", Cursor::new(rendered.into_bytes()))
+            .reftype(ReferenceType::Copyright);
+        self.builder.add_content(content).sync()?;
+        self.report.spine.push(String::from("copyright.xhtml"));
+
+        Ok(())
+    }
+
+    /// Add the "About this book" page to the book. It can't be rendered until
+    /// every chapter's words have been counted, so its content is deferred
+    /// (see `render_about_page()`).
+    fn add_about_page(&mut self) -> Result<(), Error> {
+        self.claim_path(ABOUT_PAGE, "the about page")?;
+        self.package
+            .borrow_mut()
+            .deferred
+            .insert(String::from(ABOUT_PAGE), None);
+        let content = EpubContent::new(ABOUT_PAGE, io::empty()).reftype(ReferenceType::Preface);
+        self.builder.add_content(content).sync()?;
+        self.report.spine.push(String::from(ABOUT_PAGE));
+
+        Ok(())
+    }
+
+    /// Render the about page, listing how long each chapter takes to read.
+    fn render_about_page(&mut self) -> Result<(), Error> {
+        debug!("Rendering the about page");
+
+        let rendered = {
+            let wpm = self.config.words_per_minute;
+            let words: HashMap<&Path, usize> = self
+                .report
+                .chapters
+                .iter()
+                .map(|ch| (ch.source.as_path(), ch.words))
+                .collect();
+            let mut chapters = Vec::new();
+
+            for item in self.ctx.book.iter() {
+                if let BookItem::Chapter(ref ch) = *item {
+                    let front_matter = self.chapter_front_matter(ch);
+                    let words = words.get(ch.path.as_path()).cloned().unwrap_or(0);
+                    chapters.push(AboutChapter {
+                        name: self.chapter_title(ch, &front_matter),
+                        words,
+                        minutes: stats::reading_time(words, wpm),
+                    });
+                }
+            }
+
+            let total_words = chapters.iter().map(|ch| ch.words).sum();
+            let total_minutes = stats::reading_time(total_words, wpm);
+            let title = self.ctx.config.book.title.as_deref().unwrap_or_default();
+            let reading_time = self.strings.format(
+                "reading-time",
+                &[
+                    ("title", title),
+                    ("words", &total_words.to_string()),
+                    ("minutes", &total_minutes.to_string()),
+                ],
+            );
+            let data = AboutPage {
+                title,
+                chapters,
+                total_words,
+                total_minutes,
+                reading_time,
+                strings: self.strings.template_data(),
+            };
+
+            self.hbs
+                .render("about_page", &data)
+                .sync()
+                .context("Unable to render the about page")?
+        };
+
+        self.package
+            .borrow_mut()
+            .deferred
+            .insert(String::from(ABOUT_PAGE), Some(rendered.into_bytes()));
+
+        Ok(())
+    }
+
+    /// Copy the logo into the book, returning the path it can be referenced
+    /// by.
+    fn embed_logo(&mut self, logo: &Path) -> Result<String, Error> {
+        let location = self.ctx.root.join(logo);
+        let filename = match location.file_name() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => {
+                return Err(failure::err_msg(format!(
+                    "The logo must be a file, {}",
+                    location.display()
+                )))
+            }
+        };
+
+        let asset = Asset::new(links::nfc(&filename), &location);
+        self.load_asset(&asset)
+            .with_context(|_| format!("Couldn't load the logo, {}", location.display()))?;
+
+        Ok(links::encode(&collisions::normalize(&asset.filename)))
+    }
 
-        let path = ch.path.with_extension("html").display().to_string();
-        let mut content = EpubContent::new(path, data).title(format!("{}", ch));
+    fn add_chapter(
+        &mut self,
+        ch: &Chapter,
+        matter: Matter,
+        parsed: &mut ParseStage,
+    ) -> Result<(), Error> {
+        let span = info_span!("render chapter", chapter = %ch.name);
+        let _enter = span.enter();
+
+        let Parsed {
+            events,
+            words,
+            long_lines,
+            assets,
+            ..
+        } = parsed.next(&ch.path)?;
+        let front_matter = self.chapter_front_matter(ch);
+        let title = self.chapter_title(ch, &front_matter);
+        let semantic = self.chapter_semantic(ch, &front_matter)?;
+        // chapters that weren't explicitly placed in the front or back matter
+        // go wherever their semantics say they belong
+        let matter = match semantic {
+            Some(ref semantic) if matter == Matter::Body => Matter::from(semantic.group),
+            _ => matter,
+        };
+        let stylesheets = self.embed_chapter_stylesheets(ch, &front_matter)?;
+        let style = style::inline(&front_matter.epub_style)
+            .with_context(|_| format!("Invalid epub-style in \"{}\"", ch))?;
+
+        self.chapter_assets.insert(ch.path.clone(), assets);
+        self.check_code_lines(ch, &long_lines);
+        self.check_chapter_content(ch, &events)?;
+        let events = scripts::transform(events, &self.config.scripts);
+        let events = match self.bibliography {
+            Some(ref mut bibliography) => {
+                bibliography.cite(events, &href_from_chapter(&ch.path, REFERENCES_PAGE))
+            }
+            None => events,
+        };
+        let events = links::rewrite(events, &self.config.package_links);
+        let links_into_chapters = links::links_into_chapters(&events);
+        let events = links::rewrite_chapter_links(events, &ch.path, &self.chapters);
+        let events = links::annotate_external(events, self.config.link_urls);
+        let events = match front_matter.title {
+            Some(ref title) => front_matter::retitle_first_heading(events, title),
+            None => events,
+        };
+        let events = self.typography.transform(events);
+        let events = if self.config.section_numbers && self.config.number_headings {
+            numbering::number_first_heading(events, ch)
+        } else {
+            events
+        };
+        let (events, notes) = match self.config.footnotes {
+            FootnoteMode::Inline => (events, Vec::new()),
+            FootnoteMode::EndOfChapter => endnotes::collect(events, 1, ""),
+            FootnoteMode::EndOfBook => {
+                let notes_href = href_from_chapter(&ch.path, NOTES_PAGE);
+                endnotes::collect(events, self.endnotes.len() + 1, &notes_href)
+            }
+        };
+
+        let mut chunks = split::render(events, self.config.max_chapter_size);
+        let paths: Vec<String> = (0..chunks.len())
+            .map(|i| split::chunk_path(&ch.path, i))
+            .collect();
+
+        if chunks.len() > 1 {
+            debug!("Splitting \"{}\" into {} parts", ch, chunks.len());
+        }
+        let max_size = self.config.max_chapter_size;
+        for (chunk, path) in chunks.iter().zip(&paths) {
+            if max_size > 0 && chunk.len() > max_size {
+                self.diagnose(
+                    Category::OversizedChapter,
+                    format!(
+                        "{} (from \"{}\") is {} bytes, even after splitting it at headings (the maximum is {})",
+                        path,
+                        ch,
+                        chunk.len(),
+                        max_size
+                    ),
+                );
+            }
+        }
+
+        if self.config.footnotes == FootnoteMode::EndOfChapter && !notes.is_empty() {
+            let section = endnotes::section(
+                &notes,
+                Some(self.strings.get("notes")),
+                &self.strings,
+                |note| reference_chunk(&chunks, note).map(|_| String::new()),
+            );
+            if let Some(last) = chunks.last_mut() {
+                last.push_str(&section);
+            }
+        }
+        split::link_chunks(&mut chunks, &paths);
+        {
+            let mut package = self.package.borrow_mut();
+            if chunks.len() > 1 {
+                split::record_anchors(&mut package.anchors, &chunks, &paths);
+            }
+            if links_into_chapters {
+                let documents = paths
+                    .iter()
+                    .map(|path| collisions::normalize(Path::new(path)));
+                package.linking_documents.extend(documents);
+            }
+        }
+
+        if self.config.footnotes == FootnoteMode::EndOfBook {
+            for note in notes {
+                let backref = reference_chunk(&chunks, &note).map(|ix| links::encode(&paths[ix]));
+                self.endnotes.push((note, backref));
+            }
+        }
+
+        let mut contents = Vec::new();
+        let mut files = Vec::new();
+        let image_width = self
+            .config
+            .assets
+            .image_width
+            .unwrap_or(responsive::DEFAULT_IMAGE_WIDTH);
+
+        for (i, (chunk, path)) in chunks.into_iter().zip(paths).enumerate() {
+            self.add_page_targets(&path, &chunk)?;
+            let chunk = responsive::collapse(chunk, image_width);
+            let (chunk, substitutions) = sanitize::sanitize(chunk, &self.strings);
+            for substitution in substitutions {
+                self.diagnose(
+                    Category::ForbiddenElement,
+                    format!(
+                        "Replaced {} in \"{}\" with a link, it can't be used in an EPUB",
+                        substitution, ch
+                    ),
+                );
+            }
+
+            let mut head = front_matter.epub_head.clone();
+            let chunk = if !scripts::contains_scripts(&chunk) {
+                chunk
+            } else if self.config.scripts.enabled {
+                self.check_chapter_scripts(ch, &chunk)?;
+                self.package
+                    .borrow_mut()
+                    .scripted
+                    .push(links::encode(&path));
+                head = Some(head.unwrap_or_default() + scripts::TOGGLE);
+                chunk
+            } else {
+                warn!(
+                    "Removing scripts from \"{}\", set output.epub.scripts.enabled to keep them",
+                    ch
+                );
+                scripts::strip(&chunk)
+            };
+
+            let rendered = {
+                let data = ChapterPage {
+                    title: title.clone(),
+                    content: chunk,
+                    stylesheet: stylesheet_href(&ch.path),
+                    stylesheets: stylesheets.clone(),
+                    night_stylesheet: match self.config.color_scheme {
+                        ColorScheme::Auto => Some(href_from_chapter(&ch.path, NIGHT_STYLESHEET)),
+                        _ => None,
+                    },
+                    head,
+                    style: style.clone(),
+                    matter: matter.epub_type(),
+                    section_type: semantic.map(|semantic| semantic.epub_type),
+                    section_role: semantic.map(|semantic| semantic.role),
+                };
+
+                self.hbs
+                    .render("index", &data)
+                    .sync()
+                    .with_context(|_| format!("Unable to render \"{}\"", ch))?
+            };
+            let info = ChapterInfo {
+                chapter: ch,
+                path: &path,
+                part: i,
+            };
+            let rendered = self
+                .postprocessors
+                .apply(&info, rendered)
+                .with_context(|_| format!("Unable to post-process \"{}\"", ch))?;
+
+            let source = format!("chapter \"{}\" ({})", ch.name, ch.path.display());
+            self.claim_path(&path, &source)?;
+            let data = Cursor::new(rendered.into_bytes());
+            contents.push(EpubContent::new(links::encode(&path), data));
+            files.push(path);
+        }
+
+        // only the first part of a chapter gets any toc entries
+        let mut content = contents.remove(0);
+
+        // chapters without a title don't get an entry in the toc
+        if self.in_toc(ch, &front_matter) {
+            content = content.title(title);
+        }
+        if let Some(reference) = semantic.and_then(|semantic| semantic.reference_type()) {
+            content = content.reftype(reference);
+        }
 
         let level = ch.number.as_ref().map(|n| n.len() as i32 - 1).unwrap_or(0);
         content = content.level(level);
@@ -98,23 +827,546 @@ impl<'a> Generator<'a> {
         // and the second pass actually adds the sub-items to the book.
         for sub_item in &ch.sub_items {
             if let BookItem::Chapter(ref sub_ch) = *sub_item {
-                let child_path = sub_ch.path.with_extension("html").display().to_string();
-                content = content.child(TocElement::new(child_path, format!("{}", sub_ch)));
+                let sub_front_matter = self.chapter_front_matter(sub_ch);
+                if !self.in_toc(sub_ch, &sub_front_matter) {
+                    continue;
+                }
+
+                let child_path = sub_ch.path.with_extension("html");
+                let child_path = links::encode(&collisions::normalize(&child_path));
+                let child_title = self.chapter_title(sub_ch, &sub_front_matter);
+                content = content.child(TocElement::new(child_path, child_title));
             }
         }
 
         self.builder.add_content(content).sync()?;
+        for rest in contents {
+            self.builder.add_content(rest).sync()?;
+        }
+
+        self.report.spine.extend(files.iter().cloned());
+        self.report.chapters.push(ChapterReport {
+            name: ch.name.clone(),
+            source: ch.path.clone(),
+            files,
+            words,
+            reading_time: stats::reading_time(words, self.config.words_per_minute),
+        });
+
+        self.progress
+            .chapter_rendered(&ch.name, self.chapters.len());
 
         // second pass to actually add the sub-chapters
         for sub_item in &ch.sub_items {
             if let BookItem::Chapter(ref sub_ch) = *sub_item {
-                self.add_chapter(sub_ch)?;
+                self.add_chapter(sub_ch, matter, parsed)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Add the notes page, containing every footnote in the book, to the end
+    /// of the book.
+    fn add_notes_page(&mut self) -> Result<(), Error> {
+        debug!("Rendering the notes page");
+
+        let title = self.strings.get("notes").to_string();
+        let notes: Vec<Note> = self.endnotes.iter().map(|(note, _)| note.clone()).collect();
+        let content = {
+            let endnotes = &self.endnotes;
+            let section = endnotes::section(&notes, None, &self.strings, |note| {
+                endnotes
+                    .iter()
+                    .find(|&(n, _)| n.number == note.number)
+                    .and_then(|(_, path)| path.clone())
+            });
+            format!("<h1>{}</h1>\n{}", opf::escape(&title), section)
+        };
+
+        self.add_generated_page(
+            NOTES_PAGE,
+            title,
+            content,
+            "endnotes",
+            "doc-endnotes",
+            Some(ReferenceType::Notes),
+        )
+    }
+
+    /// Add the references page, listing every entry cited from the
+    /// bibliography, to the end of the book.
+    fn add_references_page(&mut self) -> Result<(), Error> {
+        debug!("Rendering the references page");
+
+        let title = self.strings.get("references").to_string();
+        let content = match self.bibliography {
+            Some(ref bibliography) => format!(
+                "<h1>{}</h1>\n{}",
+                opf::escape(&title),
+                bibliography.references_list()
+            ),
+            None => return Ok(()),
+        };
+
+        self.add_generated_page(
+            REFERENCES_PAGE,
+            title,
+            content,
+            "bibliography",
+            "doc-bibliography",
+            Some(ReferenceType::Bibliography),
+        )
+    }
+
+    /// Render one of the pages generated at the end of the book (the notes,
+    /// references and credits pages) and add it to the spine.
+    fn add_generated_page(
+        &mut self,
+        path: &str,
+        title: String,
+        content: String,
+        section_type: &'static str,
+        role: &'static str,
+        reftype: Option<ReferenceType>,
+    ) -> Result<(), Error> {
+        let rendered = {
+            let data = ChapterPage {
+                title: title.clone(),
+                content,
+                stylesheet: stylesheet_href(Path::new(path)),
+                stylesheets: Vec::new(),
+                night_stylesheet: match self.config.color_scheme {
+                    ColorScheme::Auto => Some(String::from(NIGHT_STYLESHEET)),
+                    _ => None,
+                },
+                head: None,
+                style: None,
+                matter: Matter::Back.epub_type(),
+                section_type: Some(section_type),
+                section_role: Some(role),
+            };
+
+            self.hbs
+                .render("index", &data)
+                .sync()
+                .with_context(|_| format!("Unable to render the {} page", section_type))?
+        };
+
+        self.claim_path(path, &format!("the {} page", section_type))?;
+        let mut content = EpubContent::new(path, Cursor::new(rendered.into_bytes())).title(title);
+        if let Some(reftype) = reftype {
+            content = content.reftype(reftype);
+        }
+        self.builder.add_content(content).sync()?;
+        self.report.spine.push(String::from(path));
+
+        Ok(())
+    }
+
+    /// Record the license and attribution of every packaged asset which has
+    /// them.
+    fn attach_credits(&mut self) -> Result<(), Error> {
+        let mut unused: Vec<&String> = self.config.credits.keys().collect();
+
+        for asset in &mut self.report.assets {
+            let sidecar = match asset.source {
+                Some(ref source) => credits::load_sidecar(source)?,
+                None => None,
+            };
+            let configured = self.config.credits.iter().find(|&(path, _)| {
+                Path::new(path.trim_start_matches("./")) == asset.path.as_path()
+            });
+            if let Some((path, _)) = configured {
+                unused.retain(|&key| key != path);
+            }
+
+            asset.credit = sidecar.or_else(|| configured.map(|(_, credit)| credit.clone()));
+        }
+
+        for path in unused {
+            warn!(
+                "output.epub.credits has an entry for \"{}\", but it isn't packaged in the book",
+                path
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Add the credits page, listing the license and attribution of every
+    /// credited asset, to the end of the book.
+    fn add_credits_page(&mut self) -> Result<(), Error> {
+        let content = {
+            let credited: Vec<_> = self
+                .report
+                .assets
+                .iter()
+                .filter_map(|asset| {
+                    asset
+                        .credit
+                        .as_ref()
+                        .map(|credit| (asset.path.as_path(), credit))
+                })
+                .collect();
+            if credited.is_empty() {
+                return Ok(());
+            }
+
+            credits::render(&credited, &self.strings)
+        };
+        debug!("Rendering the credits page");
+
+        let title = self.strings.get("credits").to_string();
+        self.add_generated_page(CREDITS_PAGE, title, content, "credits", "doc-credits", None)
+    }
+
+    /// Embed the extra stylesheets a chapter asks for in its front matter,
+    /// returning the `href` of each one relative to the chapter.
+    fn embed_chapter_stylesheets(
+        &mut self,
+        ch: &Chapter,
+        front_matter: &FrontMatter,
+    ) -> Result<Vec<String>, Error> {
+        if front_matter.epub_css.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let src_dir = self.src_dir()?;
+        let mut hrefs = Vec::new();
+
+        for css in &front_matter.epub_css {
+            let asset = resources::chapter_stylesheet(&src_dir, ch, css)?;
+            self.load_asset(&asset)
+                .with_context(|_| format!("Couldn't load {}", asset.location_on_disk.display()))?;
+            hrefs.push(links::encode(&links::nfc(&css.replace('\\', "/"))));
+        }
+
+        Ok(hrefs)
+    }
+
+    /// Warn about lines of code which are too long to read comfortably on a
+    /// small screen.
+    fn check_code_lines(&self, ch: &Chapter, long_lines: &[(usize, usize)]) {
+        let max = self.config.markdown.max_code_line_length;
+
+        for &(line, length) in long_lines {
+            warn!(
+                "Line {} of a code block in \"{}\" is {} characters long (the maximum is {})",
+                line, ch, length, max
+            );
+        }
+    }
+
+    /// Report images without alt text, and links to local files or chapters
+    /// which don't exist.
+    fn check_chapter_content(&mut self, ch: &Chapter, events: &[Event]) -> Result<(), Error> {
+        let images = events
+            .iter()
+            .filter(|event| matches!(**event, Event::Start(Tag::Image(..))))
+            .count();
+        let without_alt_text = diagnostics::images_without_alt_text(events);
+        self.report.accessibility.images += images;
+        self.report.accessibility.images_without_alt_text += without_alt_text.len();
+
+        for dest in without_alt_text {
+            self.diagnose(
+                Category::MissingAltText,
+                format!("An image in \"{}\" has no alt text ({})", ch, dest),
+            );
+        }
+
+        let local_links = diagnostics::local_links(events);
+        if local_links.is_empty() {
+            return Ok(());
+        }
+
+        let chapter_dir = self
+            .src_dir()?
+            .join(ch.path.parent().unwrap_or_else(|| Path::new("")));
+        for link in local_links {
+            if links::classify(&link) == LinkKind::Chapter {
+                if links::resolve_chapter(&ch.path, &link, &self.chapters).is_none() {
+                    self.report.accessibility.broken_links += 1;
+                    self.diagnose(
+                        Category::BrokenLink,
+                        format!(
+                            "\"{}\" links to {}, which isn't a chapter in SUMMARY.md",
+                            ch, link
+                        ),
+                    );
+                }
+                continue;
+            }
+
+            let path = links::without_fragment(&link);
+            let target = chapter_dir.join(links::decode(path));
+            if !target.exists() && !target.with_extension("md").exists() {
+                self.report.accessibility.broken_links += 1;
+                self.diagnose(
+                    Category::BrokenLink,
+                    format!("\"{}\" links to {}, which doesn't exist", ch, path),
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Report a problem with the book, as a warning or an error depending on
+    /// the book's diagnostics policy.
+    fn diagnose(&mut self, category: Category, message: String) {
+        let level = category.level(&self.config);
+        match level {
+            Level::Allow => {
+                debug!("{} [{}]", message, category);
+                return;
+            }
+            Level::Warn => warn!("{} [{}]", message, category),
+            Level::Error => error!("{} [{}]", message, category),
+        }
+
+        self.report.diagnostics.push(Diagnostic {
+            category,
+            level,
+            message,
+        });
+    }
+
+    /// Make sure every script a chapter uses is in the allowlist (and will
+    /// therefore be bundled into the book).
+    fn check_chapter_scripts(&self, ch: &Chapter, html: &str) -> Result<(), Error> {
+        let src_dir = self.src_dir()?;
+        let chapter_dir = ch.path.parent().unwrap_or_else(|| Path::new(""));
+
+        for src in scripts::sources(html) {
+            if links::is_external(&src) {
+                return Err(failure::err_msg(format!(
+                    "\"{}\" uses a remote script, which isn't allowed in an EPUB ({})",
+                    ch, src
+                )));
+            }
+
+            let location = src_dir.join(chapter_dir).join(links::decode(&src));
+            let allowed = location.canonicalize().ok().is_some_and(|location| {
+                self.config.scripts.allow.iter().any(|allowed| {
+                    src_dir.join(allowed).canonicalize().ok() == Some(location.clone())
+                })
+            });
+
+            if !allowed {
+                return Err(failure::err_msg(format!(
+                    "\"{}\" uses a script which isn't in output.epub.scripts.allow, {}",
+                    ch,
+                    location.display()
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Bundle every allowlisted script into the book.
+    fn embed_scripts(&mut self) -> Result<(), Error> {
+        if !self.config.scripts.enabled {
+            return Ok(());
+        }
+
+        debug!("Embedding scripts");
+        let src_dir = self.src_dir()?;
+
+        for script in self.config.scripts.allow.clone() {
+            let location = src_dir.join(&script);
+            let asset = Asset::new(links::nfc_path(&script), &location);
+            self.load_asset(&asset)
+                .with_context(|_| format!("Couldn't load the script, {}", location.display()))?;
+        }
+
+        Ok(())
+    }
+
+    fn src_dir(&self) -> Result<PathBuf, Error> {
+        resources::src_dir(self.ctx)
+    }
+
+    /// Record every print page which starts in this document.
+    fn add_page_targets(&mut self, path: &str, html: &str) -> Result<(), Error> {
+        let mut package = self.package.borrow_mut();
+        let pages = &mut package.page_list;
+
+        for label in page_list::labels(html) {
+            if pages.iter().any(|page| page.label == label) {
+                return Err(failure::err_msg(format!(
+                    "Page {} is marked more than once (last seen in {})",
+                    label, path
+                )));
             }
+
+            let href = format!("{}#page-{}", links::encode(path), label);
+            pages.push(PageTarget { label, href });
         }
 
         Ok(())
     }
 
+    /// The chapter's front matter, which was split off before it was parsed.
+    fn chapter_front_matter(&self, ch: &Chapter) -> FrontMatter {
+        self.front_matter.get(&ch.path).cloned().unwrap_or_default()
+    }
+
+    /// The title used for a chapter's table of contents entry and `<title>`,
+    /// preferring a custom title from the front matter or `{{#title}}`
+    /// over the name in `SUMMARY.md`.
+    fn chapter_title(&self, ch: &Chapter, front_matter: &FrontMatter) -> String {
+        match front_matter.title {
+            Some(ref title) => numbering::custom_title(ch, title, self.config.section_numbers),
+            None => numbering::title(ch, self.config.section_numbers),
+        }
+    }
+
+    /// What kind of chapter this is, according to its front matter or
+    /// `output.epub.chapter-types`.
+    fn chapter_semantic(
+        &self,
+        ch: &Chapter,
+        front_matter: &FrontMatter,
+    ) -> Result<Option<Semantic>, Error> {
+        let epub_type = front_matter.epub_type.as_ref().or_else(|| {
+            self.config
+                .chapter_types
+                .iter()
+                .find(|&(path, _)| is_chapter(path, ch))
+                .map(|(_, epub_type)| epub_type)
+        });
+
+        match epub_type {
+            Some(epub_type) => match semantics::lookup(epub_type) {
+                Some(semantic) => Ok(Some(semantic)),
+                None => Err(failure::err_msg(format!(
+                    "\"{}\" has an unknown epub:type, \"{}\" (expected one of {})",
+                    ch,
+                    epub_type,
+                    semantics::known_types().join(", ")
+                ))),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Should this chapter get an entry in the table of contents?
+    fn in_toc(&self, ch: &Chapter, front_matter: &FrontMatter) -> bool {
+        let depth = ch.number.as_ref().map(|n| n.len()).unwrap_or(1);
+        let too_deep = self.config.toc_depth.is_some_and(|max| depth > max);
+
+        front_matter.epub_toc && !too_deep
+    }
+
+    /// Add the cover image and, if requested, a thumbnail of it for library
+    /// software and older reading systems which ignore the EPUB 3
+    /// `cover-image` property.
+    fn embed_cover(&mut self) -> Result<(), Error> {
+        let cover = match self.config.cover {
+            Some(ref cover) => cover.clone(),
+            None => return Ok(()),
+        };
+        debug!("Embedding the cover");
+
+        let location = self.ctx.root.join(&cover.image);
+        let filename = match location.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) => format!("cover.{}", ext.to_lowercase()),
+            None => {
+                return Err(failure::err_msg(format!(
+                    "The cover image needs a file extension, {}",
+                    location.display()
+                )))
+            }
+        };
+        let asset = Asset::new(filename.as_str(), &location);
+        let mimetype = asset.mimetype.to_string();
+        let content = fs::read(&location)
+            .with_context(|_| format!("Unable to read the cover image, {}", location.display()))?;
+
+        self.claim_path(&filename, "the cover image")?;
+        self.builder
+            .add_cover_image(filename.as_str(), content.as_slice(), mimetype.as_str())
+            .sync()?;
+        self.report.assets.push(AssetReport::new(
+            Some(&location),
+            filename.as_str(),
+            mimetype.as_str(),
+            content.len() as u64,
+        ));
+
+        let thumbnail = match cover.thumbnail_width {
+            Some(width) => self.embed_cover_thumbnail(&asset, content, width)?,
+            None => None,
+        };
+
+        self.package.borrow_mut().cover_meta =
+            Some(thumbnail.clone().unwrap_or_else(|| filename.clone()));
+        self.report.cover = Some(CoverReport {
+            source: location,
+            path: PathBuf::from(filename),
+            thumbnail: thumbnail.map(PathBuf::from),
+        });
+
+        Ok(())
+    }
+
+    /// Scale the cover down to `width` pixels wide, returning the
+    /// thumbnail's path.
+    fn embed_cover_thumbnail(
+        &mut self,
+        cover: &Asset,
+        content: Vec<u8>,
+        width: u32,
+    ) -> Result<Option<String>, Error> {
+        let mimetype = cover.mimetype.to_string();
+        let extension = match mimetype.as_str() {
+            "image/png" => "png",
+            "image/jpeg" => "jpg",
+            _ => {
+                warn!(
+                    "Only PNG and JPEG covers can have a thumbnail, not {}",
+                    cover.location_on_disk.display()
+                );
+                return Ok(None);
+            }
+        };
+        let filename = format!("cover-thumbnail.{}", extension);
+
+        let resize = ResizeImages {
+            max_width: Some(width),
+            max_height: None,
+        };
+        let data = AssetData {
+            filename: PathBuf::from(&filename),
+            mimetype: mimetype.clone(),
+            content,
+        };
+        let thumbnail = resize
+            .transform(cover, data)
+            .context("Unable to generate the cover thumbnail")?;
+
+        self.claim_path(&filename, "the cover thumbnail")?;
+        let size = thumbnail.content.len() as u64;
+        self.builder
+            .add_resource(
+                filename.as_str(),
+                Cursor::new(thumbnail.content),
+                mimetype.as_str(),
+            )
+            .sync()?;
+        self.report.assets.push(AssetReport::new(
+            Some(&cover.location_on_disk),
+            filename.as_str(),
+            mimetype.as_str(),
+            size,
+        ));
+
+        Ok(Some(filename))
+    }
+
     /// Generate the stylesheet and add it to the document.
     fn embed_stylesheets(&mut self) -> Result<(), Error> {
         debug!("Embedding stylesheets");
@@ -122,48 +1374,363 @@ impl<'a> Generator<'a> {
         let stylesheet = self
             .generate_stylesheet()
             .context("Unable to generate stylesheet")?;
+        self.claim_path("stylesheet.css", "the stylesheet")?;
         self.builder.stylesheet(stylesheet.as_slice()).sync()?;
 
+        let size = stylesheet.len() as u64;
+        self.report.assets.push(AssetReport::new(
+            None::<PathBuf>,
+            "stylesheet.css",
+            "text/css",
+            size,
+        ));
+
+        if self.config.color_scheme != ColorScheme::Light {
+            let night = self
+                .night_stylesheet()
+                .context("Unable to load the night mode stylesheet")?;
+            self.claim_path(NIGHT_STYLESHEET, "the night mode stylesheet")?;
+            let size = night.len() as u64;
+            self.builder
+                .add_resource(NIGHT_STYLESHEET, Cursor::new(night), "text/css")
+                .sync()?;
+
+            self.report.assets.push(AssetReport::new(
+                None::<PathBuf>,
+                NIGHT_STYLESHEET,
+                "text/css",
+                size,
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn night_stylesheet(&self) -> Result<Vec<u8>, Error> {
+        match self.config.night_css {
+            Some(ref night_css) => {
+                let location = self.ctx.root.join(night_css);
+                let night = fs::read(&location)
+                    .with_context(|_| format!("Unable to open {}", location.display()))?;
+                Ok(night)
+            }
+            None => Ok(self.theme.night_stylesheet.clone().into_bytes()),
+        }
+    }
+
+    fn embed_fonts(&mut self) -> Result<(), Error> {
+        debug!("Embedding fonts");
+
+        let fonts: Vec<PathBuf> = self
+            .config
+            .fonts
+            .iter()
+            .chain(&self.theme.fonts)
+            .cloned()
+            .collect();
+
+        for font in fonts {
+            let location = self.ctx.root.join(&font);
+            let mimetype = fonts::mimetype(&location).ok_or_else(|| {
+                failure::err_msg(format!("Unknown font format, {}", location.display()))
+            })?;
+            let filename = match location.file_name() {
+                Some(name) => Path::new("fonts").join(name),
+                None => {
+                    return Err(failure::err_msg(format!(
+                        "Fonts must be files, {}",
+                        location.display()
+                    )))
+                }
+            };
+
+            let source = location.display().to_string();
+            if !self.claim_path(&collisions::normalize(&filename), &source)? {
+                continue;
+            }
+
+            debug!("Embedding {}", filename.display());
+            let content = File::open(&location)
+                .with_context(|_| format!("Unable to open {}", location.display()))?;
+            let size = content.metadata()?.len();
+            let href = links::encode(&collisions::normalize(&filename));
+            self.builder.add_resource(href, content, mimetype).sync()?;
+
+            let report = AssetReport::new(Some(&location), filename, mimetype, size);
+            self.report.assets.push(report);
+        }
+
         Ok(())
     }
 
     fn additional_assets(&mut self) -> Result<(), Error> {
         debug!("Embedding additional assets");
+        let span = info_span!("collect assets");
+        let _enter = span.enter();
 
-        let assets = resources::find(self.ctx)
-            .context("Inspecting the book for additional assets failed")?;
+        // in reading order, regardless of where the spine put each chapter
+        let mut assets = Vec::new();
+        for item in self.ctx.book.iter() {
+            if let BookItem::Chapter(ref ch) = *item {
+                if let Some(found) = self.chapter_assets.remove(&ch.path) {
+                    assets.extend(found);
+                }
+            }
+        }
+        for asset in mem::take(&mut self.extra_assets) {
+            if !asset.is_contained() {
+                return Err(failure::err_msg(format!(
+                    "Extra assets must be relative to the book, {}",
+                    asset.filename.display()
+                )));
+            }
+            assets.push(asset);
+        }
+        let total = assets.len();
 
         for asset in assets {
             debug!("Embedding {}", asset.filename.display());
-            self.load_asset(&asset)
-                .with_context(|_| format!("Couldn't load {}", asset.filename.display()))?;
+            let mimetype = asset.mimetype.to_string();
+            if !diagnostics::is_core_media_type(&mimetype) {
+                self.diagnose(
+                    Category::NonCoreMediaType,
+                    format!(
+                        "{} is a {}, which reading systems don't have to support",
+                        asset.filename.display(),
+                        mimetype
+                    ),
+                );
+            }
+            match File::open(&asset.location_on_disk) {
+                Err(e) if self.config.lenient => self.add_placeholder(&asset, e.to_string())?,
+                _ => self
+                    .load_asset(&asset)
+                    .with_context(|_| format!("Couldn't load {}", asset.filename.display()))?,
+            }
+            self.progress
+                .asset_embedded(&asset.filename.display().to_string(), total);
         }
 
         Ok(())
     }
 
+    /// Copy the book's original sources (`book.toml`, `SUMMARY.md` and every
+    /// chapter) into the book, along with a record of how it was built.
+    fn embed_sources(&mut self) -> Result<(), Error> {
+        if !self.config.embed_sources {
+            return Ok(());
+        }
+
+        debug!("Embedding the book's sources");
+        let src_dir = self.src_dir()?;
+
+        let mut sources = vec![
+            (self.ctx.root.join("book.toml"), PathBuf::from("book.toml")),
+            (src_dir.join("SUMMARY.md"), PathBuf::from("src/SUMMARY.md")),
+        ];
+        for item in self.ctx.book.iter() {
+            if let BookItem::Chapter(ref ch) = *item {
+                sources.push((src_dir.join(&ch.path), Path::new("src").join(&ch.path)));
+            }
+        }
+
+        for (location, relative) in sources {
+            let filename = Path::new(archival::SOURCES_DIR).join(relative);
+            let mimetype = if location.extension().is_some_and(|ext| ext == "toml") {
+                "application/toml"
+            } else {
+                "text/markdown"
+            };
+
+            self.embed_unchanged(&location, &filename, mimetype)
+                .with_context(|_| format!("Couldn't embed {}", location.display()))?;
+        }
+
+        let info = BuildInfo::new(self.ctx, Utc::now());
+        let info = serde_json::to_vec_pretty(&info)?;
+        let path = format!("{}/build-info.json", archival::SOURCES_DIR);
+        self.claim_path(&path, "the build info")?;
+        let size = info.len() as u64;
+        self.builder
+            .add_resource(path.as_str(), Cursor::new(info), "application/json")
+            .sync()?;
+        self.report.assets.push(AssetReport::new(
+            None::<PathBuf>,
+            path,
+            "application/json",
+            size,
+        ));
+
+        Ok(())
+    }
+
+    /// Copy a file into the book as-is, without running it through the asset
+    /// transformers.
+    fn embed_unchanged(
+        &mut self,
+        location: &Path,
+        filename: &Path,
+        mimetype: &str,
+    ) -> Result<(), Error> {
+        let path = collisions::normalize(filename);
+        let source = location.display().to_string();
+        if !self.claim_path(&path, &source)? {
+            return Ok(());
+        }
+
+        let content = File::open(location).context("Unable to open the file")?;
+        let size = content.metadata()?.len();
+        self.builder
+            .add_resource(links::encode(&path), content, mimetype)
+            .sync()?;
+
+        let report = AssetReport::new(Some(location), filename, mimetype, size);
+        self.report.assets.push(report);
+
+        Ok(())
+    }
+
+    /// Add a placeholder image in place of an asset which couldn't be read,
+    /// so the book can still be built.
+    fn add_placeholder(&mut self, asset: &Asset, reason: String) -> Result<(), Error> {
+        let path = collisions::normalize(&asset.filename);
+        let source = asset.location_on_disk.display().to_string();
+        if !self.claim_path(&path, &source)? {
+            return Ok(());
+        }
+
+        self.diagnose(
+            Category::MissingAsset,
+            format!("Replaced {} with a placeholder ({})", path, reason),
+        );
+
+        let (content, mimetype) = match self.config.placeholder {
+            Some(ref placeholder) => {
+                let location = self.ctx.root.join(placeholder);
+                let content = fs::read(&location).with_context(|_| {
+                    format!("Unable to read the placeholder, {}", location.display())
+                })?;
+                let mimetype = Asset::new(&asset.filename, &location).mimetype;
+                (content, mimetype.to_string())
+            }
+            None => (DEFAULT_PLACEHOLDER.to_vec(), String::from("image/svg+xml")),
+        };
+
+        let size = content.len() as u64;
+        self.builder
+            .add_resource(links::encode(&path), Cursor::new(content), mimetype.clone())
+            .sync()?;
+
+        self.report.assets.push(AssetReport::new(
+            None::<PathBuf>,
+            &asset.filename,
+            mimetype,
+            size,
+        ));
+        self.report.failures.push(AssetFailure {
+            path: asset.filename.clone(),
+            source: asset.location_on_disk.clone(),
+            reason,
+        });
+
+        Ok(())
+    }
+
     fn load_asset(&mut self, asset: &Asset) -> Result<(), Error> {
-        let content = File::open(&asset.location_on_disk).context("Unable to open asset")?;
+        let path = collisions::normalize(&asset.filename);
+        let source = asset.location_on_disk.display().to_string();
+        if !self.claim_path(&path, &source)? {
+            trace!("{} has already been embedded", path);
+            return Ok(());
+        }
+
+        let mut content = File::open(&asset.location_on_disk).context("Unable to open asset")?;
+
+        if self.transformers.is_empty() {
+            // stream the file straight into the book
+            let size = content.metadata()?.len();
+            let mt = asset.mimetype.to_string();
+
+            self.builder
+                .add_resource(links::encode(&path), content, mt.clone())
+                .sync()?;
+
+            let report = AssetReport::new(Some(&asset.location_on_disk), &asset.filename, mt, size);
+            self.report.assets.push(report);
+
+            return Ok(());
+        }
+
+        let mut buffer = Vec::new();
+        content
+            .read_to_end(&mut buffer)
+            .context("Unable to read asset")?;
+        let data = AssetData {
+            filename: asset.filename.clone(),
+            mimetype: asset.mimetype.to_string(),
+            content: buffer,
+        };
+        let data = self.transformers.apply(asset, data)?;
 
-        let mt = asset.mimetype.to_string();
+        let transformed_path = collisions::normalize(&data.filename);
+        if transformed_path != path {
+            self.claim_path(&transformed_path, &source)?;
+        }
 
+        let size = data.content.len() as u64;
         self.builder
-            .add_resource(&asset.filename, content, mt)
+            .add_resource(
+                links::encode(&transformed_path),
+                Cursor::new(data.content),
+                data.mimetype.clone(),
+            )
             .sync()?;
 
+        let report = AssetReport::new(
+            Some(&asset.location_on_disk),
+            data.filename,
+            data.mimetype,
+            size,
+        );
+        self.report.assets.push(report);
+
         Ok(())
     }
 
+    /// Record that a file will be written to `path`, returning `false` if
+    /// this source has already been written there.
+    fn claim_path(&mut self, path: &str, source: &str) -> Result<bool, Error> {
+        self.paths.claim(path, source).map_err(Error::from)
+    }
+
     /// Concatenate all provided stylesheets into one long stylesheet.
     fn generate_stylesheet(&self) -> Result<Vec<u8>, Error> {
         let mut stylesheet = Vec::new();
 
+        // imports need to come before every other rule
+        match self.config.color_scheme {
+            ColorScheme::Auto => stylesheet.extend(
+                format!(
+                    "@import url(\"{}\") (prefers-color-scheme: dark);\n\n",
+                    NIGHT_STYLESHEET
+                )
+                .as_bytes(),
+            ),
+            ColorScheme::Dark => {
+                stylesheet.extend(format!("@import url(\"{}\");\n\n", NIGHT_STYLESHEET).as_bytes())
+            }
+            ColorScheme::Light => {}
+        }
+
+        let tokens = style::root_rule(&self.config.style).context("Invalid output.epub.style")?;
+        stylesheet.extend(tokens.as_bytes());
+
         if self.config.use_default_css {
-            stylesheet.extend(DEFAULT_CSS.as_bytes());
+            stylesheet.extend(self.theme.stylesheet.as_bytes());
         }
 
         for additional_css in &self.config.additional_css {
-            let mut f = File::open(&additional_css)
+            let mut f = File::open(additional_css)
                 .with_context(|_| format!("Unable to open {}", additional_css.display()))?;
             f.read_to_end(&mut stylesheet)
                 .context("Error reading stylesheet")?;
@@ -172,3 +1739,208 @@ impl<'a> Generator<'a> {
         Ok(stylesheet)
     }
 }
+
+/// Which part of the book a page belongs to.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Matter {
+    Front,
+    Body,
+    Back,
+}
+
+impl From<Group> for Matter {
+    fn from(group: Group) -> Matter {
+        match group {
+            Group::Front => Matter::Front,
+            Group::Body => Matter::Body,
+            Group::Back => Matter::Back,
+        }
+    }
+}
+
+impl Matter {
+    /// The `epub:type` used to annotate pages in this part of the book.
+    fn epub_type(&self) -> &'static str {
+        match *self {
+            Matter::Front => "frontmatter",
+            Matter::Body => "bodymatter",
+            Matter::Back => "backmatter",
+        }
+    }
+}
+
+/// The information made available to the chapter template.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct ChapterPage {
+    title: String,
+    content: String,
+    stylesheet: String,
+    stylesheets: Vec<String>,
+    /// The night mode stylesheet, offered to reading systems which support
+    /// alternate style tags.
+    night_stylesheet: Option<String>,
+    head: Option<String>,
+    /// Design tokens from the chapter's front matter, for the `<body>`'s
+    /// `style` attribute.
+    style: Option<String>,
+    matter: &'static str,
+    /// The chapter's structural semantics, used to wrap it in a
+    /// `<section>`.
+    section_type: Option<&'static str>,
+    section_role: Option<&'static str>,
+}
+
+/// The information made available to the title page template.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct TitlePage<'a> {
+    title: &'a str,
+    subtitle: Option<&'a str>,
+    authors: String,
+    edition: Option<&'a str>,
+    logo: Option<String>,
+}
+
+/// The information made available to the copyright page template.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct CopyrightPage<'a> {
+    title: &'a str,
+    year: Option<&'a str>,
+    holder: String,
+    license: Option<&'a str>,
+    edition: Option<&'a str>,
+    notice: Option<String>,
+    strings: BTreeMap<String, &'a str>,
+}
+
+/// The information made available to the about page template.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct AboutPage<'a> {
+    title: &'a str,
+    chapters: Vec<AboutChapter>,
+    total_words: usize,
+    total_minutes: usize,
+    /// The sentence summarising the book's length, in the book's language.
+    reading_time: String,
+    strings: BTreeMap<String, &'a str>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct AboutChapter {
+    name: String,
+    words: usize,
+    minutes: usize,
+}
+
+fn top_level_chapters(ctx: &RenderContext) -> Vec<&Chapter> {
+    ctx.book
+        .sections
+        .iter()
+        .filter_map(|item| match *item {
+            BookItem::Chapter(ref ch) => Some(ch),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Find the chunk of a chapter a note is referenced from.
+fn reference_chunk(chunks: &[String], note: &Note) -> Option<usize> {
+    let reference = format!("id=\"fnref-{}\"", note.number);
+    chunks.iter().position(|chunk| chunk.contains(&reference))
+}
+
+/// A page in `output.epub.spine` order.
+enum SpinePage<'a> {
+    /// One of the `GENERATED_PAGES`.
+    Generated(&'static str),
+    /// A top-level chapter, which brings its sub-chapters with it.
+    Chapter(&'a Chapter, Matter),
+}
+
+/// Look up an `output.epub.spine` entry.
+fn spine_page<'a>(
+    ctx: &'a RenderContext,
+    entry: &str,
+    matter: Matter,
+) -> Result<SpinePage<'a>, Error> {
+    if let Some(page) = GENERATED_PAGES.iter().find(|page| **page == entry) {
+        return Ok(SpinePage::Generated(page));
+    }
+
+    top_level_chapters(ctx)
+        .into_iter()
+        .find(|ch| is_chapter(entry, ch))
+        .map(|ch| SpinePage::Chapter(ch, matter))
+        .ok_or_else(|| {
+            failure::err_msg(format!(
+                "\"{}\" in output.epub.spine isn't a generated page or top-level chapter",
+                entry
+            ))
+        })
+}
+
+/// Add a chapter and its sub-chapters in the order they're rendered.
+fn reading_order<'a>(ch: &'a Chapter, chapters: &mut Vec<&'a Chapter>) {
+    chapters.push(ch);
+    for item in &ch.sub_items {
+        if let BookItem::Chapter(ref sub_ch) = *item {
+            reading_order(sub_ch, chapters);
+        }
+    }
+}
+
+/// Does an `output.epub.spine` entry refer to this chapter?
+fn is_chapter(entry: &str, ch: &Chapter) -> bool {
+    Path::new(entry.trim_start_matches("./")) == ch.path
+}
+
+/// Get the path to the stylesheet relative to a chapter.
+fn stylesheet_href(chapter_path: &Path) -> String {
+    href_from_chapter(chapter_path, "stylesheet.css")
+}
+
+/// Get the `href` a chapter uses to refer to a file at the root of the book.
+fn href_from_chapter(chapter_path: &Path, target: &str) -> String {
+    let depth = chapter_path
+        .parent()
+        .map(|parent| parent.components().count())
+        .unwrap_or(0);
+
+    format!("{}{}", "../".repeat(depth), target)
+}
+
+/// Read a user-provided template (relative to the book's root directory),
+/// falling back to the built-in default.
+fn load_template(
+    ctx: &RenderContext,
+    custom: Option<&PathBuf>,
+    default: &str,
+) -> Result<String, Error> {
+    match custom {
+        Some(path) => {
+            let path = ctx.root.join(path);
+            let template = fs::read_to_string(&path)
+                .with_context(|_| format!("Unable to read the template at {}", path.display()))?;
+            Ok(template)
+        }
+        None => Ok(default.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stylesheet_is_relative_to_the_chapter() {
+        let inputs = vec![
+            ("chapter_1.md", "stylesheet.css"),
+            ("nested/chapter_2.md", "../stylesheet.css"),
+            ("deeply/nested/chapter_3.md", "../../stylesheet.css"),
+        ];
+
+        for (path, should_be) in inputs {
+            let got = stylesheet_href(Path::new(path));
+            assert_eq!(got, should_be);
+        }
+    }
+}
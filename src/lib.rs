@@ -1,38 +1,134 @@
 //! A `mdbook` backend for generating a book in the `EPUB` format.
 
+// `failure_derive` puts its impls inside a named constant, which newer
+// compilers warn about.
+#![allow(unknown_lints, non_local_definitions)]
+
+extern crate chrono;
 extern crate epub_builder;
 extern crate failure;
 #[macro_use]
 extern crate failure_derive;
-#[macro_use]
-extern crate log;
+extern crate glob;
+extern crate handlebars;
+extern crate image;
 extern crate mdbook;
 extern crate mime_guess;
+#[macro_use]
+extern crate percent_encoding;
 extern crate pulldown_cmark;
 extern crate semver;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
+extern crate serde_yaml;
+extern crate sha1;
+extern crate sha2;
+extern crate tempdir;
+extern crate toml;
+#[macro_use]
+extern crate tracing;
+extern crate unicode_normalization;
+extern crate zip;
 
-use failure::Error;
+use failure::{Error, ResultExt};
 use mdbook::config::Config as MdConfig;
 use mdbook::renderer::RenderContext;
 use semver::{Version, VersionReq};
-use std::fs::{create_dir_all, File};
+use std::fs::{self, create_dir_all, File};
+use std::io::BufWriter;
 use std::path::{Path, PathBuf};
 
+use utils::Discard;
+
+mod accessibility;
+mod archival;
+mod archive;
+mod bibliography;
+mod code_blocks;
+mod collisions;
 mod config;
+mod credits;
+mod definition_lists;
+mod diagnostics;
+mod endnotes;
+mod export;
+mod filters;
+mod fonts;
+mod front_matter;
 mod generator;
+mod links;
+mod markdown;
+mod numbering;
+mod opf;
+mod page_breaks;
+mod page_list;
+mod pipeline;
+mod postprocess;
+mod progress;
+mod report;
 mod resources;
+mod responsive;
+mod sanitize;
+mod scripts;
+mod semantics;
+mod sign;
+mod split;
+mod spool;
+mod stats;
+mod strings;
+mod style;
+mod tables;
+mod theme;
+mod transform;
+mod translations;
+mod typography;
 mod utils;
 
-pub use config::Config;
+pub use config::{Config, Level, MetadataFormat};
+pub use credits::Credit;
+pub use diagnostics::{Category, Diagnostic};
 pub use generator::Generator;
+pub use postprocess::{ChapterInfo, PostProcessor};
+pub use progress::{Phase, Progress};
+pub use report::{
+    AssetFailure, AssetReport, BuildReport, ChapterReport, CoverReport, RenderedBook,
+};
+pub use resources::{find as find_assets, Asset};
+pub use transform::{AssetData, AssetTransformer, MinifySvg, ResizeImages};
 
 /// The default stylesheet used to make the rendered document pretty.
 pub const DEFAULT_CSS: &str = include_str!("master.css");
 
+/// The image used in place of assets which couldn't be packaged.
+pub const DEFAULT_PLACEHOLDER: &[u8] = include_bytes!("placeholder.svg");
+
+/// The default stylesheet used in night mode.
+pub const DEFAULT_NIGHT_CSS: &str = include_str!("night.css");
+
+/// The default template each chapter is rendered into.
+pub const DEFAULT_TEMPLATE: &str = include_str!("index.hbs");
+
+/// The default template used when rendering the book's title page.
+pub const DEFAULT_TITLE_PAGE: &str = include_str!("titlepage.hbs");
+
+/// The default template used when rendering the copyright page.
+pub const DEFAULT_COPYRIGHT_PAGE: &str = include_str!("copyright.hbs");
+
+/// The default template used when rendering the "About this book" page.
+pub const DEFAULT_ABOUT_PAGE: &str = include_str!("about.hbs");
+
+/// The template used when exporting the book's metadata as an ONIX record.
+pub const DEFAULT_ONIX: &str = include_str!("onix.hbs");
+
+/// The template used when exporting the book's metadata as an OPDS catalog
+/// entry.
+pub const DEFAULT_OPDS: &str = include_str!("opds.hbs");
+
+/// The template used for the human-readable accessibility report.
+pub const DEFAULT_ACCESSIBILITY_SUMMARY: &str = include_str!("accessibility.hbs");
+
 /// The exact version of `mdbook` this crate is compiled against.
 pub const MDBOOK_VERSION: &str = env!("MDBOOK_VERSION");
 
@@ -46,6 +142,12 @@ struct IncompatibleMdbookVersion {
     got: String,
 }
 
+#[derive(Debug, Clone, PartialEq, Fail)]
+#[fail(display = "The book was built with errors: {:?}", errors)]
+struct DiagnosticErrors {
+    errors: Vec<String>,
+}
+
 /// Check that the version of `mdbook` we're called by is compatible with this
 /// backend.
 fn version_check(ctx: &RenderContext) -> Result<(), Error> {
@@ -64,14 +166,83 @@ fn version_check(ctx: &RenderContext) -> Result<(), Error> {
     }
 }
 
-/// Generate an `EPUB` version of the provided book.
+/// Generate an `EPUB` version of the provided book, failing if anything was
+/// reported as an error.
 pub fn generate(ctx: &RenderContext) -> Result<(), Error> {
+    for book in generate_with_progress(ctx, |_| {})? {
+        check_diagnostics(&book.report)?;
+    }
+
+    Ok(())
+}
+
+/// Generate an `EPUB` version of the provided book (and each of its
+/// translations), calling `progress` as each chapter and asset is added.
+///
+/// Unlike `generate()`, diagnostics which are errors don't fail the build, so
+/// the caller can show the reports first. Use `check_diagnostics()` on each
+/// report afterwards.
+pub fn generate_with_progress<F>(
+    ctx: &RenderContext,
+    mut progress: F,
+) -> Result<Vec<RenderedBook>, Error>
+where
+    F: FnMut(&Progress),
+{
     info!("Starting the EPUB generator");
     version_check(ctx)?;
 
+    let config = Config::from_render_context(ctx)?;
     let outfile = output_filename(&ctx.destination, &ctx.config);
+    let mut books = vec![render(ctx, &config, &outfile, &mut progress)?];
+
+    for (language, translation) in &config.translations {
+        info!("Generating the \"{}\" translation", language);
+        let translated = translations::load(ctx, language, translation)?;
+        let outfile = translations::filename(&outfile, language);
+        let config = Config {
+            language: Some(language.clone()),
+            ..config.clone()
+        };
+        books.push(render(&translated, &config, &outfile, &mut progress)?);
+    }
+
+    Ok(books)
+}
+
+/// Render a single book to `outfile`, along with any sidecar files.
+fn render<F>(
+    ctx: &RenderContext,
+    config: &Config,
+    outfile: &Path,
+    progress: &mut F,
+) -> Result<RenderedBook, Error>
+where
+    F: FnMut(&Progress),
+{
     trace!("Output File: {}", outfile.display());
 
+    let filtered;
+    let ctx = if config.filters.is_empty() {
+        ctx
+    } else {
+        filtered = filters::apply(ctx, &config.filters)?;
+        &filtered
+    };
+
+    if config.dry_run {
+        info!("Dry run, nothing will be written to disk");
+        let report = Generator::new(ctx, Discard::default())?
+            .with_progress(progress)
+            .generate()?;
+
+        return Ok(RenderedBook {
+            path: outfile.to_path_buf(),
+            dry_run: true,
+            report,
+        });
+    }
+
     if !ctx.destination.exists() {
         debug!(
             "Creating destination directory ({})",
@@ -80,10 +251,101 @@ pub fn generate(ctx: &RenderContext) -> Result<(), Error> {
         create_dir_all(&ctx.destination)?;
     }
 
-    let f = File::create(&outfile)?;
-    Generator::new(ctx)?.generate(f)?;
+    let f = File::create(outfile)?;
+    let report = Generator::new(ctx, BufWriter::new(f))?
+        .with_progress(progress)
+        .generate()?;
 
-    Ok(())
+    if config.build_manifest {
+        let manifest = manifest_filename(outfile);
+        debug!("Writing the build manifest to {}", manifest.display());
+
+        let f = File::create(&manifest)?;
+        serde_json::to_writer_pretty(f, &report).context("Unable to write the build manifest")?;
+    }
+
+    if let Some(format) = config.export_metadata {
+        let filename = metadata_filename(outfile, format);
+        debug!("Writing the {} record to {}", format, filename.display());
+
+        let identifier = report.identifier.as_deref().unwrap_or_default();
+        let record = export::render(ctx, config, format, identifier, outfile)?;
+        fs::write(&filename, record)
+            .with_context(|_| format!("Unable to write {}", filename.display()))?;
+    }
+
+    if config.accessibility_report {
+        let checks = accessibility::evaluate(config, &report.accessibility);
+        for (filename, contents) in [
+            (
+                accessibility_report_filename(outfile),
+                accessibility::render_json(ctx, &checks, &report)?,
+            ),
+            (
+                accessibility_summary_filename(outfile),
+                accessibility::render_summary(ctx, &checks)?,
+            ),
+        ] {
+            debug!("Writing the accessibility report to {}", filename.display());
+            fs::write(&filename, contents)
+                .with_context(|_| format!("Unable to write {}", filename.display()))?;
+        }
+    }
+
+    sign::run(&config.sign, outfile)?;
+
+    Ok(RenderedBook {
+        path: outfile.to_path_buf(),
+        dry_run: false,
+        report,
+    })
+}
+
+/// Fail the build if any of a report's diagnostics were errors.
+pub fn check_diagnostics(report: &BuildReport) -> Result<(), Error> {
+    let errors: Vec<String> = report
+        .diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.level == Level::Error)
+        .map(ToString::to_string)
+        .collect();
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(DiagnosticErrors { errors }.into())
+    }
+}
+
+/// Get the path the JSON build manifest for an EPUB is written to.
+pub fn manifest_filename(epub: &Path) -> PathBuf {
+    epub.with_extension("manifest.json")
+}
+
+/// Get the path the JSON accessibility report for an EPUB is written to.
+pub fn accessibility_report_filename(epub: &Path) -> PathBuf {
+    epub.with_extension("a11y.json")
+}
+
+/// Get the path the human-readable accessibility report for an EPUB is
+/// written to.
+pub fn accessibility_summary_filename(epub: &Path) -> PathBuf {
+    epub.with_extension("a11y.html")
+}
+
+/// Get the path the SHA-256 checksum for an EPUB is written to.
+pub fn checksum_filename(epub: &Path) -> PathBuf {
+    let mut filename = epub.as_os_str().to_owned();
+    filename.push(".sha256");
+    PathBuf::from(filename)
+}
+
+/// Get the path the exported metadata record for an EPUB is written to.
+pub fn metadata_filename(epub: &Path, format: MetadataFormat) -> PathBuf {
+    match format {
+        MetadataFormat::Onix => epub.with_extension("onix.xml"),
+        MetadataFormat::Opds => epub.with_extension("opds.xml"),
+    }
 }
 
 /// Calculate the output filename using the `mdbook` config.
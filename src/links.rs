@@ -0,0 +1,534 @@
+//! Helpers for the paths and URLs used to link documents together.
+//!
+//! Paths inside the EPUB are always NFC normalized, while anything which
+//! ends up in an `href` or `src` attribute (including the manifest in the
+//! package document) is percent-encoded.
+
+use percent_encoding::{percent_decode, utf8_percent_encode, DEFAULT_ENCODE_SET};
+use pulldown_cmark::{CowStr, Event, LinkType, Tag};
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use unicode_normalization::UnicodeNormalization;
+
+use collisions;
+use config::LinkUrls;
+use opf;
+use split;
+
+define_encode_set! {
+    /// Characters which need to be escaped in a relative URL's path. Unlike
+    /// the default set, this also escapes `%` so encoding is reversible.
+    pub HREF_ENCODE_SET = [DEFAULT_ENCODE_SET] | {'%'}
+}
+
+/// Percent-encode a relative path so it can be used in an `href`.
+pub fn encode(path: &str) -> String {
+    utf8_percent_encode(path, HREF_ENCODE_SET).to_string()
+}
+
+/// Decode a percent-encoded `href`.
+pub fn decode(href: &str) -> String {
+    percent_decode(href.as_bytes())
+        .decode_utf8_lossy()
+        .into_owned()
+}
+
+/// Normalize a string to Unicode Normalization Form C.
+pub fn nfc(s: &str) -> String {
+    s.nfc().collect()
+}
+
+/// Normalize a path to Unicode Normalization Form C.
+pub fn nfc_path(path: &Path) -> PathBuf {
+    PathBuf::from(nfc(&path.to_string_lossy()))
+}
+
+/// Does this link point to something outside the book (e.g. a website or
+/// `data:` URL)?
+pub fn is_external(link: &str) -> bool {
+    link.starts_with("//") || link.contains("://") || link.starts_with("data:")
+}
+
+/// What a link points to.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LinkKind {
+    /// Somewhere in the same chapter (`#section`).
+    Fragment,
+    /// A website or `data:` URL.
+    External,
+    /// Something handled outside the reading system, like `mailto:` or
+    /// `tel:`.
+    Scheme,
+    /// Another chapter's markdown file.
+    Chapter,
+    /// Any other local file.
+    File,
+}
+
+/// Figure out what kind of thing a link points to.
+pub fn classify(link: &str) -> LinkKind {
+    if link.starts_with('#') {
+        LinkKind::Fragment
+    } else if is_external(link) {
+        LinkKind::External
+    } else if has_scheme(link) {
+        LinkKind::Scheme
+    } else if without_fragment(link).to_lowercase().ends_with(".md") {
+        LinkKind::Chapter
+    } else {
+        LinkKind::File
+    }
+}
+
+/// Does the link start with a URI scheme? Single letters are ignored so
+/// Windows paths (`C:\...`) aren't mistaken for one.
+fn has_scheme(link: &str) -> bool {
+    let scheme = match link.find(':') {
+        Some(ix) if ix > 1 => &link[..ix],
+        _ => return false,
+    };
+
+    scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.')
+}
+
+/// The part of a link before any `#fragment`.
+pub fn without_fragment(link: &str) -> &str {
+    link.split('#').next().unwrap_or_default()
+}
+
+/// Find the chapter (as listed in `SUMMARY.md`) a link from another chapter
+/// refers to. Links are normally relative to the chapter they're in, but
+/// links relative to `SUMMARY.md` itself are accepted too.
+pub fn resolve_chapter(from: &Path, link: &str, chapters: &[PathBuf]) -> Option<PathBuf> {
+    let from = nfc_path(from);
+    let path = nfc(&decode(without_fragment(link)));
+    let candidates = if path.starts_with('/') {
+        vec![clean(Path::new(path.trim_start_matches('/')))]
+    } else {
+        let dir = from.parent().unwrap_or_else(|| Path::new(""));
+        vec![clean(&dir.join(&path)), clean(Path::new(&path))]
+    };
+
+    candidates
+        .into_iter()
+        .flatten()
+        .find(|candidate| chapters.contains(candidate))
+}
+
+/// The `href` a chapter uses to link to another chapter, keeping the
+/// fragment.
+pub fn chapter_href(from: &Path, link: &str, chapters: &[PathBuf]) -> Option<String> {
+    let target = resolve_chapter(from, link, chapters)?;
+    let depth = from
+        .parent()
+        .map(|parent| parent.components().count())
+        .unwrap_or(0);
+    let html = collisions::normalize(Path::new(&split::chunk_path(&target, 0)));
+    let fragment = link.find('#').map_or("", |ix| &link[ix..]);
+
+    Some(format!(
+        "{}{}{}",
+        "../".repeat(depth),
+        encode(&html),
+        fragment
+    ))
+}
+
+/// Resolve any `.` and `..` components, without leaving the `src/`
+/// directory.
+pub fn clean(path: &Path) -> Option<PathBuf> {
+    let mut cleaned = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => cleaned.push(part),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !cleaned.pop() {
+                    return None;
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+
+    Some(cleaned)
+}
+
+/// Is this a link to a local file which should be packaged into the book,
+/// according to the `package-links` allowlist?
+pub fn is_packaged(link: &str, extensions: &[String]) -> bool {
+    if classify(link) != LinkKind::File {
+        return false;
+    }
+
+    match Path::new(link).extension() {
+        Some(ext) => {
+            let ext = ext.to_string_lossy();
+            extensions
+                .iter()
+                .any(|allowed| allowed.trim_start_matches('.').eq_ignore_ascii_case(&ext))
+        }
+        None => false,
+    }
+}
+
+/// Make sure every local image (and every link to a packaged file) is
+/// referenced by its normalized, percent-encoded path.
+pub fn rewrite<'a, I>(events: I, package_links: &[String]) -> Vec<Event<'a>>
+where
+    I: IntoIterator<Item = Event<'a>>,
+{
+    events
+        .into_iter()
+        .map(|event| match event {
+            Event::Start(Tag::Image(kind, dest, title)) => {
+                Event::Start(Tag::Image(kind, normalize_href(dest), title))
+            }
+            Event::End(Tag::Image(kind, dest, title)) => {
+                Event::End(Tag::Image(kind, normalize_href(dest), title))
+            }
+            Event::Start(Tag::Link(kind, ref dest, ref title))
+                if is_packaged(dest, package_links) =>
+            {
+                Event::Start(Tag::Link(kind, normalize_href(dest.clone()), title.clone()))
+            }
+            Event::End(Tag::Link(kind, ref dest, ref title))
+                if is_packaged(dest, package_links) =>
+            {
+                Event::End(Tag::Link(kind, normalize_href(dest.clone()), title.clone()))
+            }
+            other => other,
+        })
+        .collect()
+}
+
+/// Does the chapter link to an anchor in another chapter? Those links may need
+/// fixing once every chapter has been split (see `split::link_across_chapters()`).
+pub fn links_into_chapters(events: &[Event]) -> bool {
+    events.iter().any(|event| match *event {
+        Event::Start(Tag::Link(_, ref dest, _)) => {
+            classify(dest) == LinkKind::Chapter && dest.contains('#')
+        }
+        _ => false,
+    })
+}
+
+/// Point links to other chapters at the page each one is rendered to. Links
+/// which don't match a chapter are left alone.
+pub fn rewrite_chapter_links<'a, I>(
+    events: I,
+    chapter: &Path,
+    chapters: &[PathBuf],
+) -> Vec<Event<'a>>
+where
+    I: IntoIterator<Item = Event<'a>>,
+{
+    let href = |dest: &CowStr<'a>| -> CowStr<'a> {
+        if classify(dest) != LinkKind::Chapter {
+            return dest.clone();
+        }
+
+        match chapter_href(chapter, dest, chapters) {
+            Some(href) => href.into(),
+            None => dest.clone(),
+        }
+    };
+
+    events
+        .into_iter()
+        .map(|event| match event {
+            Event::Start(Tag::Link(kind, ref dest, ref title)) => {
+                Event::Start(Tag::Link(kind, href(dest), title.clone()))
+            }
+            Event::End(Tag::Link(kind, ref dest, ref title)) => {
+                Event::End(Tag::Link(kind, href(dest), title.clone()))
+            }
+            other => other,
+        })
+        .collect()
+}
+
+/// Show where each link to a website goes, either in parentheses after the
+/// link or as a footnote. Links whose text is already the URL are left
+/// alone.
+pub fn annotate_external<'a, I>(events: I, mode: LinkUrls) -> Vec<Event<'a>>
+where
+    I: IntoIterator<Item = Event<'a>>,
+{
+    let events = events.into_iter();
+    if mode == LinkUrls::None {
+        return events.collect();
+    }
+
+    let mut out = Vec::new();
+    // the text of the external link we're currently in
+    let mut text: Option<String> = None;
+    // the footnote used for each URL, in order
+    let mut notes: Vec<String> = Vec::new();
+    let mut names: HashMap<String, String> = HashMap::new();
+
+    for event in events {
+        match event {
+            Event::Start(Tag::Link(_, ref dest, _)) if is_annotated(dest) => {
+                text = Some(String::new());
+            }
+            Event::Text(ref t) => {
+                if let Some(ref mut text) = text {
+                    text.push_str(t);
+                }
+            }
+            Event::End(Tag::Link(_, ref dest, _)) if is_annotated(dest) => {
+                let text = text.take().unwrap_or_default();
+                if !shows_url(&text, dest) {
+                    out.push(event.clone());
+                    match mode {
+                        LinkUrls::Inline => out.push(Event::InlineHtml(
+                            format!(" <span class=\"link-url\">({})</span>", opf::escape(dest))
+                                .into(),
+                        )),
+                        _ => {
+                            let next = format!("link-url-{}", names.len() + 1);
+                            let name = names.entry(dest.to_string()).or_insert_with(|| {
+                                notes.push(dest.to_string());
+                                next
+                            });
+                            out.push(Event::FootnoteReference(name.clone().into()));
+                        }
+                    }
+                    continue;
+                }
+            }
+            _ => {}
+        }
+
+        out.push(event);
+    }
+
+    for url in notes {
+        let name: CowStr = names[&url].clone().into();
+        let url: CowStr = url.into();
+        out.push(Event::Start(Tag::FootnoteDefinition(name.clone())));
+        out.push(Event::Start(Tag::Paragraph));
+        out.push(Event::Start(Tag::Link(
+            LinkType::Inline,
+            url.clone(),
+            "".into(),
+        )));
+        out.push(Event::Text(url.clone()));
+        out.push(Event::End(Tag::Link(LinkType::Inline, url, "".into())));
+        out.push(Event::End(Tag::Paragraph));
+        out.push(Event::End(Tag::FootnoteDefinition(name)));
+    }
+
+    out
+}
+
+fn is_annotated(dest: &str) -> bool {
+    is_external(dest) && !dest.starts_with("data:")
+}
+
+/// Is the link's text already its URL (e.g. an autolink)?
+fn shows_url(text: &str, dest: &str) -> bool {
+    let without_scheme = |url: &str| {
+        let url = url.trim().trim_end_matches('/');
+        match url.find("://") {
+            Some(ix) => url[ix + 3..].to_string(),
+            None => url.trim_start_matches("//").to_string(),
+        }
+    };
+
+    without_scheme(text) == without_scheme(dest)
+}
+
+fn normalize_href(href: CowStr) -> CowStr {
+    if is_external(&href) {
+        href
+    } else {
+        encode(&nfc(&decode(&href))).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::{html, Parser};
+
+    #[test]
+    fn encoding_is_reversible() {
+        let inputs = vec!["images/mon image é.png", "100%.png", "plain.png"];
+
+        for input in inputs {
+            let encoded = encode(input);
+            assert!(!encoded.contains(' '));
+            assert_eq!(decode(&encoded), input);
+        }
+    }
+
+    #[test]
+    fn directory_separators_are_left_alone() {
+        assert_eq!(encode("images/a b.png"), "images/a%20b.png");
+    }
+
+    #[test]
+    fn decomposed_characters_are_composed() {
+        let decomposed = "e\u{301}";
+
+        assert_eq!(nfc(decomposed), "\u{e9}");
+    }
+
+    #[test]
+    fn local_images_are_encoded() {
+        let src = "![](./mon%20image%20e%CC%81.png) ![](https://example.com/a%20b.png)";
+
+        let events = rewrite(Parser::new(src), &[]);
+        let mut got = String::new();
+        html::push_html(&mut got, events.into_iter());
+
+        assert!(got.contains(r#"src="./mon%20image%20%C3%A9.png""#));
+        assert!(got.contains(r#"src="https://example.com/a%20b.png""#));
+    }
+
+    #[test]
+    fn only_allowed_links_are_packaged() {
+        let allowed = vec![String::from("pdf"), String::from(".ZIP")];
+        let inputs = vec![
+            ("./files/examples.zip", true),
+            ("manual.PDF", true),
+            ("chapter_2.md", false),
+            ("https://example.com/examples.zip", false),
+            ("README", false),
+        ];
+
+        for (link, should_be) in inputs {
+            assert_eq!(is_packaged(link, &allowed), should_be, "{}", link);
+        }
+    }
+
+    fn annotate(src: &str, mode: LinkUrls) -> String {
+        let mut got = String::new();
+        html::push_html(
+            &mut got,
+            annotate_external(Parser::new(src), mode).into_iter(),
+        );
+        got
+    }
+
+    const LINKS: &str = "See [the docs](https://example.com/docs?a=1&b=2), \
+                         <https://example.com/> and [the next chapter](chapter_2.md).";
+
+    #[test]
+    fn external_urls_can_be_shown_inline() {
+        let got = annotate(LINKS, LinkUrls::Inline);
+
+        assert!(got.contains(
+            "the docs</a> <span class=\"link-url\">(https://example.com/docs?a=1&amp;b=2)</span>"
+        ));
+        assert_eq!(got.matches("link-url").count(), 1);
+    }
+
+    #[test]
+    fn external_urls_can_be_footnotes() {
+        let src = "[one](https://example.com/) and [two](https://example.com/) and \
+                   [three](https://example.org/)";
+
+        let got = annotate(src, LinkUrls::Footnote);
+
+        assert_eq!(got.matches("href=\"#link-url-1\"").count(), 2);
+        assert_eq!(got.matches("id=\"link-url-2\"").count(), 1);
+        assert!(got.contains("<a href=\"https://example.org/\">https://example.org/</a>"));
+    }
+
+    #[test]
+    fn links_showing_their_url_as_code_arent_annotated() {
+        let got = annotate(
+            "[`https://example.com/`](https://example.com/)",
+            LinkUrls::Inline,
+        );
+
+        assert!(!got.contains("link-url"));
+    }
+
+    #[test]
+    fn links_are_left_alone_by_default() {
+        let got = annotate(LINKS, LinkUrls::None);
+
+        assert!(!got.contains("link-url"));
+    }
+
+    #[test]
+    fn classify_links() {
+        let inputs = vec![
+            ("#installation", LinkKind::Fragment),
+            ("https://example.com/#top", LinkKind::External),
+            ("mailto:me@example.com", LinkKind::Scheme),
+            ("tel:+44-20-7946-0000", LinkKind::Scheme),
+            ("../chapter_2.md#intro", LinkKind::Chapter),
+            ("C:/images/logo.png", LinkKind::File),
+            ("files/data.csv", LinkKind::File),
+        ];
+
+        for (link, should_be) in inputs {
+            assert_eq!(classify(link), should_be, "{}", link);
+        }
+    }
+
+    #[test]
+    fn chapter_links_point_at_the_rendered_page() {
+        let chapters = vec![
+            PathBuf::from("intro.md"),
+            PathBuf::from("part-1/chapter 1.md"),
+            PathBuf::from("part-1/chapter_2.md"),
+        ];
+        let from = Path::new("part-1/chapter_2.md");
+        let inputs = vec![
+            (
+                "chapter%201.md#setup",
+                Some("../part-1/chapter%201.html#setup"),
+            ),
+            ("../intro.md", Some("../intro.html")),
+            // relative to SUMMARY.md rather than the chapter
+            ("intro.md", Some("../intro.html")),
+            ("/part-1/chapter_2.md", Some("../part-1/chapter_2.html")),
+            ("missing.md", None),
+            ("../../outside.md", None),
+        ];
+
+        for (link, should_be) in inputs {
+            let got = chapter_href(from, link, &chapters);
+            assert_eq!(got.as_deref(), should_be, "{}", link);
+        }
+    }
+
+    #[test]
+    fn only_chapter_links_are_rewritten() {
+        let chapters = vec![PathBuf::from("chapter_1.md"), PathBuf::from("chapter_2.md")];
+        let src = "[next](chapter_2.md#intro) [top](#top) [mail](mailto:me@example.com) \
+                   [gone](chapter_3.md)";
+
+        let events = rewrite_chapter_links(Parser::new(src), Path::new("chapter_1.md"), &chapters);
+        let mut got = String::new();
+        html::push_html(&mut got, events.into_iter());
+
+        assert!(got.contains(r#"href="chapter_2.html#intro""#));
+        assert!(got.contains(r##"href="#top""##));
+        assert!(got.contains(r#"href="mailto:me@example.com""#));
+        assert!(got.contains(r#"href="chapter_3.md""#));
+    }
+
+    #[test]
+    fn packaged_links_are_encoded() {
+        let allowed = vec![String::from("zip")];
+        let src = "[download](./sample%20code.zip) [next](./chapter_2.md#intro)";
+
+        let events = rewrite(Parser::new(src), &allowed);
+        let mut got = String::new();
+        html::push_html(&mut got, events.into_iter());
+
+        assert!(got.contains(r#"href="./sample%20code.zip""#));
+        assert!(got.contains(r#"href="./chapter_2.md#intro""#));
+    }
+}
@@ -0,0 +1,148 @@
+//! Parsing a chapter's markdown into the events we render.
+
+use pulldown_cmark::{Event, Options, Parser};
+
+use code_blocks;
+use config::MarkdownConfig;
+use definition_lists;
+use opf;
+use page_breaks;
+use page_list;
+use strings::Strings;
+use tables;
+
+/// Create a parser with the markdown extensions enabled in
+/// `output.epub.markdown`.
+pub fn parser<'a>(text: &'a str, config: &MarkdownConfig) -> Parser<'a> {
+    let mut opts = Options::empty();
+
+    if config.tables {
+        opts.insert(Options::ENABLE_TABLES);
+    }
+    if config.footnotes {
+        opts.insert(Options::ENABLE_FOOTNOTES);
+    }
+    if config.strikethrough {
+        opts.insert(Options::ENABLE_STRIKETHROUGH);
+    }
+    if config.tasklists {
+        opts.insert(Options::ENABLE_TASKLISTS);
+    }
+
+    Parser::new_ext(text, opts)
+}
+
+/// Parse some markdown, applying every transformation a chapter needs before
+/// it can be rendered.
+pub fn events<'a>(text: &'a str, config: &MarkdownConfig, strings: &Strings) -> Vec<Event<'a>> {
+    transform(parser(text, config), config, strings)
+}
+
+/// Apply everything `events()` does to the events from a `parser()`.
+pub fn transform<'a, I>(events: I, config: &MarkdownConfig, strings: &Strings) -> Vec<Event<'a>>
+where
+    I: IntoIterator<Item = Event<'a>>,
+{
+    let events = task_list_glyphs(events, strings);
+    let events = page_breaks::transform(events, &config.page_break_marker);
+    let events = page_list::transform(events);
+    let events = tables::transform(events, config);
+    let events = code_blocks::transform(events, config);
+
+    if config.definition_lists {
+        definition_lists::transform(events)
+    } else {
+        events
+    }
+}
+
+/// Task list markers are normally rendered as checkboxes, but an ebook can't
+/// be interacted with so we use a glyph with a textual label instead.
+pub fn task_list_glyphs<'a, I>(events: I, strings: &Strings) -> Vec<Event<'a>>
+where
+    I: IntoIterator<Item = Event<'a>>,
+{
+    events
+        .into_iter()
+        .map(|event| match event {
+            Event::TaskListMarker(checked) => {
+                let (glyph, label) = if checked {
+                    ("\u{2611}", strings.get("completed"))
+                } else {
+                    ("\u{2610}", strings.get("not-completed"))
+                };
+
+                Event::InlineHtml(
+                    format!(
+                        r#"<span class="task-list-marker" role="img" aria-label="{}">{}</span> "#,
+                        opf::escape(label),
+                        glyph
+                    )
+                    .into(),
+                )
+            }
+            other => other,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::html;
+
+    fn render(src: &str, config: &MarkdownConfig) -> String {
+        let mut buffer = String::new();
+        html::push_html(
+            &mut buffer,
+            events(src, config, &Strings::default()).into_iter(),
+        );
+        buffer
+    }
+
+    #[test]
+    fn extensions_are_enabled_by_default() {
+        let src = "~~gone~~\n\n| a | b |\n|---|---|\n| 1 | 2 |\n";
+
+        let got = render(src, &MarkdownConfig::default());
+
+        assert!(got.contains("<del>gone</del>"));
+        assert!(got.contains("<table>"));
+        assert!(got.contains("table-wrapper"));
+    }
+
+    #[test]
+    fn extensions_can_be_turned_off() {
+        let config = MarkdownConfig {
+            strikethrough: false,
+            ..Default::default()
+        };
+
+        let got = render("~~kept~~", &config);
+
+        assert!(!got.contains("<del>"));
+    }
+
+    #[test]
+    fn task_lists_are_not_interactive() {
+        let src = "- [x] done\n- [ ] todo\n";
+
+        let got = render(src, &MarkdownConfig::default());
+
+        assert!(!got.contains("<input"));
+        assert!(got.contains(r#"aria-label="Completed">"#));
+        assert!(got.contains(r#"aria-label="Not completed">"#));
+    }
+
+    #[test]
+    fn definition_lists_can_be_turned_off() {
+        let src = "Term\n: Definition\n";
+        let config = MarkdownConfig {
+            definition_lists: false,
+            ..Default::default()
+        };
+
+        assert!(render(src, &MarkdownConfig::default()).contains("<dl>"));
+        assert!(!render(src, &config).contains("<dl>"));
+    }
+}
@@ -0,0 +1,89 @@
+//! Section numbering, mirroring the numbers `mdbook` derives from
+//! `SUMMARY.md`.
+
+use mdbook::book::Chapter;
+use pulldown_cmark::{Event, Tag};
+
+/// Get a chapter's title, optionally prefixed with its section number (e.g.
+/// "1.2. Getting Started"). Prefix and suffix chapters are never numbered.
+pub fn title(ch: &Chapter, section_numbers: bool) -> String {
+    custom_title(ch, &ch.name, section_numbers)
+}
+
+/// Like `title()`, but using a title other than the chapter's name.
+pub fn custom_title(ch: &Chapter, title: &str, section_numbers: bool) -> String {
+    match ch.number {
+        Some(ref number) if section_numbers => format!("{} {}", number, title),
+        _ => title.to_string(),
+    }
+}
+
+/// Prefix the first heading in a chapter with the chapter's section number.
+pub fn number_first_heading<'a, I>(events: I, ch: &Chapter) -> Vec<Event<'a>>
+where
+    I: IntoIterator<Item = Event<'a>>,
+{
+    let mut events: Vec<Event<'a>> = events.into_iter().collect();
+
+    let number = match ch.number {
+        Some(ref number) => format!("{} ", number),
+        None => return events,
+    };
+
+    let first_heading = events
+        .iter()
+        .position(|event| matches!(*event, Event::Start(Tag::Header(_))));
+
+    if let Some(ix) = first_heading {
+        events.insert(ix + 1, Event::Text(number.into()));
+    }
+
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mdbook::book::SectionNumber;
+    use pulldown_cmark::{html, Parser};
+    use std::path::PathBuf;
+
+    fn chapter(number: Option<Vec<u32>>) -> Chapter {
+        let mut ch = Chapter::new(
+            "Getting Started",
+            String::new(),
+            PathBuf::from("start.md"),
+            Vec::new(),
+        );
+        ch.number = number.map(SectionNumber);
+        ch
+    }
+
+    #[test]
+    fn numbered_titles() {
+        let ch = chapter(Some(vec![1, 2]));
+
+        assert_eq!(title(&ch, true), "1.2. Getting Started");
+        assert_eq!(title(&ch, false), "Getting Started");
+    }
+
+    #[test]
+    fn unnumbered_chapters_keep_their_name() {
+        let ch = chapter(None);
+
+        assert_eq!(title(&ch, true), "Getting Started");
+    }
+
+    #[test]
+    fn only_the_first_heading_is_numbered() {
+        let ch = chapter(Some(vec![3]));
+        let src = "Intro\n\n# Getting Started\n\n## Installation\n";
+
+        let events = number_first_heading(Parser::new(src), &ch);
+        let mut got = String::new();
+        html::push_html(&mut got, events.into_iter());
+
+        assert!(got.contains("<h1>3. Getting Started</h1>"));
+        assert!(got.contains("<h2>Installation</h2>"));
+    }
+}
@@ -0,0 +1,473 @@
+//! Post-processing the package document (`content.opf`) generated by
+//! `epub-builder`, for the metadata it doesn't know how to write.
+
+use failure::{self, Error};
+
+use config::{ExtraMeta, SeriesConfig};
+
+/// The Dublin Core Metadata Element Set, the only `dc:` elements a package
+/// document can contain.
+const DCMES: &[&str] = &[
+    "contributor",
+    "coverage",
+    "creator",
+    "date",
+    "description",
+    "format",
+    "identifier",
+    "language",
+    "publisher",
+    "relation",
+    "rights",
+    "source",
+    "subject",
+    "title",
+    "type",
+];
+
+/// Insert extra elements at the end of the package document's `<metadata>`.
+pub fn insert_metadata<S: AsRef<str>>(opf: &str, elements: &[S]) -> String {
+    if elements.is_empty() {
+        return opf.to_string();
+    }
+
+    let ix = match opf.find("</metadata>") {
+        Some(ix) => ix,
+        None => {
+            warn!("Unable to find the package document's metadata");
+            return opf.to_string();
+        }
+    };
+
+    let mut extra = String::new();
+    for element in elements {
+        extra.push_str("    ");
+        extra.push_str(element.as_ref());
+        extra.push('\n');
+    }
+
+    format!("{}{}{}", &opf[..ix], extra, &opf[ix..])
+}
+
+/// The metadata used by calibre and EPUB 3 reading systems to group the
+/// volumes of a series.
+pub fn series_metadata(series: &SeriesConfig) -> Vec<String> {
+    let name = escape(&series.name);
+    let mut elements = vec![
+        format!(r#"<meta name="calibre:series" content="{}"/>"#, name),
+        format!(
+            r#"<meta property="belongs-to-collection" id="series">{}</meta>"#,
+            name
+        ),
+        String::from(r##"<meta refines="#series" property="collection-type">series</meta>"##),
+    ];
+
+    if let Some(index) = series.index {
+        elements.push(format!(
+            r#"<meta name="calibre:series_index" content="{}"/>"#,
+            index
+        ));
+        elements.push(format!(
+            r##"<meta refines="#series" property="group-position">{}</meta>"##,
+            index
+        ));
+    }
+
+    elements
+}
+
+/// The elements for each entry in `output.epub.extra-opf-meta`, which are
+/// passed through as-is.
+pub fn extra_metadata(extra: &[ExtraMeta]) -> Result<Vec<String>, Error> {
+    let mut elements = Vec::new();
+
+    for meta in extra {
+        let property = meta.property.trim();
+        if property.is_empty() || property.contains(char::is_whitespace) {
+            return Err(failure::err_msg(format!(
+                "\"{}\" isn't a valid property for extra-opf-meta",
+                meta.property
+            )));
+        }
+
+        let mut attributes = String::new();
+        for &(name, value) in &[
+            ("id", &meta.id),
+            ("refines", &meta.refines),
+            ("scheme", &meta.scheme),
+        ] {
+            if let Some(ref value) = *value {
+                attributes.push_str(&format!(" {}=\"{}\"", name, escape(value)));
+            }
+        }
+
+        let element = if let Some(element) = property.strip_prefix("dc:") {
+            // the property becomes the element's name, so it can't be
+            // escaped like everything else
+            if !DCMES.contains(&element) {
+                return Err(failure::err_msg(format!(
+                    "\"{}\" isn't a Dublin Core element, extra-opf-meta can only add {}",
+                    meta.property,
+                    DCMES
+                        .iter()
+                        .map(|name| format!("dc:{}", name))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )));
+            }
+
+            format!(
+                "<{0}{1}>{2}</{0}>",
+                property,
+                attributes,
+                escape(&meta.value)
+            )
+        } else {
+            format!(
+                "<meta property=\"{}\"{}>{}</meta>",
+                escape(property),
+                attributes,
+                escape(&meta.value)
+            )
+        };
+        elements.push(element);
+    }
+
+    Ok(elements)
+}
+
+/// Remove everything which is only there for EPUB 2 reading systems (the NCX
+/// and `<guide>`), leaving a strict EPUB 3 package document.
+pub fn strip_epub2(opf: &str) -> String {
+    let mut opf = opf.replacen(r#" toc="ncx""#, "", 1);
+
+    if let Some(start) = opf.find("<guide") {
+        if let Some(end) = opf[start..].find("</guide>") {
+            let end = start + end + "</guide>".len();
+            opf.replace_range(start..end, "");
+        }
+    }
+
+    let mut offset = 0;
+    while let Some(start) = opf[offset..].find("<item ").map(|ix| ix + offset) {
+        let end = match opf[start..].find("/>") {
+            Some(ix) => start + ix + "/>".len(),
+            None => break,
+        };
+
+        if opf[start..end].contains(r#"href="toc.ncx""#) {
+            opf.replace_range(start..end, "");
+        } else {
+            offset = end;
+        }
+    }
+
+    opf
+}
+
+/// Add a property (e.g. `scripted`) to the manifest items for each of these
+/// `href`s.
+pub fn add_property<S: AsRef<str>>(opf: &str, hrefs: &[S], property: &str) -> String {
+    if hrefs.is_empty() {
+        return opf.to_string();
+    }
+
+    let mut opf = opf.to_string();
+    let mut offset = 0;
+
+    while let Some(start) = opf[offset..].find("<item ").map(|ix| ix + offset) {
+        let end = match opf[start..].find("/>") {
+            Some(ix) => start + ix,
+            None => break,
+        };
+
+        let matches = hrefs
+            .iter()
+            .any(|href| opf[start..end].contains(&format!(r#"href="{}""#, href.as_ref())));
+
+        if matches {
+            let item = &opf[start..end];
+            let inserted = match item.find(r#"properties=""#) {
+                Some(ix) => {
+                    let ix = start + ix + r#"properties=""#.len();
+                    opf.insert_str(ix, &format!("{} ", property));
+                    property.len() + 1
+                }
+                None => {
+                    let attribute = format!(r#" properties="{}""#, property);
+                    let ix = start + item.trim_end().len();
+                    opf.insert_str(ix, &attribute);
+                    attribute.len()
+                }
+            };
+            offset = end + inserted;
+        } else {
+            offset = end;
+        }
+    }
+
+    opf
+}
+
+/// Point the `<meta name="cover">` element older reading systems and
+/// library software use to find the cover at the manifest item for `href`,
+/// replacing any which is already there.
+pub fn set_cover_meta(opf: &str, href: &str) -> String {
+    let id = match manifest_id(opf, href) {
+        Some(id) => id.to_string(),
+        None => {
+            warn!("Unable to find {} in the package document's manifest", href);
+            return opf.to_string();
+        }
+    };
+
+    let mut opf = opf.to_string();
+    while let Some(start) = opf.find(r#"<meta name="cover""#) {
+        match opf[start..].find("/>") {
+            Some(ix) => opf.replace_range(start..start + ix + "/>".len(), ""),
+            None => break,
+        }
+    }
+
+    insert_metadata(
+        &opf,
+        &[format!(r#"<meta name="cover" content="{}"/>"#, escape(&id))],
+    )
+}
+
+/// The `id` of the manifest item for `href`, if there is one.
+pub fn manifest_id<'a>(opf: &'a str, href: &str) -> Option<&'a str> {
+    let needle = format!(r#"href="{}""#, href);
+    let mut offset = 0;
+
+    while let Some(start) = opf[offset..].find("<item ").map(|ix| ix + offset) {
+        let end = opf[start..].find("/>").map(|ix| start + ix)?;
+        let item = &opf[start..end];
+
+        if item.contains(&needle) {
+            return item
+                .split(r#" id=""#)
+                .nth(1)
+                .and_then(|rest| rest.split('"').next());
+        }
+
+        offset = end;
+    }
+
+    None
+}
+
+/// The `href` of the manifest item with the `cover-image` property, if
+/// there is one.
+pub fn cover_image(opf: &str) -> Option<&str> {
+    let mut offset = 0;
+
+    while let Some(start) = opf[offset..].find("<item ").map(|ix| ix + offset) {
+        let end = opf[start..].find("/>").map(|ix| start + ix)?;
+        let item = &opf[start..end];
+
+        let is_cover = item
+            .split(r#"properties=""#)
+            .nth(1)
+            .and_then(|rest| rest.split('"').next())
+            .is_some_and(|properties| properties.split_whitespace().any(|p| p == "cover-image"));
+        if is_cover {
+            return item
+                .split(r#"href=""#)
+                .nth(1)
+                .and_then(|rest| rest.split('"').next());
+        }
+
+        offset = end;
+    }
+
+    None
+}
+
+/// Escape text so it can be used in XML content or attributes.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            other => escaped.push(other),
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metadata_is_added_to_the_end() {
+        let opf = "<package><metadata><dc:title>Book</dc:title></metadata></package>";
+
+        let got = insert_metadata(opf, &["<meta name=\"a\" content=\"b\"/>"]);
+
+        assert_eq!(
+            got,
+            "<package><metadata><dc:title>Book</dc:title>    \
+             <meta name=\"a\" content=\"b\"/>\n</metadata></package>"
+        );
+    }
+
+    #[test]
+    fn extra_meta_is_passed_through() {
+        let extra = vec![
+            ExtraMeta {
+                property: String::from("rendition:flow"),
+                value: String::from("scrolled-doc"),
+                id: None,
+                refines: None,
+                scheme: None,
+            },
+            ExtraMeta {
+                property: String::from("dc:subject"),
+                value: String::from("Fiction & Poetry"),
+                id: Some(String::from("subject")),
+                refines: None,
+                scheme: None,
+            },
+        ];
+
+        let got = extra_metadata(&extra).unwrap();
+
+        assert_eq!(
+            got,
+            vec![
+                r#"<meta property="rendition:flow">scrolled-doc</meta>"#,
+                r#"<dc:subject id="subject">Fiction &amp; Poetry</dc:subject>"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn extra_meta_needs_a_property() {
+        let extra = vec![ExtraMeta {
+            property: String::from("not a property"),
+            value: String::new(),
+            id: None,
+            refines: None,
+            scheme: None,
+        }];
+
+        assert!(extra_metadata(&extra).is_err());
+    }
+
+    #[test]
+    fn extra_meta_only_adds_dublin_core_elements() {
+        for property in &[
+            "dc:subject><script/",
+            "dc:sub\"ject",
+            "dc:foo/bar",
+            "dc:colour",
+        ] {
+            let extra = vec![ExtraMeta {
+                property: property.to_string(),
+                value: String::new(),
+                id: None,
+                refines: None,
+                scheme: None,
+            }];
+
+            assert!(extra_metadata(&extra).is_err(), "{}", property);
+        }
+    }
+
+    #[test]
+    fn strict_epub3_has_no_ncx_or_guide() {
+        let opf = concat!(
+            "<manifest>",
+            r#"<item media-type="application/x-dtbncx+xml" id="ncx" href="toc.ncx"/>"#,
+            r#"<item id="nav" href="nav.xhtml" properties="nav"/>"#,
+            "</manifest>",
+            r#"<spine toc="ncx"><itemref idref="chapter_1"/></spine>"#,
+            r#"<guide><reference type="text" href="chapter_1.html"/></guide>"#,
+        );
+        let should_be = concat!(
+            "<manifest>",
+            r#"<item id="nav" href="nav.xhtml" properties="nav"/>"#,
+            "</manifest>",
+            r#"<spine><itemref idref="chapter_1"/></spine>"#,
+        );
+
+        let got = strip_epub2(opf);
+
+        assert_eq!(got, should_be);
+    }
+
+    #[test]
+    fn scripted_documents_get_the_property() {
+        let opf = concat!(
+            r#"<item media-type="application/xhtml+xml" id="a" href="quiz.html"/>"#,
+            r#"<item media-type="application/xhtml+xml" id="b" href="other.html"/>"#,
+            r#"<item id="nav" href="nav.xhtml" properties="nav"/>"#,
+        );
+        let should_be = concat!(
+            r#"<item media-type="application/xhtml+xml" id="a" href="quiz.html" properties="scripted"/>"#,
+            r#"<item media-type="application/xhtml+xml" id="b" href="other.html"/>"#,
+            r#"<item id="nav" href="nav.xhtml" properties="scripted nav"/>"#,
+        );
+
+        let got = add_property(opf, &["quiz.html", "nav.xhtml"], "scripted");
+
+        assert_eq!(got, should_be);
+    }
+
+    #[test]
+    fn series_use_both_calibre_and_epub3_metadata() {
+        let series = SeriesConfig {
+            name: String::from("Tom & Jerry"),
+            index: Some(2.5),
+        };
+
+        let got = series_metadata(&series);
+
+        assert!(got.contains(&String::from(
+            r#"<meta name="calibre:series" content="Tom &amp; Jerry"/>"#
+        )));
+        assert!(got.contains(&String::from(
+            r#"<meta name="calibre:series_index" content="2.5"/>"#
+        )));
+        assert!(got.contains(&String::from(
+            r##"<meta refines="#series" property="group-position">2.5</meta>"##
+        )));
+    }
+
+    #[test]
+    fn find_the_cover_image() {
+        let opf = concat!(
+            r#"<item media-type="image/png" id="logo" href="logo.png"/>"#,
+            r#"<item media-type="image/jpeg" id="cover" href="images/cover.jpg" properties="cover-image"/>"#,
+        );
+
+        assert_eq!(cover_image(opf), Some("images/cover.jpg"));
+        assert_eq!(cover_image(r#"<item id="a" href="a.png"/>"#), None);
+    }
+
+    #[test]
+    fn cover_meta_points_at_the_chosen_image() {
+        let opf = concat!(
+            "<metadata>",
+            r#"<meta name="cover" content="cover-image"/>"#,
+            "</metadata><manifest>",
+            r#"<item media-type="image/png" id="cover-image" href="cover.png" properties="cover-image"/>"#,
+            r#"<item media-type="image/png" id="cover-thumbnail_png" href="cover-thumbnail.png"/>"#,
+            "</manifest>",
+        );
+
+        let got = set_cover_meta(opf, "cover-thumbnail.png");
+
+        assert_eq!(got.matches(r#"<meta name="cover""#).count(), 1);
+        assert!(got.contains(r#"<meta name="cover" content="cover-thumbnail_png"/>"#));
+        assert_eq!(set_cover_meta(opf, "missing.png"), opf);
+    }
+}
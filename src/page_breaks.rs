@@ -0,0 +1,67 @@
+//! Explicit page breaks, inserted wherever a chapter contains the page break
+//! marker (`<!-- pagebreak -->` by default) on a line of its own.
+
+use pulldown_cmark::Event;
+
+/// The element each page break marker is replaced with. It's styled with
+/// `page-break-before` by the default stylesheet.
+const PAGE_BREAK: &str = "<div class=\"page-break\" epub:type=\"pagebreak\"></div>\n";
+
+/// Replace every occurrence of `marker` with a page break.
+pub fn transform<'a, I>(events: I, marker: &str) -> Vec<Event<'a>>
+where
+    I: IntoIterator<Item = Event<'a>>,
+{
+    let marker = marker.trim();
+
+    events
+        .into_iter()
+        .map(|event| match event {
+            Event::Html(ref html) if !marker.is_empty() && html.trim() == marker => {
+                Event::Html(PAGE_BREAK.into())
+            }
+            other => other,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::{html, Parser};
+
+    fn render(src: &str, marker: &str) -> String {
+        let mut buffer = String::new();
+        html::push_html(&mut buffer, transform(Parser::new(src), marker).into_iter());
+        buffer
+    }
+
+    #[test]
+    fn markers_become_page_breaks() {
+        let src = "First page\n\n<!-- pagebreak -->\n\nSecond page\n";
+
+        let got = render(src, "<!-- pagebreak -->");
+
+        assert!(got.contains(PAGE_BREAK));
+        assert!(!got.contains("<!--"));
+    }
+
+    #[test]
+    fn the_marker_is_configurable() {
+        let src = "First page\n\n<!-- newpage -->\n\n<!-- pagebreak -->\n";
+
+        let got = render(src, "<!-- newpage -->");
+
+        assert_eq!(got.matches("page-break").count(), 1);
+        assert!(got.contains("<!-- pagebreak -->"));
+    }
+
+    #[test]
+    fn an_empty_marker_disables_page_breaks() {
+        let src = "<!-- pagebreak -->\n";
+
+        let got = render(src, "");
+
+        assert!(!got.contains("page-break"));
+    }
+}
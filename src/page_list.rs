@@ -0,0 +1,194 @@
+//! Print page numbers, so readers can cite the page numbers of a print
+//! edition.
+//!
+//! Authors mark where each print page starts with `{{#page 37}}`. The marker
+//! becomes an `epub:type="pagebreak"` anchor, and every anchor gets an entry
+//! in the EPUB 3 `page-list` navigation.
+
+use pulldown_cmark::{Event, Tag};
+
+const MARKER_START: &str = "{{#page ";
+const MARKER_END: &str = "}}";
+/// The bit of the anchor we search for when collecting page numbers from
+/// rendered HTML.
+const ANCHOR_PREFIX: &str = "<span epub:type=\"pagebreak\" id=\"page-";
+
+/// A page in the print edition.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PageTarget {
+    /// The page number (e.g. `"37"` or `"xii"`).
+    pub label: String,
+    /// Where the page starts, relative to the package document.
+    pub href: String,
+}
+
+/// Replace every `{{#page N}}` marker with a page break anchor.
+pub fn transform<'a, I>(events: I) -> Vec<Event<'a>>
+where
+    I: IntoIterator<Item = Event<'a>>,
+{
+    let mut out = Vec::new();
+    let mut text = String::new();
+    let mut in_code = false;
+
+    for event in events {
+        match event {
+            Event::Text(ref t) if !in_code => {
+                text.push_str(t);
+                continue;
+            }
+            Event::Start(Tag::CodeBlock(_)) | Event::Start(Tag::Code) => in_code = true,
+            Event::End(Tag::CodeBlock(_)) | Event::End(Tag::Code) => in_code = false,
+            _ => {}
+        }
+
+        flush(&mut text, &mut out);
+        out.push(event);
+    }
+
+    flush(&mut text, &mut out);
+    out
+}
+
+/// Emit the text we've seen so far, with every marker replaced.
+fn flush<'a>(text: &mut String, out: &mut Vec<Event<'a>>) {
+    if text.is_empty() {
+        return;
+    }
+
+    let mut rest = &text[..];
+
+    while let Some((start, label, end)) = next_marker(rest) {
+        if start > 0 {
+            out.push(Event::Text(rest[..start].to_string().into()));
+        }
+        out.push(Event::InlineHtml(anchor(label).into()));
+        rest = &rest[end..];
+    }
+
+    if !rest.is_empty() {
+        out.push(Event::Text(rest.to_string().into()));
+    }
+
+    text.clear();
+}
+
+/// Find the next valid marker, returning where it starts, its label, and
+/// where it ends.
+fn next_marker(text: &str) -> Option<(usize, &str, usize)> {
+    let mut offset = 0;
+
+    while let Some(ix) = text[offset..].find(MARKER_START) {
+        let start = offset + ix;
+        let label_start = start + MARKER_START.len();
+        let label_end = label_start + text[label_start..].find(MARKER_END)?;
+        let label = text[label_start..label_end].trim();
+
+        if is_valid_label(label) {
+            return Some((start, label, label_end + MARKER_END.len()));
+        }
+
+        offset = label_start;
+    }
+
+    None
+}
+
+/// Labels end up in an `id`, so we only accept alphanumeric page numbers.
+fn is_valid_label(label: &str) -> bool {
+    !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+fn anchor(label: &str) -> String {
+    format!("{}{}\" title=\"{}\"></span>", ANCHOR_PREFIX, label, label)
+}
+
+/// Get the label of every page break anchor in some rendered HTML, in the
+/// order they appear.
+pub fn labels(html: &str) -> Vec<String> {
+    html.match_indices(ANCHOR_PREFIX)
+        .filter_map(|(ix, _)| {
+            let rest = &html[ix + ANCHOR_PREFIX.len()..];
+            rest.find('"').map(|end| rest[..end].to_string())
+        })
+        .collect()
+}
+
+/// Add a `page-list` navigation element to `epub-builder`'s navigation
+/// document.
+pub fn insert_nav(nav: &str, pages: &[PageTarget]) -> String {
+    if pages.is_empty() {
+        return nav.to_string();
+    }
+
+    let mut page_list = String::from("<nav epub:type=\"page-list\" hidden=\"\">\n<ol>\n");
+    for page in pages {
+        page_list.push_str(&format!(
+            "<li><a href=\"{}\">{}</a></li>\n",
+            page.href, page.label
+        ));
+    }
+    page_list.push_str("</ol>\n</nav>\n");
+
+    match nav.rfind("</body>") {
+        Some(ix) => format!("{}{}{}", &nav[..ix], page_list, &nav[ix..]),
+        None => format!("{}{}", nav, page_list),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::{html, Parser};
+
+    fn render(src: &str) -> String {
+        let mut buffer = String::new();
+        html::push_html(&mut buffer, transform(Parser::new(src)).into_iter());
+        buffer
+    }
+
+    #[test]
+    fn markers_become_anchors() {
+        let src = "The end of one page.{{#page 37}} The start of the next.";
+        let should_be = "<p>The end of one page.<span epub:type=\"pagebreak\" id=\"page-37\" \
+                         title=\"37\"></span> The start of the next.</p>\n";
+
+        let got = render(src);
+
+        assert_eq!(got, should_be);
+    }
+
+    #[test]
+    fn invalid_markers_and_code_are_left_alone() {
+        let src = "{{#page not a number}}\n\n```\n{{#page 12}}\n```\n\nSee `{{#page 3}}`.\n";
+
+        let got = render(src);
+
+        assert!(got.contains("{{#page not a number}}"));
+        assert!(got.contains("{{#page 12}}"));
+        assert!(got.contains("<code>{{#page 3}}</code>"));
+        assert!(labels(&got).is_empty());
+    }
+
+    #[test]
+    fn find_the_labels_in_rendered_html() {
+        let got = labels(&render("{{#page xi}}\n\nText {{#page xii}}"));
+
+        assert_eq!(got, vec!["xi", "xii"]);
+    }
+
+    #[test]
+    fn page_list_goes_at_the_end_of_the_nav_document() {
+        let nav = "<html><body><nav epub:type=\"toc\"></nav></body></html>";
+        let pages = vec![PageTarget {
+            label: String::from("37"),
+            href: String::from("chapter_1.html#page-37"),
+        }];
+
+        let got = insert_nav(nav, &pages);
+
+        assert!(got.contains("</nav><nav epub:type=\"page-list\""));
+        assert!(got.contains("<li><a href=\"chapter_1.html#page-37\">37</a></li>"));
+        assert!(got.ends_with("</nav>\n</body></html>"));
+    }
+}
@@ -0,0 +1,240 @@
+//! The stages a book flows through on its way into the archive.
+//!
+//! Rendering a chapter needs most of the `Generator`'s state (footnote
+//! numbering, citations, the path registry, diagnostics), so it happens on
+//! the generator's thread. The work either side of it doesn't, and runs
+//! alongside it:
+//!
+//! - the parse stage parses each chapter on a worker thread, in the order
+//!   they're rendered, counting its words and finding the assets it
+//!   references
+//! - the zip stage compresses each entry on a writer thread while the next
+//!   chapter is rendered (see the `spool` module)
+//!
+//! Stages are connected by bounded channels, so a stage which gets ahead
+//! waits for the next one instead of holding the whole book in memory.
+
+use failure::{err_msg, Error, ResultExt};
+use pulldown_cmark::Event;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::thread::Scope;
+
+use code_blocks;
+use config::Config;
+use markdown;
+use resources::{self, Asset};
+use stats;
+use strings::Strings;
+
+/// How many items a stage can get ahead of the next one.
+pub const CAPACITY: usize = 16;
+
+/// A chapter waiting to be parsed.
+#[derive(Debug, Clone, Copy)]
+pub struct Source<'t> {
+    pub path: &'t Path,
+    /// The chapter's markdown, without its front matter.
+    pub text: &'t str,
+}
+
+/// A chapter which is ready to be rendered.
+#[derive(Debug)]
+pub struct Parsed<'t> {
+    pub path: PathBuf,
+    pub events: Vec<Event<'t>>,
+    pub words: usize,
+    /// The line number and length of every line of code which is longer than
+    /// `max-code-line-length`.
+    pub long_lines: Vec<(usize, usize)>,
+    pub assets: Vec<Asset>,
+}
+
+/// Parse a chapter.
+pub fn parse<'t>(
+    source: &Source<'t>,
+    src_dir: &Path,
+    config: &Config,
+    strings: &Strings,
+) -> Result<Parsed<'t>, Error> {
+    trace!("Parsing {}", source.path.display());
+
+    // code blocks are gone once the events have been transformed
+    let events: Vec<_> = markdown::parser(source.text, &config.markdown).collect();
+    let long_lines = code_blocks::long_lines(&events, config.markdown.max_code_line_length);
+    let events = markdown::transform(events, &config.markdown, strings);
+
+    let words = stats::word_count(&events);
+    let assets = resources::chapter_assets(src_dir, source.path, &events, config)
+        .context("Inspecting the book for additional assets failed")?;
+
+    Ok(Parsed {
+        path: source.path.to_path_buf(),
+        events,
+        words,
+        long_lines,
+        assets,
+    })
+}
+
+/// The receiving end of the parse stage.
+#[derive(Debug)]
+pub struct ParseStage<'t> {
+    receiver: Receiver<Result<Parsed<'t>, Error>>,
+}
+
+impl<'t> ParseStage<'t> {
+    /// Start parsing every source, in order, on a worker thread belonging to
+    /// `scope`. The worker stops at the first error, or once the receiving
+    /// end is dropped.
+    pub fn start<'scope, 'env, F>(
+        scope: &'scope Scope<'scope, 'env>,
+        sources: Vec<Source<'t>>,
+        parse: F,
+    ) -> ParseStage<'t>
+    where
+        't: 'scope,
+        F: Fn(&Source<'t>) -> Result<Parsed<'t>, Error> + Send + 'scope,
+    {
+        let (sender, receiver) = mpsc::sync_channel(CAPACITY);
+
+        scope.spawn(move || {
+            for source in &sources {
+                let parsed = parse(source);
+                let failed = parsed.is_err();
+                // the generator hung up or there's no point going on
+                if sender.send(parsed).is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        ParseStage { receiver }
+    }
+
+    /// The chapter at `path`, which must be the next one to be parsed,
+    /// waiting for it if the worker hasn't got that far yet.
+    pub fn next(&mut self, path: &Path) -> Result<Parsed<'t>, Error> {
+        match self.receiver.recv() {
+            Ok(Ok(ref parsed)) if parsed.path != path => Err(err_msg(format!(
+                "Expected to render {} next, but {} was parsed",
+                path.display(),
+                parsed.path.display()
+            ))),
+            Ok(parsed) => parsed,
+            Err(_) => Err(err_msg(format!(
+                "The parse stage stopped before {}",
+                path.display()
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn sources(paths: &[PathBuf]) -> Vec<Source<'_>> {
+        paths
+            .iter()
+            .map(|path| Source {
+                path,
+                text: "Some *text*.",
+            })
+            .collect()
+    }
+
+    fn parse_words<'t>(source: &Source<'t>) -> Result<Parsed<'t>, Error> {
+        Ok(Parsed {
+            path: source.path.to_path_buf(),
+            events: Vec::new(),
+            words: source.text.split_whitespace().count(),
+            long_lines: Vec::new(),
+            assets: Vec::new(),
+        })
+    }
+
+    fn paths(count: usize) -> Vec<PathBuf> {
+        (0..count)
+            .map(|ix| PathBuf::from(format!("chapter_{}.md", ix)))
+            .collect()
+    }
+
+    #[test]
+    fn chapters_arrive_in_order() {
+        let paths = paths(CAPACITY * 2);
+
+        thread::scope(|scope| {
+            let mut stage = ParseStage::start(scope, sources(&paths), parse_words);
+
+            for path in &paths {
+                let parsed = stage.next(path).unwrap();
+                assert_eq!(parsed.path, *path);
+                assert_eq!(parsed.words, 2);
+            }
+            assert!(stage.next(Path::new("missing.md")).is_err());
+        });
+    }
+
+    #[test]
+    fn chapters_are_really_parsed() {
+        let mut config = Config::default();
+        config.markdown.max_code_line_length = 10;
+        let strings = Strings::new(None, &Default::default()).unwrap();
+        let source = Source {
+            path: Path::new("chapter_1.md"),
+            text: "# Chapter 1\n\n```\nfn main() {}\n```\n",
+        };
+
+        let got = parse(&source, Path::new("src"), &config, &strings).unwrap();
+
+        assert_eq!(got.words, 4);
+        assert_eq!(got.long_lines, vec![(1, 12)]);
+        assert!(got.assets.is_empty());
+    }
+
+    #[test]
+    fn the_stage_stops_at_the_first_error() {
+        let paths = paths(3);
+
+        thread::scope(|scope| {
+            let mut stage = ParseStage::start(scope, sources(&paths), |source| {
+                if source.path == paths[1] {
+                    Err(err_msg("broken"))
+                } else {
+                    parse_words(source)
+                }
+            });
+
+            assert!(stage.next(&paths[0]).is_ok());
+            assert_eq!(stage.next(&paths[1]).unwrap_err().to_string(), "broken");
+            let got = stage.next(&paths[2]).unwrap_err().to_string();
+            assert!(got.contains("stopped before"), "{}", got);
+        });
+    }
+
+    #[test]
+    fn the_stage_stops_when_the_generator_hangs_up() {
+        // more than the channel can hold, so the worker would block forever
+        // if it didn't notice
+        let paths = paths(CAPACITY * 4);
+
+        thread::scope(|scope| {
+            let mut stage = ParseStage::start(scope, sources(&paths), parse_words);
+            assert!(stage.next(&paths[0]).is_ok());
+            drop(stage);
+        });
+    }
+
+    #[test]
+    fn chapters_parsed_out_of_order_are_an_error() {
+        let paths = paths(2);
+
+        thread::scope(|scope| {
+            let mut stage = ParseStage::start(scope, sources(&paths), parse_words);
+
+            assert!(stage.next(&paths[1]).is_err());
+        });
+    }
+}
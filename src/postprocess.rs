@@ -0,0 +1,89 @@
+//! Letting library users rewrite each chapter's XHTML before it's packaged
+//! (e.g. to add a watermark, custom classes, or publisher boilerplate).
+
+use failure::Error;
+use mdbook::book::Chapter;
+use std::fmt::{self, Debug, Formatter};
+
+/// The chapter a rendered document came from.
+#[derive(Debug, Copy, Clone)]
+pub struct ChapterInfo<'c> {
+    pub chapter: &'c Chapter,
+    /// The document's path inside the book.
+    pub path: &'c str,
+    /// Oversized chapters are split into several documents, this is the
+    /// index of the current one.
+    pub part: usize,
+}
+
+/// Something which can rewrite a chapter's rendered XHTML.
+pub trait PostProcessor {
+    fn process(&self, info: &ChapterInfo, xhtml: String) -> Result<String, Error>;
+}
+
+impl<F> PostProcessor for F
+where
+    F: Fn(&ChapterInfo, String) -> Result<String, Error>,
+{
+    fn process(&self, info: &ChapterInfo, xhtml: String) -> Result<String, Error> {
+        self(info, xhtml)
+    }
+}
+
+/// Every post-processor, applied in the order they were added.
+#[derive(Default)]
+pub struct PostProcessors<'a>(Vec<Box<dyn PostProcessor + 'a>>);
+
+impl<'a> PostProcessors<'a> {
+    pub fn push<P: PostProcessor + 'a>(&mut self, processor: P) {
+        self.0.push(Box::new(processor));
+    }
+
+    pub fn apply(&self, info: &ChapterInfo, mut xhtml: String) -> Result<String, Error> {
+        for processor in &self.0 {
+            xhtml = processor.process(info, xhtml)?;
+        }
+
+        Ok(xhtml)
+    }
+}
+
+impl<'a> Debug for PostProcessors<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_tuple("PostProcessors")
+            .field(&self.0.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn processors_are_applied_in_order() {
+        let ch = Chapter::new(
+            "Chapter 1",
+            String::new(),
+            PathBuf::from("chapter_1.md"),
+            Vec::new(),
+        );
+        let info = ChapterInfo {
+            chapter: &ch,
+            path: "chapter_1.html",
+            part: 0,
+        };
+        let mut processors = PostProcessors::default();
+        processors.push(|info: &ChapterInfo, xhtml: String| {
+            Ok(format!("{}<!-- {} -->", xhtml, info.chapter.name))
+        });
+        processors.push(|_: &ChapterInfo, xhtml: String| Ok(xhtml.to_uppercase()));
+
+        let got = processors
+            .apply(&info, String::from("<p>text</p>"))
+            .unwrap();
+
+        assert_eq!(got, "<P>TEXT</P><!-- CHAPTER 1 -->");
+    }
+}
@@ -0,0 +1,208 @@
+//! Progress reporting, so long builds don't look like they've hung.
+
+use std::cell::Cell;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// The distinct phases of generating a book.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Phase {
+    /// Rendering chapters and generated pages.
+    Chapters,
+    /// Embedding stylesheets, fonts and other assets.
+    Assets,
+    /// Writing the table of contents and package document, then finishing
+    /// the archive.
+    Packaging,
+}
+
+impl Display for Phase {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let description = match *self {
+            Phase::Chapters => "Rendering chapters",
+            Phase::Assets => "Embedding assets",
+            Phase::Packaging => "Packaging",
+        };
+
+        f.write_str(description)
+    }
+}
+
+/// Something interesting which happened while generating a book.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Progress {
+    /// A new phase has started.
+    PhaseStarted(Phase),
+    /// A chapter has been rendered and added to the book.
+    ChapterRendered {
+        name: String,
+        completed: usize,
+        total: usize,
+    },
+    /// An asset has been added to the book.
+    AssetEmbedded {
+        name: String,
+        completed: usize,
+        total: usize,
+    },
+    /// The total number of bytes written to the output so far.
+    BytesWritten(u64),
+    /// A phase has finished, along with how long it took.
+    PhaseFinished(Phase, Duration),
+}
+
+/// Passes progress events on to a user-provided callback.
+pub struct Reporter<'a> {
+    callback: Box<dyn FnMut(&Progress) + 'a>,
+    written: Rc<Cell<u64>>,
+    current: Option<(Phase, Instant)>,
+    chapters: usize,
+    assets: usize,
+}
+
+impl<'a> Reporter<'a> {
+    /// Create a new `Reporter` which ignores every event.
+    pub fn new(written: Rc<Cell<u64>>) -> Reporter<'a> {
+        Reporter {
+            callback: Box::new(|_: &Progress| {}),
+            written,
+            current: None,
+            chapters: 0,
+            assets: 0,
+        }
+    }
+
+    pub fn set_callback<F>(&mut self, callback: F)
+    where
+        F: FnMut(&Progress) + 'a,
+    {
+        self.callback = Box::new(callback);
+    }
+
+    /// Start a new phase, finishing the current one (if any).
+    pub fn start(&mut self, phase: Phase) {
+        self.finish();
+        self.current = Some((phase, Instant::now()));
+        self.emit(Progress::PhaseStarted(phase));
+    }
+
+    /// Finish the current phase.
+    pub fn finish(&mut self) {
+        if let Some((phase, started)) = self.current.take() {
+            self.emit(Progress::PhaseFinished(phase, started.elapsed()));
+        }
+    }
+
+    pub fn chapter_rendered(&mut self, name: &str, total: usize) {
+        self.chapters += 1;
+        let event = Progress::ChapterRendered {
+            name: name.to_string(),
+            completed: self.chapters,
+            total,
+        };
+
+        self.emit(event);
+        self.bytes_written();
+    }
+
+    pub fn asset_embedded(&mut self, name: &str, total: usize) {
+        self.assets += 1;
+        let event = Progress::AssetEmbedded {
+            name: name.to_string(),
+            completed: self.assets,
+            total,
+        };
+
+        self.emit(event);
+        self.bytes_written();
+    }
+
+    pub fn bytes_written(&mut self) {
+        let written = self.written.get();
+        self.emit(Progress::BytesWritten(written));
+    }
+
+    fn emit(&mut self, progress: Progress) {
+        (self.callback)(&progress);
+    }
+}
+
+impl<'a> Debug for Reporter<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Reporter")
+            .field("written", &self.written)
+            .field("current", &self.current)
+            .field("chapters", &self.chapters)
+            .field("assets", &self.assets)
+            .finish()
+    }
+}
+
+/// A writer which keeps track of how many bytes have passed through it.
+#[derive(Debug)]
+pub struct CountingWriter<W> {
+    inner: W,
+    written: Rc<Cell<u64>>,
+}
+
+impl<W> CountingWriter<W> {
+    pub fn new(inner: W, written: Rc<Cell<u64>>) -> CountingWriter<W> {
+        CountingWriter { inner, written }
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let bytes = self.inner.write(buf)?;
+        self.written.set(self.written.get() + bytes as u64);
+        Ok(bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Seek> Seek for CountingWriter<W> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn count_bytes_written() {
+        let written = Rc::new(Cell::new(0));
+        let mut writer = CountingWriter::new(Vec::new(), Rc::clone(&written));
+
+        writer.write_all(b"Hello, World!").unwrap();
+
+        assert_eq!(written.get(), 13);
+    }
+
+    #[test]
+    fn starting_a_phase_finishes_the_previous_one() {
+        let mut events = Vec::new();
+
+        {
+            let mut reporter = Reporter::new(Rc::new(Cell::new(0)));
+            reporter.set_callback(|progress: &Progress| events.push(progress.clone()));
+            reporter.start(Phase::Chapters);
+            reporter.start(Phase::Assets);
+            reporter.finish();
+        }
+
+        assert_eq!(events.len(), 4);
+        assert_eq!(events[0], Progress::PhaseStarted(Phase::Chapters));
+        match events[1] {
+            Progress::PhaseFinished(Phase::Chapters, _) => {}
+            ref other => panic!("Unexpected event: {:?}", other),
+        }
+        assert_eq!(events[2], Progress::PhaseStarted(Phase::Assets));
+    }
+}
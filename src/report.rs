@@ -0,0 +1,175 @@
+//! A machine-readable record of everything which went into a book, for
+//! downstream tooling which wants to audit the final artifact (e.g. for
+//! license scanning).
+
+use std::fmt::{self, Display, Formatter};
+use std::path::PathBuf;
+
+use accessibility::Audit;
+use credits::Credit;
+use diagnostics::Diagnostic;
+
+/// A book (or one of its translations) which has been generated.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderedBook {
+    /// Where the EPUB was written, or would have been for a dry run.
+    pub path: PathBuf,
+    /// Was this a dry run, so nothing was actually written to disk?
+    pub dry_run: bool,
+    /// Everything which went into the book.
+    pub report: BuildReport,
+}
+
+/// Everything which was packaged into the EPUB.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BuildReport {
+    /// The book's chapters, in reading order.
+    pub chapters: Vec<ChapterReport>,
+    /// Every content document (including generated pages), in reading
+    /// order.
+    pub spine: Vec<String>,
+    /// Every other file included in the book.
+    pub assets: Vec<AssetReport>,
+    /// The package's unique identifier.
+    #[serde(default)]
+    pub identifier: Option<String>,
+    /// Assets which couldn't be packaged and were replaced with a
+    /// placeholder.
+    #[serde(default)]
+    pub failures: Vec<AssetFailure>,
+    /// Every warning or error reported while building the book.
+    #[serde(default)]
+    pub diagnostics: Vec<Diagnostic>,
+    /// Facts used to check the book's accessibility.
+    #[serde(default)]
+    pub accessibility: Audit,
+    /// The cover image, if the book has one.
+    #[serde(default)]
+    pub cover: Option<CoverReport>,
+}
+
+impl Display for BuildReport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        writeln!(f, "Spine:")?;
+        for page in &self.spine {
+            writeln!(f, "    {}", page)?;
+        }
+
+        if let Some(ref cover) = self.cover {
+            match cover.thumbnail {
+                Some(ref thumbnail) => writeln!(
+                    f,
+                    "Cover: {} (thumbnail {})",
+                    cover.path.display(),
+                    thumbnail.display()
+                )?,
+                None => writeln!(f, "Cover: {}", cover.path.display())?,
+            }
+        }
+
+        writeln!(f, "Assets:")?;
+        for asset in &self.assets {
+            writeln!(
+                f,
+                "    {} ({}, {} bytes)",
+                asset.path.display(),
+                asset.mimetype,
+                asset.size
+            )?;
+        }
+
+        if !self.failures.is_empty() {
+            writeln!(f, "Replaced with placeholders:")?;
+            for failure in &self.failures {
+                writeln!(f, "    {} ({})", failure.path.display(), failure.reason)?;
+            }
+        }
+
+        if !self.diagnostics.is_empty() {
+            writeln!(f, "Diagnostics:")?;
+            for diagnostic in &self.diagnostics {
+                writeln!(f, "    {}", diagnostic)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A single chapter.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChapterReport {
+    /// The chapter's name, as it appears in `SUMMARY.md`.
+    pub name: String,
+    /// The chapter's markdown file, relative to the `src/` directory.
+    pub source: PathBuf,
+    /// The content document(s) the chapter was rendered to. Oversized
+    /// chapters may be split across several documents.
+    pub files: Vec<String>,
+    /// How many words the chapter contains.
+    #[serde(default)]
+    pub words: usize,
+    /// Roughly how many minutes it takes to read the chapter.
+    #[serde(default)]
+    pub reading_time: usize,
+}
+
+/// A file which isn't part of the spine (images, fonts, stylesheets, etc.).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AssetReport {
+    /// Where the asset was read from, if it came from a file on disk.
+    pub source: Option<PathBuf>,
+    /// The asset's path inside the EPUB.
+    pub path: PathBuf,
+    pub mimetype: String,
+    /// The asset's size in bytes.
+    pub size: u64,
+    /// The URL the asset was originally retrieved from, if it wasn't a
+    /// local file.
+    pub origin_url: Option<String>,
+    /// Who made the asset and how it's licensed, if known.
+    #[serde(default)]
+    pub credit: Option<Credit>,
+}
+
+/// The book's cover.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CoverReport {
+    /// Where the cover was read from.
+    pub source: PathBuf,
+    /// The cover's path inside the EPUB.
+    pub path: PathBuf,
+    /// The downscaled copy `<meta name="cover">` points to, if one was
+    /// generated.
+    pub thumbnail: Option<PathBuf>,
+}
+
+/// An asset which was replaced with a placeholder because it couldn't be
+/// packaged.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AssetFailure {
+    /// The asset's path inside the EPUB.
+    pub path: PathBuf,
+    /// Where the asset should have been read from.
+    pub source: PathBuf,
+    /// Why it couldn't be packaged.
+    pub reason: String,
+}
+
+impl AssetReport {
+    pub fn new<P, Q, S>(source: Option<P>, path: Q, mimetype: S, size: u64) -> AssetReport
+    where
+        P: Into<PathBuf>,
+        Q: Into<PathBuf>,
+        S: Into<String>,
+    {
+        AssetReport {
+            source: source.map(Into::into),
+            path: path.into(),
+            mimetype: mimetype.into(),
+            size,
+            origin_url: None,
+            credit: None,
+        }
+    }
+}
@@ -1,49 +1,153 @@
 use failure::{self, Error, ResultExt};
-use mdbook::book::BookItem;
+use mdbook::book::{BookItem, Chapter};
 use mdbook::renderer::RenderContext;
 use mime_guess::{self, Mime};
-use pulldown_cmark::{Event, Parser, Tag};
-use std::path::{Path, PathBuf};
+use pulldown_cmark::{Event, Tag};
+use std::borrow::Borrow;
+use std::path::{Component, Path, PathBuf};
+use unicode_normalization::UnicodeNormalization;
+
+use config::Config;
+use filters;
+use front_matter;
+use links;
+use markdown;
+use responsive;
+use sanitize;
+use strings::Strings;
+
+/// Find every local file referenced by the book, that is every image (using
+/// the chosen source for responsive images), any linked files whose
+/// extension is in `package_links` and the stylesheets chapters add in their
+/// front matter.
+///
+/// Chapters go through the same filters and markdown extensions as when the
+/// book is rendered, so this is the same list of files the `Generator`
+/// packages.
+///
+/// When `lenient`, files which don't exist are still returned (so they can be
+/// replaced with a placeholder) instead of being an error.
+///
+/// Files the chapters don't reference (e.g. ones generated by a
+/// preprocessor) can be added with `Generator::with_asset()`.
+pub fn find(ctx: &RenderContext, config: &Config) -> Result<Vec<Asset>, Error> {
+    let filtered;
+    let ctx = if config.filters.is_empty() {
+        ctx
+    } else {
+        filtered = filters::apply(ctx, &config.filters)?;
+        &filtered
+    };
 
-pub fn find(ctx: &RenderContext) -> Result<Vec<Asset>, Error> {
     let mut assets = Vec::new();
+    let src_dir = src_dir(ctx)?;
+    let strings = Strings::new(config.language.as_deref(), &config.strings)?;
+
+    for section in ctx.book.iter() {
+        if let BookItem::Chapter(ref ch) = *section {
+            trace!("Searching {} for links and assets", ch);
+            let (front_matter, text) = front_matter::split(&ch.content)
+                .with_context(|_| format!("Unable to read \"{}\"'s front matter", ch))?;
+            for css in &front_matter.epub_css {
+                assets.push(chapter_stylesheet(&src_dir, ch, css)?);
+            }
+
+            let events = markdown::events(&text, &config.markdown, &strings);
+            assets.extend(chapter_assets(&src_dir, &ch.path, &events, config)?);
+        }
+    }
+
+    Ok(assets)
+}
+
+/// A stylesheet a chapter adds with `epub-css`, relative to the chapter.
+pub fn chapter_stylesheet(src_dir: &Path, ch: &Chapter, css: &str) -> Result<Asset, Error> {
+    let chapter_dir = ch.path.parent().unwrap_or_else(|| Path::new(""));
+    let location = src_dir.join(chapter_dir).join(css);
+    let location = location.canonicalize().with_context(|_| {
+        format!(
+            "Unable to find \"{}\"'s stylesheet, {}",
+            ch,
+            location.display()
+        )
+    })?;
+    let filename = match location.strip_prefix(src_dir) {
+        Ok(relative) => links::nfc_path(relative),
+        Err(_) => {
+            return Err(failure::err_msg(format!(
+                "\"{}\"'s stylesheet must be inside the src directory, {}",
+                ch,
+                location.display()
+            )))
+        }
+    };
+
+    Ok(Asset::new(filename, &location))
+}
+
+/// The book's canonicalized `src/` directory.
+pub fn src_dir(ctx: &RenderContext) -> Result<PathBuf, Error> {
     let src_dir = ctx
         .root
         .join(&ctx.config.book.src)
         .canonicalize()
         .context("Unable to canonicalize the src directory")?;
 
-    for section in ctx.book.iter() {
-        if let BookItem::Chapter(ref ch) = *section {
-            trace!("Searching {} for links and assets", ch);
+    Ok(src_dir)
+}
 
-            let full_path = src_dir.join(&ch.path);
-            let parent = full_path
-                .parent()
-                .expect("All book chapters have a parent directory");
-            let found = assets_in_markdown(&ch.content, parent)?;
+/// Find the assets referenced by a single chapter, whose `path` is relative
+/// to `src_dir`, from its parsed markdown.
+///
+/// The `Generator` passes in the events it's about to render, so the book
+/// doesn't need to be parsed a second time just to find its assets.
+pub fn chapter_assets<'e, I>(
+    src_dir: &Path,
+    path: &Path,
+    events: I,
+    config: &Config,
+) -> Result<Vec<Asset>, Error>
+where
+    I: IntoIterator,
+    I::Item: Borrow<Event<'e>>,
+{
+    let full_path = src_dir.join(path);
+    let parent = full_path
+        .parent()
+        .expect("All book chapters have a parent directory");
+    let found = assets_in_events(events, parent, config)?;
 
-            for full_filename in found {
-                let relative = full_filename.strip_prefix(&src_dir).unwrap();
-                assets.push(Asset::new(relative, &full_filename));
+    let mut assets = Vec::new();
+    for full_filename in found {
+        let relative = match full_filename.strip_prefix(src_dir) {
+            Ok(relative) => relative,
+            Err(_) => {
+                return Err(failure::err_msg(format!(
+                    "Assets must be inside the src directory, {}",
+                    full_filename.display()
+                )))
             }
-        }
+        };
+        assets.push(Asset::new(links::nfc_path(relative), &full_filename));
     }
 
     Ok(assets)
 }
 
+/// A file to be packaged into the book.
 #[derive(Clone, PartialEq, Debug)]
 pub struct Asset {
     /// The asset's absolute location on disk.
     pub location_on_disk: PathBuf,
-    /// The asset's filename relative to the `src/` directory.
+    /// The asset's filename relative to the `src/` directory, which is also
+    /// its path inside the EPUB.
     pub filename: PathBuf,
     pub mimetype: Mime,
 }
 
 impl Asset {
-    fn new<P, Q>(filename: P, absolute_location: Q) -> Asset
+    /// Create an asset, guessing its media type from the file extension.
+    pub fn new<P, Q>(filename: P, absolute_location: Q) -> Asset
     where
         P: Into<PathBuf>,
         Q: Into<PathBuf>,
@@ -57,17 +161,59 @@ impl Asset {
             mimetype: mt,
         }
     }
+
+    /// Use a particular media type instead of the guessed one.
+    pub fn with_mimetype(mut self, mimetype: Mime) -> Asset {
+        self.mimetype = mimetype;
+        self
+    }
+
+    /// Is the asset's filename a relative path which stays inside the book?
+    pub fn is_contained(&self) -> bool {
+        self.filename
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+    }
 }
 
-fn assets_in_markdown(src: &str, parent_dir: &Path) -> Result<Vec<PathBuf>, Error> {
+fn assets_in_events<'e, I>(
+    events: I,
+    parent_dir: &Path,
+    config: &Config,
+) -> Result<Vec<PathBuf>, Error>
+where
+    I: IntoIterator,
+    I::Item: Borrow<Event<'e>>,
+{
     let mut found = Vec::new();
+    let mut raw_html = String::new();
 
-    for event in Parser::new(src) {
-        if let Event::Start(Tag::Image(dest, _)) = event {
-            found.push(dest.into_owned());
+    for event in events {
+        match *event.borrow() {
+            Event::Start(Tag::Image(_, ref dest, _)) if !links::is_external(dest) => {
+                found.push(dest.to_string());
+            }
+            Event::Start(Tag::Link(_, ref dest, _))
+                if links::is_packaged(dest, &config.package_links) =>
+            {
+                found.push(dest.to_string());
+            }
+            Event::Html(ref html) | Event::InlineHtml(ref html) => raw_html.push_str(html),
+            _ => {}
         }
     }
 
+    let width = config
+        .assets
+        .image_width
+        .unwrap_or(responsive::DEFAULT_IMAGE_WIDTH);
+    found.extend(
+        responsive::chosen_sources(&raw_html, width)
+            .into_iter()
+            .filter(|source| !links::is_external(source)),
+    );
+    found.extend(sanitize::screenshots(&raw_html));
+
     // TODO: Allow linked images to be either a URL or path on disk
 
     // I'm assuming you'd just determine if each link is a URL or filename so
@@ -79,8 +225,13 @@ fn assets_in_markdown(src: &str, parent_dir: &Path) -> Result<Vec<PathBuf>, Erro
     let mut assets = Vec::new();
 
     for link in found {
-        let link = PathBuf::from(link);
-        let filename = parent_dir.join(link);
+        let filename = resolve(parent_dir, &link);
+        if config.lenient && !filename.is_file() {
+            warn!("Unable to find {}", filename.display());
+            assets.push(clean(&filename));
+            continue;
+        }
+
         let filename = filename.canonicalize().with_context(|_| {
             format!(
                 "Unable to fetch the canonical path for {}",
@@ -101,9 +252,49 @@ fn assets_in_markdown(src: &str, parent_dir: &Path) -> Result<Vec<PathBuf>, Erro
     Ok(assets)
 }
 
+/// Figure out which file on disk a (possibly percent-encoded) link refers to.
+///
+/// Filenames may be stored in either composed or decomposed form depending on
+/// the filesystem, so we try both before falling back to the link as written.
+fn resolve(parent_dir: &Path, link: &str) -> PathBuf {
+    let decoded = links::decode(link);
+    let candidates = [
+        decoded.clone(),
+        links::nfc(&decoded),
+        decoded.nfd().collect(),
+        link.to_string(),
+    ];
+
+    candidates
+        .iter()
+        .map(|candidate| parent_dir.join(candidate))
+        .find(|path| path.exists())
+        .unwrap_or_else(|| parent_dir.join(decoded))
+}
+
+/// Remove any `.` and `..` components from a path without touching the
+/// filesystem.
+fn clean(path: &Path) -> PathBuf {
+    let mut cleaned = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                cleaned.pop();
+            }
+            other => cleaned.push(other.as_os_str()),
+        }
+    }
+
+    cleaned
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use config::AssetConfig;
+    use pulldown_cmark::Parser;
 
     #[test]
     fn find_images() {
@@ -115,8 +306,88 @@ mod tests {
             parent_dir.join("reddit.svg").canonicalize().unwrap(),
         ];
 
-        let got = assets_in_markdown(src, &parent_dir).unwrap();
+        let got = assets_in_events(Parser::new(src), &parent_dir, &Config::default()).unwrap();
+
+        assert_eq!(got, should_be);
+    }
+
+    #[test]
+    fn linked_files_in_the_allowlist_are_found() {
+        let parent_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/dummy/src");
+        let src = "[the logo](./rust-logo.png) [a vector](reddit.svg) [elsewhere](chapter_1.md)";
+        let should_be = vec![parent_dir.join("rust-logo.png").canonicalize().unwrap()];
+
+        let config = Config {
+            package_links: vec![String::from("png")],
+            ..Default::default()
+        };
+
+        let got = assets_in_events(Parser::new(src), &parent_dir, &config).unwrap();
+
+        assert_eq!(got, should_be);
+    }
+
+    #[test]
+    fn percent_encoded_images_are_decoded() {
+        let parent_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/dummy/src");
+        let src = "![Logo](./rust%20logo%20%C3%A9.png) ![Remote](https://example.com/a.png)";
+        let should_be = vec![parent_dir
+            .join("rust logo \u{e9}.png")
+            .canonicalize()
+            .unwrap()];
+
+        let got = assets_in_events(Parser::new(src), &parent_dir, &Config::default()).unwrap();
+
+        assert_eq!(got, should_be);
+    }
+
+    #[test]
+    fn missing_images_are_placeholders_when_lenient() {
+        let parent_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/dummy/src");
+        let src = "![Gone](./images/../missing.png)";
+
+        assert!(assets_in_events(Parser::new(src), &parent_dir, &Config::default()).is_err());
+
+        let config = Config {
+            lenient: true,
+            ..Default::default()
+        };
+        let got = assets_in_events(Parser::new(src), &parent_dir, &config).unwrap();
+
+        assert_eq!(got, vec![parent_dir.join("missing.png")]);
+    }
+
+    #[test]
+    fn only_the_chosen_responsive_image_is_found() {
+        let parent_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/dummy/src");
+        let src = "<picture>\n<source srcset=\"missing.png 2400w, rust-logo.png 600w\">\n\
+                   <img src=\"reddit.svg\" alt=\"\">\n</picture>\n";
+        let config = Config {
+            assets: AssetConfig {
+                image_width: Some(500),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let should_be = vec![parent_dir.join("rust-logo.png").canonicalize().unwrap()];
+
+        let got = assets_in_events(Parser::new(src), &parent_dir, &config).unwrap();
 
         assert_eq!(got, should_be);
     }
+
+    #[test]
+    fn extra_assets_must_stay_inside_the_book() {
+        assert!(Asset::new("generated/plot.svg", "/tmp/plot.svg").is_contained());
+        assert!(!Asset::new("../plot.svg", "/tmp/plot.svg").is_contained());
+        assert!(!Asset::new("/plot.svg", "/tmp/plot.svg").is_contained());
+    }
+
+    #[test]
+    fn media_types_can_be_overridden() {
+        let asset = Asset::new("data/results.bin", "/tmp/results.bin")
+            .with_mimetype("application/vnd.example+json".parse().unwrap());
+
+        assert_eq!(asset.mimetype.to_string(), "application/vnd.example+json");
+    }
 }
@@ -0,0 +1,424 @@
+//! Responsive images (`<picture>` and `srcset`) in a chapter's raw HTML.
+//!
+//! Reading systems handle responsive images poorly and every alternate just
+//! bloats the book, so each one is collapsed to a plain `<img>` using the
+//! single source which best fits the target width.
+
+/// The width used to pick between sources when `output.epub.assets.image-width`
+/// isn't set. Roughly the resolution of a modern e-reader.
+pub const DEFAULT_IMAGE_WIDTH: u32 = 1200;
+
+/// The image types every EPUB reading system supports.
+const CORE_IMAGE_TYPES: &[&str] = &["image/gif", "image/jpeg", "image/png", "image/svg+xml"];
+
+/// Replace every `<picture>` element and `<img srcset>` with a plain `<img>`.
+///
+/// Most chapters don't have any, so they're handed back without being copied.
+pub fn collapse(html: String, width: u32) -> String {
+    let images = responsive_images(&html, width);
+    if images.is_empty() {
+        return html;
+    }
+
+    let mut collapsed = String::with_capacity(html.len());
+    let mut offset = 0;
+
+    for image in images {
+        collapsed.push_str(&html[offset..image.start]);
+        collapsed.push_str(&image.replacement);
+        offset = image.end;
+    }
+    collapsed.push_str(&html[offset..]);
+
+    collapsed
+}
+
+/// The source which will be kept for each responsive image.
+pub fn chosen_sources(html: &str, width: u32) -> Vec<String> {
+    responsive_images(html, width)
+        .into_iter()
+        .map(|image| image.source)
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct ResponsiveImage {
+    start: usize,
+    end: usize,
+    source: String,
+    replacement: String,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Descriptor {
+    Width(u32),
+    Density(f32),
+}
+
+fn responsive_images(html: &str, width: u32) -> Vec<ResponsiveImage> {
+    let mut images = Vec::new();
+    let mut offset = 0;
+
+    loop {
+        let picture = find_tag(html, offset, "picture");
+        let img = find_tag(html, offset, "img");
+
+        let image = match (picture, img) {
+            (Some(p), Some(i)) if p < i => picture_element(html, p, width),
+            (Some(p), None) => picture_element(html, p, width),
+            (_, Some(i)) => img_element(html, i, width),
+            (None, None) => break,
+        };
+
+        match image {
+            Ok(image) => {
+                offset = image.end;
+                images.push(image);
+            }
+            Err(end) => offset = end,
+        }
+    }
+
+    images
+}
+
+/// Collapse a `<picture>`, returning where to carry on looking if it can't
+/// be.
+fn picture_element(html: &str, start: usize, width: u32) -> Result<ResponsiveImage, usize> {
+    let open_end = end_of_tag(html, start).ok_or(html.len())?;
+    let end = match find_closing_tag(html, open_end, "picture") {
+        Some(ix) => ix + "</picture>".len(),
+        None => return Err(open_end),
+    };
+    let inner = &html[open_end..end];
+
+    let mut candidates = Vec::new();
+    let mut img = None;
+    let mut offset = 0;
+
+    while let Some(tag_start) = inner[offset..].find('<').map(|ix| ix + offset) {
+        let tag_end = match end_of_tag(inner, tag_start) {
+            Some(tag_end) => tag_end,
+            None => break,
+        };
+        let tag = &inner[tag_start..tag_end];
+
+        match tag_name(tag).to_lowercase().as_str() {
+            "source" => {
+                let attributes = attributes(tag);
+                let supported = attribute(&attributes, "type")
+                    .is_none_or(|mime| CORE_IMAGE_TYPES.contains(&mime));
+                if supported {
+                    if let Some(srcset) = attribute(&attributes, "srcset") {
+                        candidates.extend(parse_srcset(srcset));
+                    }
+                }
+            }
+            "img" => img = Some(attributes(tag)),
+            _ => {}
+        }
+
+        offset = tag_end;
+    }
+
+    let img = match img {
+        Some(img) => img,
+        None => return Err(end),
+    };
+    candidates.extend(img_candidates(&img));
+
+    let source = choose(&candidates, width).ok_or(end)?;
+    Ok(ResponsiveImage {
+        start,
+        end,
+        replacement: plain_img(&img, &source),
+        source,
+    })
+}
+
+/// Collapse an `<img>` with a `srcset`.
+fn img_element(html: &str, start: usize, width: u32) -> Result<ResponsiveImage, usize> {
+    let end = end_of_tag(html, start).ok_or(html.len())?;
+    let attributes = attributes(&html[start..end]);
+
+    if attribute(&attributes, "srcset").is_none() {
+        return Err(end);
+    }
+
+    let source = choose(&img_candidates(&attributes), width).ok_or(end)?;
+    Ok(ResponsiveImage {
+        start,
+        end,
+        replacement: plain_img(&attributes, &source),
+        source,
+    })
+}
+
+fn img_candidates(attributes: &[(String, String)]) -> Vec<(String, Descriptor)> {
+    let mut candidates = Vec::new();
+
+    if let Some(srcset) = attribute(attributes, "srcset") {
+        candidates.extend(parse_srcset(srcset));
+    }
+    if let Some(src) = attribute(attributes, "src") {
+        candidates.push((src.to_string(), Descriptor::Density(1.0)));
+    }
+
+    candidates
+}
+
+/// Pick the smallest source which is at least as wide as the target, or the
+/// widest one if they're all too small. When there aren't any widths, the
+/// 1x source (or the lowest density) is used.
+fn choose(candidates: &[(String, Descriptor)], width: u32) -> Option<String> {
+    let mut widths: Vec<(&str, u32)> = candidates
+        .iter()
+        .filter_map(|&(ref url, descriptor)| match descriptor {
+            Descriptor::Width(w) => Some((url.as_str(), w)),
+            Descriptor::Density(_) => None,
+        })
+        .collect();
+    widths.sort_by_key(|&(_, w)| w);
+
+    if !widths.is_empty() {
+        let chosen = widths
+            .iter()
+            .find(|&&(_, w)| w >= width)
+            .or_else(|| widths.last())
+            .map(|&(url, _)| url.to_string());
+        return chosen;
+    }
+
+    candidates
+        .iter()
+        .filter_map(|&(ref url, descriptor)| match descriptor {
+            Descriptor::Density(d) => Some((url, d)),
+            Descriptor::Width(_) => None,
+        })
+        .min_by(|a, b| {
+            let a = (a.1 - 1.0).abs();
+            let b = (b.1 - 1.0).abs();
+            a.partial_cmp(&b).unwrap_or(::std::cmp::Ordering::Equal)
+        })
+        .map(|(url, _)| url.clone())
+}
+
+fn parse_srcset(srcset: &str) -> Vec<(String, Descriptor)> {
+    srcset
+        .split(',')
+        .filter_map(|candidate| {
+            let mut words = candidate.split_whitespace();
+            let url = words.next()?;
+            let descriptor = match words.next() {
+                Some(d) if d.ends_with('w') => Descriptor::Width(d[..d.len() - 1].parse().ok()?),
+                Some(d) if d.ends_with('x') => Descriptor::Density(d[..d.len() - 1].parse().ok()?),
+                Some(_) => return None,
+                None => Descriptor::Density(1.0),
+            };
+
+            Some((url.to_string(), descriptor))
+        })
+        .collect()
+}
+
+/// An `<img>` with the same attributes, but a single `src`.
+fn plain_img(attributes: &[(String, String)], src: &str) -> String {
+    let mut img = format!("<img src=\"{}\"", src.replace('"', "&quot;"));
+
+    for (name, value) in attributes {
+        if name == "src" || name == "srcset" || name == "sizes" {
+            continue;
+        }
+        img.push_str(&format!(" {}=\"{}\"", name, value.replace('"', "&quot;")));
+    }
+
+    img.push_str(" />");
+    img
+}
+
+/// Find the next `<name` tag at or after `offset`, ignoring case.
+pub fn find_tag(html: &str, offset: usize, name: &str) -> Option<usize> {
+    let mut offset = offset;
+
+    while let Some(start) = find_ignoring_case(html, offset, &format!("<{}", name)) {
+        let after = start + name.len() + 1;
+        match html[after..].chars().next() {
+            Some(c) if c.is_whitespace() || c == '>' || c == '/' => return Some(start),
+            None => return None,
+            _ => offset = after,
+        }
+    }
+
+    None
+}
+
+/// Find the next `</name>` at or after `offset`, ignoring case.
+pub fn find_closing_tag(html: &str, offset: usize, name: &str) -> Option<usize> {
+    find_ignoring_case(html, offset, &format!("</{}>", name))
+}
+
+fn find_ignoring_case(html: &str, offset: usize, needle: &str) -> Option<usize> {
+    let needle = needle.as_bytes();
+
+    html.as_bytes()[offset..]
+        .windows(needle.len())
+        .position(|window| window.eq_ignore_ascii_case(needle))
+        .map(|ix| offset + ix)
+}
+
+/// Where the tag starting at `start` ends, just after its `>`. A `>` inside
+/// a quoted attribute value doesn't end the tag.
+pub fn end_of_tag(html: &str, start: usize) -> Option<usize> {
+    let mut quote = None;
+    // quotes only delimit a value straight after its `=`
+    let mut after_equals = false;
+
+    for (ix, c) in html[start..].char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => continue,
+            None if c == '>' => return Some(start + ix + 1),
+            None if after_equals && (c == '"' || c == '\'') => {
+                quote = Some(c);
+                continue;
+            }
+            None => {}
+        }
+
+        if !c.is_whitespace() {
+            after_equals = c == '=';
+        }
+    }
+
+    None
+}
+
+fn tag_name(tag: &str) -> &str {
+    let tag = tag.trim_start_matches('<');
+    let end = tag
+        .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+        .unwrap_or(tag.len());
+    &tag[..end]
+}
+
+/// Look up an attribute parsed by `attributes()`.
+pub fn attribute<'a>(attributes: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    attributes
+        .iter()
+        .find(|&(n, _)| n == name)
+        .map(|(_, value)| value.as_str())
+}
+
+/// Parse a tag's attributes, in order.
+pub fn attributes(tag: &str) -> Vec<(String, String)> {
+    let mut attributes = Vec::new();
+    let name_len = tag_name(tag).len() + 1;
+    let mut rest = tag[name_len..].trim_end_matches('>').trim_end_matches('/');
+
+    loop {
+        rest = rest.trim_start();
+        let name_end = rest
+            .find(|c: char| c.is_whitespace() || c == '=')
+            .unwrap_or(rest.len());
+        if name_end == 0 {
+            break;
+        }
+        let name = rest[..name_end].to_lowercase();
+        rest = rest[name_end..].trim_start();
+
+        if !rest.starts_with('=') {
+            attributes.push((name, String::new()));
+            continue;
+        }
+        rest = rest[1..].trim_start();
+
+        let value = match rest.chars().next() {
+            Some(quote) if quote == '"' || quote == '\'' => {
+                let end = rest[1..].find(quote).map_or(rest.len(), |ix| ix + 1);
+                let value = rest[1..end].to_string();
+                rest = &rest[(end + 1).min(rest.len())..];
+                value
+            }
+            _ => {
+                let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                let value = rest[..end].to_string();
+                rest = &rest[end..];
+                value
+            }
+        };
+
+        attributes.push((name, value));
+    }
+
+    attributes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pick_the_smallest_source_which_is_wide_enough() {
+        let html = r#"<img src="small.png" srcset="small.png 480w, medium.png 1024w, large.png 2048w" sizes="100vw" alt="A diagram">"#;
+
+        let got = collapse(html.to_string(), 1000);
+
+        assert_eq!(got, r#"<img src="medium.png" alt="A diagram" />"#);
+    }
+
+    #[test]
+    fn fall_back_to_the_widest_source() {
+        let html = r#"<img srcset="small.png 480w, medium.png 1024w">"#;
+
+        assert_eq!(chosen_sources(html, 4000), vec!["medium.png"]);
+    }
+
+    #[test]
+    fn density_descriptors_prefer_1x() {
+        let html = r#"<img srcset="logo@2x.png 2x, logo.png 1x">"#;
+
+        assert_eq!(chosen_sources(html, 1200), vec!["logo.png"]);
+    }
+
+    #[test]
+    fn pictures_collapse_to_a_single_img() {
+        let html = concat!(
+            "<p>Before</p>\n<picture>\n",
+            "  <source type=\"image/webp\" srcset=\"photo.webp 1200w\">\n",
+            "  <source srcset=\"photo-1200.jpg 1200w, photo-600.jpg 600w\">\n",
+            "  <img src=\"photo-600.jpg\" alt=\"A photo\">\n",
+            "</picture>\n<p>After</p>",
+        );
+
+        let got = collapse(html.to_string(), 1200);
+
+        assert_eq!(
+            got,
+            "<p>Before</p>\n<img src=\"photo-1200.jpg\" alt=\"A photo\" />\n<p>After</p>"
+        );
+    }
+
+    #[test]
+    fn tag_names_are_case_insensitive() {
+        let html = r#"<P><IMG SRC="small.png" SRCSET="small.png 480w, large.png 2048w"></P><Picture><Source srcset="photo.jpg 1200w"><Img src="fallback.jpg"></PICTURE>"#;
+
+        assert_eq!(chosen_sources(html, 1200), vec!["large.png", "photo.jpg"]);
+    }
+
+    #[test]
+    fn quoted_attributes_can_contain_angle_brackets() {
+        let html = r#"<img alt="a > b" srcset="small.png 480w, large.png 2048w">"#;
+
+        let got = collapse(html.to_string(), 1200);
+
+        assert_eq!(got, r#"<img src="large.png" alt="a > b" />"#);
+    }
+
+    #[test]
+    fn plain_images_are_left_alone() {
+        let html = r#"<img src="logo.png" alt="">"#;
+
+        assert_eq!(collapse(html.to_string(), 1200), html);
+        assert!(chosen_sources(html, 1200).is_empty());
+    }
+}
@@ -0,0 +1,277 @@
+//! Replacing elements which don't belong in an EPUB content document.
+//!
+//! Embeds (`<iframe>`) and remote scripts pull in content from the web, which
+//! reading systems won't load, and forms can't be submitted from a book.
+//! Rather than leave a blank hole on the page, each one is swapped for a link
+//! to the original content. An `<iframe>` can provide a local screenshot to
+//! use instead with a `data-screenshot` attribute.
+
+use std::fmt::{self, Display, Formatter};
+
+use links;
+use opf;
+use responsive::{attribute, attributes, end_of_tag, find_closing_tag, find_tag};
+use strings::Strings;
+
+/// The elements which get replaced.
+const FORBIDDEN: &[&str] = &["iframe", "form", "script"];
+
+/// An element which was replaced.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Substitution {
+    pub element: &'static str,
+    /// Where the element pointed to (an `<iframe>`'s `src`, a form's
+    /// `action`, etc.), if anywhere.
+    pub target: Option<String>,
+}
+
+impl Display for Substitution {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.target {
+            Some(ref target) => write!(f, "<{}> ({})", self.element, target),
+            None => write!(f, "<{}>", self.element),
+        }
+    }
+}
+
+/// Replace every forbidden element, returning the sanitized document and what
+/// was replaced.
+///
+/// Documents without any of the elements are handed back without being
+/// copied.
+pub fn sanitize(html: String, strings: &Strings) -> (String, Vec<Substitution>) {
+    if FORBIDDEN
+        .iter()
+        .all(|&name| find_tag(&html, 0, name).is_none())
+    {
+        return (html, Vec::new());
+    }
+
+    let html = html.as_str();
+    let mut sanitized = String::with_capacity(html.len());
+    let mut substitutions = Vec::new();
+    let mut offset = 0;
+
+    loop {
+        let next = FORBIDDEN
+            .iter()
+            .filter_map(|&name| find_tag(html, offset, name).map(|start| (start, name)))
+            .min();
+        let (start, name) = match next {
+            Some(next) => next,
+            None => break,
+        };
+        let open_end = match end_of_tag(html, start) {
+            Some(open_end) => open_end,
+            None => break,
+        };
+        let end = element_end(html, open_end, name);
+        let attributes = attributes(&html[start..open_end]);
+
+        let replacement = match name {
+            "iframe" => {
+                let src = attribute(&attributes, "src").map(String::from);
+                let placeholder = iframe_placeholder(&attributes, src.as_deref(), strings);
+                Some((placeholder, src))
+            }
+            "form" => {
+                let action = attribute(&attributes, "action")
+                    .filter(|action| links::is_external(action))
+                    .map(String::from);
+                Some((form_placeholder(action.as_deref(), strings), action))
+            }
+            // local scripts are handled by `output.epub.scripts`
+            _ => attribute(&attributes, "src")
+                .filter(|src| links::is_external(src))
+                .map(|src| (String::new(), Some(src.to_string()))),
+        };
+
+        sanitized.push_str(&html[offset..start]);
+        match replacement {
+            Some((replacement, target)) => {
+                sanitized.push_str(&replacement);
+                substitutions.push(Substitution {
+                    element: name,
+                    target,
+                });
+            }
+            None => sanitized.push_str(&html[start..end]),
+        }
+        offset = end;
+    }
+
+    sanitized.push_str(&html[offset..]);
+    (sanitized, substitutions)
+}
+
+/// The local screenshot of each `<iframe>` which has one, so they can be
+/// packaged.
+pub fn screenshots(html: &str) -> Vec<String> {
+    let mut found = Vec::new();
+    let mut offset = 0;
+
+    while let Some(start) = find_tag(html, offset, "iframe") {
+        let open_end = match end_of_tag(html, start) {
+            Some(open_end) => open_end,
+            None => break,
+        };
+
+        let attributes = attributes(&html[start..open_end]);
+        if let Some(screenshot) = attribute(&attributes, "data-screenshot") {
+            if !links::is_external(screenshot) {
+                found.push(screenshot.to_string());
+            }
+        }
+
+        offset = open_end;
+    }
+
+    found
+}
+
+/// Where an element which starts at `open_end` finishes, including its
+/// closing tag.
+fn element_end(html: &str, open_end: usize, name: &str) -> usize {
+    if html[..open_end].ends_with("/>") {
+        return open_end;
+    }
+
+    find_closing_tag(html, open_end, name).map_or(open_end, |ix| ix + name.len() + "</>".len())
+}
+
+fn iframe_placeholder(
+    attributes: &[(String, String)],
+    src: Option<&str>,
+    strings: &Strings,
+) -> String {
+    let url = src.map(watch_url);
+    let label = match attribute(attributes, "title") {
+        Some(title) if !title.trim().is_empty() => title.to_string(),
+        _ if url.as_ref().is_some_and(|url| is_video(url)) => {
+            strings.get("watch-video").to_string()
+        }
+        _ => strings.get("view-embed").to_string(),
+    };
+
+    let content = match attribute(attributes, "data-screenshot") {
+        Some(screenshot) => format!(
+            "<img src=\"{}\" alt=\"{}\" />",
+            opf::escape(screenshot),
+            opf::escape(&label)
+        ),
+        None => opf::escape(&label),
+    };
+
+    match url {
+        Some(url) => format!(
+            "<p class=\"embed-placeholder\"><a href=\"{}\">{}</a></p>",
+            opf::escape(&url),
+            content
+        ),
+        None => format!("<p class=\"embed-placeholder\">{}</p>", content),
+    }
+}
+
+fn form_placeholder(action: Option<&str>, strings: &Strings) -> String {
+    let unavailable = opf::escape(strings.get("form-unavailable"));
+
+    match action {
+        Some(action) => format!(
+            "<p class=\"embed-placeholder\">{} <a href=\"{}\">{}</a></p>",
+            unavailable,
+            opf::escape(action),
+            opf::escape(strings.get("open-form"))
+        ),
+        None => format!("<p class=\"embed-placeholder\">{}</p>", unavailable),
+    }
+}
+
+/// Link to a video's own page instead of the embeddable player.
+fn watch_url(src: &str) -> String {
+    let after = |needle: &str| {
+        src.find(needle)
+            .map(|ix| &src[ix + needle.len()..])
+            .map(|rest| rest.split(['?', '#', '/']).next().unwrap_or(rest))
+            .filter(|id| !id.is_empty())
+    };
+
+    if let Some(id) = after("youtube.com/embed/").or_else(|| after("youtube-nocookie.com/embed/")) {
+        format!("https://www.youtube.com/watch?v={}", id)
+    } else if let Some(id) = after("player.vimeo.com/video/") {
+        format!("https://vimeo.com/{}", id)
+    } else if src.starts_with("//") {
+        format!("https:{}", src)
+    } else {
+        src.to_string()
+    }
+}
+
+fn is_video(url: &str) -> bool {
+    url.starts_with("https://www.youtube.com/") || url.starts_with("https://vimeo.com/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn youtube_embeds_become_links() {
+        let html = concat!(
+            "<p>Before</p>",
+            r#"<iframe width="560" height="315" src="https://www.youtube.com/embed/dQw4w9WgXcQ?start=10" allowfullscreen></iframe>"#,
+            "<p>After</p>",
+        );
+
+        let (got, substitutions) = sanitize(html.to_string(), &Strings::default());
+
+        assert_eq!(
+            got,
+            concat!(
+                "<p>Before</p>",
+                r#"<p class="embed-placeholder"><a href="https://www.youtube.com/watch?v=dQw4w9WgXcQ">Watch the video online</a></p>"#,
+                "<p>After</p>",
+            )
+        );
+        assert_eq!(substitutions.len(), 1);
+        assert_eq!(substitutions[0].element, "iframe");
+    }
+
+    #[test]
+    fn iframes_can_use_a_screenshot() {
+        let html = r#"<iframe src="https://example.com/map" title="A map" data-screenshot="images/map.png"></iframe>"#;
+
+        let (got, _) = sanitize(html.to_string(), &Strings::default());
+
+        assert!(got.contains(
+            r#"<a href="https://example.com/map"><img src="images/map.png" alt="A map" /></a>"#
+        ));
+        assert_eq!(screenshots(html), vec!["images/map.png"]);
+    }
+
+    #[test]
+    fn forms_and_remote_scripts_are_removed() {
+        let html = concat!(
+            r#"<form action="https://example.com/signup"><input type="email" /></form>"#,
+            r#"<script src="https://cdn.example.com/lib.js"></script>"#,
+            r#"<script src="quiz.js"></script>"#,
+        );
+
+        let (got, substitutions) = sanitize(html.to_string(), &Strings::default());
+
+        assert!(got.contains(r#"<a href="https://example.com/signup">Open it online</a>"#));
+        assert!(!got.contains("cdn.example.com"));
+        assert!(got.ends_with(r#"<script src="quiz.js"></script>"#));
+        let elements: Vec<_> = substitutions.iter().map(|s| s.element).collect();
+        assert_eq!(elements, vec!["form", "script"]);
+    }
+
+    #[test]
+    fn documents_without_forbidden_elements_are_unchanged() {
+        let html = r#"<p>Nothing to see here, <a href="https://example.com/">move along</a></p>"#;
+
+        let (got, substitutions) = sanitize(html.to_string(), &Strings::default());
+
+        assert_eq!(got, html);
+        assert!(substitutions.is_empty());
+    }
+}
@@ -0,0 +1,258 @@
+//! Interactive (scripted) chapters.
+//!
+//! Interactive content is marked by putting `<!-- interactive -->`,
+//! `<!-- fallback -->` and `<!-- end-interactive -->` on lines of their own.
+//! Everything between the first two markers is only shown by reading systems
+//! which run scripts, and everything between the last two is shown by
+//! everything else.
+
+use pulldown_cmark::{Event, Tag};
+
+use config::ScriptConfig;
+
+const INTERACTIVE: &str = "<!-- interactive -->";
+const FALLBACK: &str = "<!-- fallback -->";
+const END_INTERACTIVE: &str = "<!-- end-interactive -->";
+
+/// Added to the `<head>` of scripted documents so the stylesheet can tell
+/// whether scripts actually run, and hide the interactive content or its
+/// fallback accordingly.
+pub const TOGGLE: &str =
+    "<script type=\"text/javascript\">document.documentElement.className += \" js\";</script>";
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum State {
+    Outside,
+    Interactive,
+    Fallback,
+}
+
+/// Wrap interactive content and its fallback in elements the stylesheet can
+/// toggle between. When scripts are disabled the interactive content is
+/// dropped entirely, leaving only the fallback.
+pub fn transform<'a, I>(events: I, config: &ScriptConfig) -> Vec<Event<'a>>
+where
+    I: IntoIterator<Item = Event<'a>>,
+{
+    let mut state = State::Outside;
+    let mut transformed = Vec::new();
+
+    for event in events {
+        let marker = match event {
+            Event::Html(ref html) => marker(html),
+            _ => None,
+        };
+
+        match (state, marker) {
+            (State::Outside, Some(INTERACTIVE)) => {
+                if config.enabled {
+                    transformed.push(Event::Html("<div class=\"interactive\">\n".into()));
+                }
+                state = State::Interactive;
+            }
+            (State::Interactive, Some(FALLBACK)) => {
+                if config.enabled {
+                    transformed.push(Event::Html("</div>\n".into()));
+                }
+                transformed.push(Event::Html("<div class=\"script-fallback\">\n".into()));
+                state = State::Fallback;
+            }
+            (State::Interactive, Some(END_INTERACTIVE)) => {
+                if config.enabled {
+                    transformed.push(Event::Html("</div>\n".into()));
+                }
+                default_fallback(&mut transformed, &config.fallback);
+                state = State::Outside;
+            }
+            (State::Fallback, Some(END_INTERACTIVE)) => {
+                transformed.push(Event::Html("</div>\n".into()));
+                state = State::Outside;
+            }
+            (State::Interactive, _) if !config.enabled => {}
+            (_, _) => transformed.push(event),
+        }
+    }
+
+    match state {
+        State::Outside => {}
+        State::Interactive => {
+            warn!(
+                "Interactive content is missing its {} marker",
+                END_INTERACTIVE
+            );
+            if config.enabled {
+                transformed.push(Event::Html("</div>\n".into()));
+            }
+            default_fallback(&mut transformed, &config.fallback);
+        }
+        State::Fallback => {
+            warn!(
+                "Interactive content is missing its {} marker",
+                END_INTERACTIVE
+            );
+            transformed.push(Event::Html("</div>\n".into()));
+        }
+    }
+
+    transformed
+}
+
+fn marker(html: &str) -> Option<&'static str> {
+    let html = html.trim();
+
+    [INTERACTIVE, FALLBACK, END_INTERACTIVE]
+        .iter()
+        .cloned()
+        .find(|marker| html == *marker)
+}
+
+fn default_fallback<'a>(events: &mut Vec<Event<'a>>, text: &str) {
+    events.push(Event::Html("<div class=\"script-fallback\">\n".into()));
+    events.push(Event::Start(Tag::Paragraph));
+    events.push(Event::Text(text.to_string().into()));
+    events.push(Event::End(Tag::Paragraph));
+    events.push(Event::Html("</div>\n".into()));
+}
+
+/// Does this document contain any `<script>` elements?
+pub fn contains_scripts(html: &str) -> bool {
+    html.contains("<script")
+}
+
+/// The `src` of every external script referenced by a document.
+pub fn sources(html: &str) -> Vec<String> {
+    script_elements(html)
+        .into_iter()
+        .filter_map(|(start, end)| src_attribute(&html[start..end]))
+        .collect()
+}
+
+/// Remove every `<script>` element from a document.
+pub fn strip(html: &str) -> String {
+    let mut stripped = String::with_capacity(html.len());
+    let mut offset = 0;
+
+    for (start, end) in script_elements(html) {
+        stripped.push_str(&html[offset..start]);
+        offset = end;
+    }
+    stripped.push_str(&html[offset..]);
+
+    stripped
+}
+
+/// The byte range of each `<script>` element in a document.
+fn script_elements(html: &str) -> Vec<(usize, usize)> {
+    let mut elements = Vec::new();
+    let mut offset = 0;
+
+    while let Some(start) = html[offset..].find("<script").map(|ix| ix + offset) {
+        let open_end = match html[start..].find('>') {
+            Some(ix) => start + ix + 1,
+            None => break,
+        };
+
+        let end = if html[..open_end].ends_with("/>") {
+            open_end
+        } else {
+            match html[open_end..].find("</script>") {
+                Some(ix) => open_end + ix + "</script>".len(),
+                None => html.len(),
+            }
+        };
+
+        elements.push((start, end));
+        offset = end;
+    }
+
+    elements
+}
+
+fn src_attribute(element: &str) -> Option<String> {
+    let open_tag = &element[..element.find('>').unwrap_or(element.len())];
+
+    for quote in &['"', '\''] {
+        let needle = format!("src={}", quote);
+        if let Some(start) = open_tag.find(&needle) {
+            let rest = &open_tag[start + needle.len()..];
+            if let Some(end) = rest.find(*quote) {
+                return Some(rest[..end].to_string());
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::{html, Parser};
+
+    fn render(src: &str, enabled: bool) -> String {
+        let config = ScriptConfig {
+            enabled,
+            ..Default::default()
+        };
+        let mut buffer = String::new();
+        html::push_html(
+            &mut buffer,
+            transform(Parser::new(src), &config).into_iter(),
+        );
+        buffer
+    }
+
+    const QUIZ: &str = "<!-- interactive -->\n\n<div id=\"quiz\"></div>\n\n\
+                        <!-- fallback -->\n\nThe answer is 42.\n\n<!-- end-interactive -->\n";
+
+    #[test]
+    fn interactive_content_and_fallbacks_are_wrapped() {
+        let got = render(QUIZ, true);
+
+        assert!(got.contains("<div class=\"interactive\">"));
+        assert!(got.contains("<div id=\"quiz\">"));
+        assert!(got.contains("<div class=\"script-fallback\">"));
+        assert!(got.contains("The answer is 42."));
+        assert!(!got.contains("<!--"));
+    }
+
+    #[test]
+    fn only_the_fallback_is_kept_without_scripts() {
+        let got = render(QUIZ, false);
+
+        assert!(!got.contains("interactive"));
+        assert!(!got.contains("quiz"));
+        assert!(got.contains("The answer is 42."));
+    }
+
+    #[test]
+    fn missing_fallbacks_use_the_default() {
+        let src = "<!-- interactive -->\n\n<div id=\"quiz\"></div>\n\n<!-- end-interactive -->\n";
+
+        let got = render(src, true);
+
+        assert!(got.contains(&ScriptConfig::default().fallback));
+    }
+
+    #[test]
+    fn find_script_sources() {
+        let src = concat!(
+            "<script src=\"quiz.js\"></script>\n",
+            "<script type=\"text/javascript\">var x = 1 < 2;</script>\n",
+            "<script src='js/toggle.js'/>",
+        );
+
+        let got = sources(src);
+
+        assert_eq!(got, vec!["quiz.js", "js/toggle.js"]);
+    }
+
+    #[test]
+    fn scripts_are_stripped() {
+        let src = "<p>Before</p><script src=\"quiz.js\"></script><p>After</p><script/>";
+
+        let got = strip(src);
+
+        assert_eq!(got, "<p>Before</p><p>After</p>");
+    }
+}
@@ -0,0 +1,96 @@
+//! Structural semantics (preface, appendix, etc.) chapters can declare with
+//! `epub-type` in their front matter or `output.epub.chapter-types`.
+
+use epub_builder::ReferenceType;
+
+/// Which part of the book a kind of chapter normally belongs to.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Group {
+    Front,
+    Body,
+    Back,
+}
+
+/// What a particular `epub:type` means for the rest of the book.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Semantic {
+    /// The `epub:type` from the EPUB Structural Semantics Vocabulary.
+    pub epub_type: &'static str,
+    /// The equivalent DPUB-ARIA role.
+    pub role: &'static str,
+    pub group: Group,
+}
+
+const SEMANTICS: &[(&str, &str, Group)] = &[
+    ("acknowledgments", "doc-acknowledgments", Group::Front),
+    ("afterword", "doc-afterword", Group::Back),
+    ("appendix", "doc-appendix", Group::Back),
+    ("bibliography", "doc-bibliography", Group::Back),
+    ("chapter", "doc-chapter", Group::Body),
+    ("colophon", "doc-colophon", Group::Back),
+    ("conclusion", "doc-conclusion", Group::Body),
+    ("dedication", "doc-dedication", Group::Front),
+    ("epigraph", "doc-epigraph", Group::Front),
+    ("epilogue", "doc-epilogue", Group::Body),
+    ("foreword", "doc-foreword", Group::Front),
+    ("glossary", "doc-glossary", Group::Back),
+    ("index", "doc-index", Group::Back),
+    ("introduction", "doc-introduction", Group::Front),
+    ("part", "doc-part", Group::Body),
+    ("preface", "doc-preface", Group::Front),
+    ("prologue", "doc-prologue", Group::Body),
+];
+
+/// Look up a supported `epub:type`.
+pub fn lookup(epub_type: &str) -> Option<Semantic> {
+    SEMANTICS
+        .iter()
+        .find(|&&(name, _, _)| name == epub_type)
+        .map(|&(epub_type, role, group)| Semantic {
+            epub_type,
+            role,
+            group,
+        })
+}
+
+/// The `epub:type`s chapters may declare.
+pub fn known_types() -> Vec<&'static str> {
+    SEMANTICS.iter().map(|&(name, _, _)| name).collect()
+}
+
+impl Semantic {
+    /// The landmark (and EPUB 2 guide reference) this kind of chapter gets,
+    /// if any.
+    pub fn reference_type(&self) -> Option<ReferenceType> {
+        let reference = match self.epub_type {
+            "acknowledgments" => ReferenceType::Acknowledgements,
+            "bibliography" => ReferenceType::Bibliography,
+            "colophon" => ReferenceType::Colophon,
+            "dedication" => ReferenceType::Dedication,
+            "epigraph" => ReferenceType::Epigraph,
+            "foreword" => ReferenceType::Foreword,
+            "glossary" => ReferenceType::Glossary,
+            "index" => ReferenceType::Index,
+            "preface" => ReferenceType::Preface,
+            _ => return None,
+        };
+
+        Some(reference)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn look_up_semantics() {
+        let got = lookup("appendix").unwrap();
+
+        assert_eq!(got.role, "doc-appendix");
+        assert_eq!(got.group, Group::Back);
+        assert!(got.reference_type().is_none());
+        assert!(lookup("preface").unwrap().reference_type().is_some());
+        assert!(lookup("not-a-type").is_none());
+    }
+}
@@ -0,0 +1,125 @@
+//! Post-build steps for release pipelines: checksums and signing.
+
+use failure::{self, Error, ResultExt};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use std::process::Command;
+
+use checksum_filename;
+use config::SignConfig;
+
+/// The placeholder in `output.epub.sign.command` which is replaced with the
+/// book's path.
+const EPUB_PLACEHOLDER: &str = "{epub}";
+
+/// Run every post-build step the book asks for.
+pub fn run(config: &SignConfig, epub: &Path) -> Result<(), Error> {
+    if config.checksum {
+        let filename = checksum_filename(epub);
+        debug!("Writing the checksum to {}", filename.display());
+
+        let line = format!("{}  {}\n", sha256(epub)?, file_name(epub));
+        fs::write(&filename, line)
+            .with_context(|_| format!("Unable to write {}", filename.display()))?;
+    }
+
+    if !config.command.is_empty() {
+        sign(&config.command, epub)?;
+    }
+
+    Ok(())
+}
+
+/// Calculate a file's SHA-256 hash, as a hex string.
+pub fn sha256(path: &Path) -> Result<String, Error> {
+    let mut f = File::open(path).with_context(|_| format!("Unable to open {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0; 8192];
+
+    loop {
+        let bytes_read = f.read(&mut buffer)?;
+        if bytes_read == 0 {
+            break;
+        }
+        hasher.input(&buffer[..bytes_read]);
+    }
+
+    Ok(hasher
+        .result()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+fn sign(command: &[String], epub: &Path) -> Result<(), Error> {
+    let path = epub.display().to_string();
+    let mut args: Vec<String> = command
+        .iter()
+        .map(|arg| arg.replace(EPUB_PLACEHOLDER, &path))
+        .collect();
+    if !command.iter().any(|arg| arg.contains(EPUB_PLACEHOLDER)) {
+        args.push(path);
+    }
+
+    debug!("Signing the book with {:?}", args);
+    let status = Command::new(&args[0])
+        .args(&args[1..])
+        .status()
+        .with_context(|_| format!("Unable to run the signing command, {}", args[0]))?;
+
+    if !status.success() {
+        return Err(failure::err_msg(format!(
+            "The signing command ({}) failed with {}",
+            args[0], status
+        )));
+    }
+
+    Ok(())
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn known_checksum() {
+        let temp = TempDir::new("mdbook-epub").unwrap();
+        let path = temp.path().join("book.epub");
+        fs::write(&path, "hello world").unwrap();
+
+        let got = sha256(&path).unwrap();
+
+        assert_eq!(
+            got,
+            "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9"
+        );
+    }
+
+    #[test]
+    fn checksums_are_written_next_to_the_book() {
+        let temp = TempDir::new("mdbook-epub").unwrap();
+        let path = temp.path().join("book.epub");
+        fs::write(&path, "hello world").unwrap();
+        let config = SignConfig {
+            checksum: true,
+            ..Default::default()
+        };
+
+        run(&config, &path).unwrap();
+
+        let got = fs::read_to_string(temp.path().join("book.epub.sha256")).unwrap();
+        assert_eq!(
+            got,
+            "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9  book.epub\n"
+        );
+    }
+}
@@ -0,0 +1,360 @@
+//! Splitting oversized chapters into several smaller content documents.
+//!
+//! Some readers (notably Adobe Digital Editions and older Kindles) choke on
+//! content documents larger than about 260KB, so when a chapter's rendered
+//! HTML exceeds the configured limit it gets broken up at top-level heading
+//! boundaries. Links to an anchor in another part of the same chapter are
+//! rewritten to point at the right document.
+//!
+//! Links from other chapters can't be fixed until the chapter they point into
+//! has been rendered, so they're fixed up while the archive is finished
+//! instead (see `link_across_chapters()`).
+
+use pulldown_cmark::{html, Event, Tag};
+use std::collections::HashMap;
+use std::mem;
+use std::path::Path;
+
+use collisions;
+use links;
+
+/// The file each anchor in the later parts of a split chapter ended up in,
+/// keyed by the path of the chapter's first document and the anchor's id.
+pub type Anchors = HashMap<(String, String), String>;
+
+/// Render a chapter's markdown events to HTML, splitting the output into
+/// chunks of roughly `max_size` bytes or less. A `max_size` of `0` disables
+/// splitting.
+///
+/// A single section (everything from one heading to the next) is never
+/// split, so a chunk may still exceed `max_size` if that section is huge.
+///
+/// The chapter is always rendered in one go and split up afterwards, so
+/// footnotes are numbered the same as they would be in a single document.
+pub fn render<'a, I>(events: I, max_size: usize) -> Vec<String>
+where
+    I: IntoIterator<Item = Event<'a>>,
+{
+    let mut buffer = String::new();
+    if max_size == 0 {
+        html::push_html(&mut buffer, events.into_iter());
+        return vec![buffer];
+    }
+
+    let marked = sections(events.into_iter().collect())
+        .into_iter()
+        .enumerate()
+        .flat_map(|(i, section)| {
+            let marker = if i > 0 {
+                Some(Event::Html(SECTION_BREAK.into()))
+            } else {
+                None
+            };
+            marker.into_iter().chain(section)
+        });
+    html::push_html(&mut buffer, marked);
+
+    let sections: Vec<&str> = buffer.split(SECTION_BREAK).collect();
+    if sections.iter().map(|section| section.len()).sum::<usize>() <= max_size {
+        return vec![sections.concat()];
+    }
+
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+
+    for rendered in sections {
+        if !current.is_empty() && current.len() + rendered.len() > max_size {
+            chunks.push(mem::take(&mut current));
+        }
+
+        current.push_str(rendered);
+    }
+
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+
+    chunks
+}
+
+/// Get the path a particular chunk of a chapter will be written to. The
+/// first chunk keeps the chapter's normal path.
+pub fn chunk_path(chapter_path: &Path, index: usize) -> String {
+    if index == 0 {
+        return chapter_path.with_extension("html").display().to_string();
+    }
+
+    let stem = chapter_path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    chapter_path
+        .with_file_name(format!("{}-{}.html", stem, index + 1))
+        .display()
+        .to_string()
+}
+
+/// Make sure fragment links (`href="#some-id"`) still work when the element
+/// they point to ended up in a different chunk of the chapter.
+pub fn link_chunks(chunks: &mut [String], paths: &[String]) {
+    if chunks.len() <= 1 {
+        return;
+    }
+
+    let ids: Vec<Vec<String>> = chunks.iter().map(|chunk| ids(chunk)).collect();
+    let filenames: Vec<String> = paths
+        .iter()
+        .map(|path| {
+            Path::new(path)
+                .file_name()
+                .map(|name| links::encode(&name.to_string_lossy()))
+                .unwrap_or_default()
+        })
+        .collect();
+
+    for (i, chunk) in chunks.iter_mut().enumerate() {
+        let mut rewritten = String::with_capacity(chunk.len());
+        let mut rest = &chunk[..];
+
+        while let Some(ix) = rest.find(FRAGMENT_LINK) {
+            let start = ix + FRAGMENT_LINK.len();
+            rewritten.push_str(&rest[..ix]);
+            rest = &rest[start..];
+
+            let id = rest.split('"').next().unwrap_or_default();
+            let target = if ids[i].iter().any(|existing| existing == id) {
+                None
+            } else {
+                ids.iter()
+                    .position(|chunk_ids| chunk_ids.iter().any(|x| x == id))
+            };
+
+            match target {
+                Some(j) => rewritten.push_str(&format!("href=\"{}#", filenames[j])),
+                None => rewritten.push_str(FRAGMENT_LINK),
+            }
+        }
+
+        rewritten.push_str(rest);
+        *chunk = rewritten;
+    }
+}
+
+const FRAGMENT_LINK: &str = "href=\"#";
+
+/// Remember which part of a split chapter each anchor is in.
+pub fn record_anchors(anchors: &mut Anchors, chunks: &[String], paths: &[String]) {
+    let first = links::nfc(&collisions::normalize(Path::new(&paths[0])));
+
+    for (chunk, path) in chunks.iter().zip(paths).skip(1) {
+        let filename = Path::new(path)
+            .file_name()
+            .map(|name| links::encode(&name.to_string_lossy()))
+            .unwrap_or_default();
+
+        for id in ids(chunk) {
+            anchors.insert((first.clone(), id), filename.clone());
+        }
+    }
+}
+
+/// Point links to an anchor in a split chapter (`../chapter_2.html#id`) at
+/// the part of the chapter the anchor ended up in. `document` is the path of
+/// the document the links are in, relative to the same directory as the
+/// paths in `anchors`.
+pub fn link_across_chapters(html: &str, document: &str, anchors: &Anchors) -> String {
+    let dir = Path::new(document)
+        .parent()
+        .unwrap_or_else(|| Path::new(""));
+    let mut rewritten = String::with_capacity(html.len());
+    let mut rest = html;
+
+    while let Some(ix) = rest.find(LINK) {
+        let start = ix + LINK.len();
+        rewritten.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let end = rest.find('"').unwrap_or(rest.len());
+        let href = &rest[..end];
+        match relink(dir, href, anchors) {
+            Some(relinked) => rewritten.push_str(&relinked),
+            None => rewritten.push_str(href),
+        }
+        rest = &rest[end..];
+    }
+
+    rewritten.push_str(rest);
+    rewritten
+}
+
+const LINK: &str = "href=\"";
+
+fn relink(dir: &Path, href: &str, anchors: &Anchors) -> Option<String> {
+    let hash = href.find('#')?;
+    let (path, id) = (&href[..hash], &href[hash + 1..]);
+    if path.is_empty() || links::is_external(path) {
+        return None;
+    }
+
+    let target = links::clean(&dir.join(links::decode(path)))?;
+    let key = (links::nfc(&collisions::normalize(&target)), id.to_string());
+    let filename = anchors.get(&key)?;
+    let prefix = path.rfind('/').map_or("", |ix| &path[..=ix]);
+
+    Some(format!("{}{}#{}", prefix, filename, id))
+}
+
+/// Written between sections while rendering, so the HTML can be split up
+/// afterwards.
+const SECTION_BREAK: &str = "<!-- mdbook-epub section break -->\n";
+
+/// Find the value of every `id` attribute in some HTML.
+fn ids(html: &str) -> Vec<String> {
+    html.match_indices(" id=\"")
+        .filter_map(|(ix, pattern)| {
+            let rest = &html[ix + pattern.len()..];
+            rest.find('"').map(|end| rest[..end].to_string())
+        })
+        .collect()
+}
+
+/// Break a stream of events up into sections, starting a new section at
+/// each top-level heading.
+fn sections(events: Vec<Event>) -> Vec<Vec<Event>> {
+    let mut sections = Vec::new();
+    let mut current = Vec::new();
+    let mut depth = 0;
+
+    for event in events {
+        match event {
+            Event::Start(Tag::Header(_)) if depth == 0 && !current.is_empty() => {
+                sections.push(mem::take(&mut current));
+            }
+            _ => {}
+        }
+
+        match event {
+            Event::Start(_) => depth += 1,
+            Event::End(_) => depth -= 1,
+            _ => {}
+        }
+
+        current.push(event);
+    }
+
+    if !current.is_empty() {
+        sections.push(current);
+    }
+
+    sections
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::{Options, Parser};
+
+    #[test]
+    fn small_chapters_are_left_alone() {
+        let src = "# Chapter 1\n\nSome text.\n\n## Section\n\nMore text.\n";
+
+        let got = render(Parser::new(src), 1024);
+
+        assert_eq!(got.len(), 1);
+    }
+
+    #[test]
+    fn split_at_headings() {
+        let src = "# Chapter 1\n\nSome text.\n\n## Section 1\n\nMore text.\n\n## Section 2\n\n> # Quoted\n";
+
+        let got = render(Parser::new(src), 40);
+
+        assert_eq!(got.len(), 3);
+        assert!(got[0].starts_with("<h1>Chapter 1</h1>"));
+        assert!(got[1].starts_with("<h2>Section 1</h2>"));
+        assert!(got[2].starts_with("<h2>Section 2</h2>"));
+        assert!(got[2].contains("<h1>Quoted</h1>"));
+    }
+
+    #[test]
+    fn footnotes_are_numbered_across_chunks() {
+        let src = "# Chapter 1\n\nSome text.[^a]\n\n## Section 1\n\nMore text.[^b]\n\n\
+                   [^a]: The first note.\n\n[^b]: The second note.\n";
+        let paths = vec![
+            String::from("chapter_1.html"),
+            String::from("chapter_1-2.html"),
+        ];
+
+        let mut got = render(Parser::new_ext(src, Options::ENABLE_FOOTNOTES), 60);
+        link_chunks(&mut got, &paths);
+
+        assert_eq!(got.len(), 2);
+        assert!(got[0].contains(r##"<a href="chapter_1-2.html#a">1</a>"##));
+        assert!(got[1].contains(r##"<a href="#b">2</a>"##));
+        assert!(got[1].contains(r#"id="a""#));
+        assert!(got[1].contains(r#"id="b""#));
+        assert!(!got.iter().any(|chunk| chunk.contains("section break")));
+    }
+
+    #[test]
+    fn links_from_other_chapters_point_at_the_right_chunk() {
+        let chunks = vec![
+            String::from(r#"<h1>Chapter 2</h1><p id="intro"></p>"#),
+            String::from(r#"<h2>Details</h2><p id="details"></p>"#),
+        ];
+        let paths = vec![
+            String::from("part/chapter_2.html"),
+            String::from("part/chapter_2-2.html"),
+        ];
+        let mut anchors = Anchors::new();
+        record_anchors(&mut anchors, &chunks, &paths);
+
+        let html = concat!(
+            r#"<a href="../part/chapter_2.html#details">details</a> "#,
+            r#"<a href="../part/chapter_2.html#intro">intro</a> "#,
+            r#"<a href="https://example.com/part/chapter_2.html#details">elsewhere</a>"#,
+        );
+        let got = link_across_chapters(html, "other/chapter_1.html", &anchors);
+
+        assert_eq!(
+            got,
+            concat!(
+                r#"<a href="../part/chapter_2-2.html#details">details</a> "#,
+                r#"<a href="../part/chapter_2.html#intro">intro</a> "#,
+                r#"<a href="https://example.com/part/chapter_2.html#details">elsewhere</a>"#,
+            )
+        );
+    }
+
+    #[test]
+    fn later_chunks_get_their_own_filename() {
+        let path = Path::new("nested/chapter_1.md");
+
+        assert_eq!(chunk_path(path, 0), "nested/chapter_1.html");
+        assert_eq!(chunk_path(path, 1), "nested/chapter_1-2.html");
+    }
+
+    #[test]
+    fn fragment_links_point_at_the_right_chunk() {
+        let mut chunks = vec![
+            String::from(r##"<a href="#note">1</a> <a href="#here">here</a><p id="here"></p>"##),
+            String::from(r##"<p id="note">A note <a href="#here">back</a></p>"##),
+        ];
+        let paths = vec![
+            String::from("nested/chapter_1.html"),
+            String::from("nested/chapter_1-2.html"),
+        ];
+
+        link_chunks(&mut chunks, &paths);
+
+        assert_eq!(
+            chunks[0],
+            r##"<a href="chapter_1-2.html#note">1</a> <a href="#here">here</a><p id="here"></p>"##
+        );
+        assert_eq!(
+            chunks[1],
+            r##"<p id="note">A note <a href="chapter_1.html#here">back</a></p>"##
+        );
+    }
+}
@@ -0,0 +1,261 @@
+//! Archive entries held back so they can be written in a better order.
+//!
+//! `epub-builder` only writes the package document and navigation once the
+//! whole book has been added, but streaming reading systems fetch an archive
+//! front to back and can't show anything until they've seen them. Entries are
+//! spooled to a temporary archive instead of memory, then copied into the real
+//! archive in order once the book is finished.
+//!
+//! The spool is the zip stage of the pipeline (see the `pipeline` module).
+//! Entries are compressed on a writer thread as they're added, so the next
+//! chapter can be rendered while the last one is compressed, and copying them
+//! into the real archive doesn't need to compress them again.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::sync::mpsc::{self, SyncSender};
+use std::thread::{self, JoinHandle};
+use tempdir::TempDir;
+use zip::read::ZipFile;
+use zip::result::ZipResult;
+use zip::write::FileOptions;
+use zip::{ZipArchive, ZipWriter};
+
+use pipeline::CAPACITY;
+
+pub struct Spool {
+    sender: Option<SyncSender<(String, Vec<u8>)>>,
+    worker: Option<JoinHandle<io::Result<File>>>,
+    entries: Vec<Entry>,
+    /// How many entries have been sent to the writer thread.
+    written: usize,
+    // deleted when the spool is dropped
+    dir: TempDir,
+}
+
+struct Entry {
+    name: String,
+    /// Where the entry is in the temporary archive, or `None` if its slot was
+    /// reserved and it hasn't been added yet.
+    index: Option<usize>,
+}
+
+impl Spool {
+    pub fn new() -> io::Result<Spool> {
+        let dir = TempDir::new("mdbook-epub")?;
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(dir.path().join("spool.zip"))?;
+
+        let (sender, receiver) = mpsc::sync_channel::<(String, Vec<u8>)>(CAPACITY);
+        let worker = thread::Builder::new()
+            .name(String::from("zip"))
+            .spawn(move || {
+                let mut writer = ZipWriter::new(file);
+                for (name, content) in receiver {
+                    writer.start_file(name, FileOptions::default())?;
+                    writer.write_all(&content)?;
+                }
+
+                Ok(writer.finish()?)
+            })?;
+
+        Ok(Spool {
+            sender: Some(sender),
+            worker: Some(worker),
+            entries: Vec::new(),
+            written: 0,
+            dir,
+        })
+    }
+
+    pub fn add<R: Read>(&mut self, name: &str, content: R) -> io::Result<()> {
+        self.entries.push(Entry {
+            name: name.to_string(),
+            index: None,
+        });
+        self.fill(name, content)
+    }
+
+    /// Keep a place for an entry which will be added later, with `fill()`.
+    pub fn reserve(&mut self, name: &str) {
+        self.entries.push(Entry {
+            name: name.to_string(),
+            index: None,
+        });
+    }
+
+    /// Add the content of an entry whose place was kept with `reserve()`.
+    pub fn fill<R: Read>(&mut self, name: &str, mut content: R) -> io::Result<()> {
+        let slot = self
+            .entries
+            .iter()
+            .rposition(|entry| entry.name == name && entry.index.is_none())
+            .ok_or_else(|| other_error(format!("{} has no place in the spool", name)))?;
+
+        let mut data = Vec::new();
+        content.read_to_end(&mut data)?;
+
+        let sent = match self.sender {
+            Some(ref sender) => sender.send((name.to_string(), data)).is_ok(),
+            None => false,
+        };
+        if !sent {
+            // the writer only stops early when something went wrong
+            return match self.join() {
+                Err(e) => Err(e),
+                Ok(_) => Err(other_error("the spool was already finished")),
+            };
+        }
+
+        self.entries[slot].index = Some(self.written);
+        self.written += 1;
+        Ok(())
+    }
+
+    /// Wait for every entry to be written, so they can be read back out.
+    pub fn finish(mut self) -> io::Result<Spooled> {
+        if let Some(entry) = self.entries.iter().find(|entry| entry.index.is_none()) {
+            return Err(other_error(format!("{} was never added", entry.name)));
+        }
+
+        let mut file = self.join()?;
+        file.seek(SeekFrom::Start(0))?;
+        let archive = ZipArchive::new(file)?;
+
+        let entries = self
+            .entries
+            .drain(..)
+            .filter_map(|entry| entry.index.map(|index| (entry.name, index)))
+            .collect();
+
+        Ok(Spooled {
+            archive,
+            entries,
+            _dir: self.dir,
+        })
+    }
+
+    fn join(&mut self) -> io::Result<File> {
+        self.sender.take();
+
+        match self.worker.take() {
+            Some(worker) => worker
+                .join()
+                .unwrap_or_else(|_| Err(other_error("the zip stage panicked"))),
+            None => Err(other_error("the spool was already finished")),
+        }
+    }
+}
+
+/// Entries which have been compressed into the spool, ready to be copied into
+/// the real archive.
+pub struct Spooled {
+    archive: ZipArchive<File>,
+    /// The name of each entry and where it is in `archive`, in the order
+    /// they'll be read.
+    entries: Vec<(String, usize)>,
+    _dir: TempDir,
+}
+
+impl Spooled {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Reorder the entries, keeping the order their places were kept in for
+    /// entries with the same key.
+    pub fn sort_by_key<K: Ord, F: FnMut(&str) -> K>(&mut self, mut key: F) {
+        self.entries.sort_by_key(|(name, _)| key(name));
+    }
+
+    /// Read the `index`'th entry back out of the spool.
+    pub fn read(&mut self, index: usize) -> ZipResult<ZipFile<'_>> {
+        self.archive.by_index(self.entries[index].1)
+    }
+}
+
+fn other_error<E: Into<Box<dyn std::error::Error + Send + Sync>>>(e: E) -> io::Error {
+    io::Error::other(e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contents(mut spooled: Spooled) -> Vec<(String, String)> {
+        let mut got = Vec::new();
+        for ix in 0..spooled.len() {
+            let mut entry = spooled.read(ix).unwrap();
+            let mut text = String::new();
+            entry.read_to_string(&mut text).unwrap();
+            got.push((entry.name().to_string(), text));
+        }
+        got
+    }
+
+    #[test]
+    fn entries_are_read_back_in_sorted_order() {
+        let mut spool = Spool::new().unwrap();
+        spool.add("b.html", "second".as_bytes()).unwrap();
+        spool.add("a.opf", "first".as_bytes()).unwrap();
+        spool.add("c.html", "third".as_bytes()).unwrap();
+
+        let mut spooled = spool.finish().unwrap();
+        spooled.sort_by_key(|name| !name.ends_with(".opf"));
+
+        assert_eq!(
+            contents(spooled),
+            vec![
+                ("a.opf".to_string(), "first".to_string()),
+                ("b.html".to_string(), "second".to_string()),
+                ("c.html".to_string(), "third".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn reserved_entries_keep_their_place() {
+        let mut spool = Spool::new().unwrap();
+        spool.add("a.html", "first".as_bytes()).unwrap();
+        spool.reserve("b.html");
+        spool.add("c.html", "third".as_bytes()).unwrap();
+        spool.fill("b.html", "second".as_bytes()).unwrap();
+
+        let mut spooled = spool.finish().unwrap();
+        spooled.sort_by_key(|_| 0);
+
+        let names: Vec<_> = contents(spooled)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(names, vec!["a.html", "b.html", "c.html"]);
+    }
+
+    #[test]
+    fn more_entries_than_the_channel_holds_are_all_written() {
+        let mut spool = Spool::new().unwrap();
+        for ix in 0..CAPACITY * 2 {
+            spool
+                .add(&format!("{}.txt", ix), ix.to_string().as_bytes())
+                .unwrap();
+        }
+
+        let spooled = spool.finish().unwrap();
+
+        let got = contents(spooled);
+        assert_eq!(got.len(), CAPACITY * 2);
+        assert_eq!(got.last().unwrap().1, (CAPACITY * 2 - 1).to_string());
+    }
+
+    #[test]
+    fn unfilled_entries_are_an_error() {
+        let mut spool = Spool::new().unwrap();
+        spool.reserve("about.xhtml");
+
+        assert!(spool.finish().is_err());
+    }
+}
@@ -0,0 +1,54 @@
+//! Word counts and reading time estimates.
+
+use pulldown_cmark::Event;
+
+/// Count the words in a chapter's text (including code, whose text arrives as
+/// `Text` events between the `Tag::Code` or `Tag::CodeBlock` tags).
+/// Punctuation on its own doesn't count as a word.
+pub fn word_count(events: &[Event]) -> usize {
+    events
+        .iter()
+        .map(|event| match *event {
+            Event::Text(ref text) => text
+                .split_whitespace()
+                .filter(|word| word.chars().any(char::is_alphanumeric))
+                .count(),
+            _ => 0,
+        })
+        .sum()
+}
+
+/// Estimate how many minutes it'll take to read this many words, rounding up
+/// so nothing takes "0 minutes".
+pub fn reading_time(words: usize, words_per_minute: usize) -> usize {
+    if words == 0 || words_per_minute == 0 {
+        return 0;
+    }
+
+    words.div_ceil(words_per_minute)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::Parser;
+
+    #[test]
+    fn count_words() {
+        let src = "# Chapter 1\n\nSome *emphasised* text and `code`.\n\n```\nfn main() {}\n```\n";
+        let events: Vec<_> = Parser::new(src).collect();
+
+        let got = word_count(&events);
+
+        assert_eq!(got, 9);
+    }
+
+    #[test]
+    fn reading_time_rounds_up() {
+        let inputs = vec![(0, 0), (1, 1), (200, 1), (201, 2), (1000, 5)];
+
+        for (words, should_be) in inputs {
+            assert_eq!(reading_time(words, 200), should_be, "{}", words);
+        }
+    }
+}
@@ -0,0 +1,341 @@
+//! The labels used in generated pages and navigation.
+//!
+//! English is used unless `book.language` has a bundled translation, and any
+//! label can be overridden with the `output.epub.strings` table. Labels
+//! containing `{placeholders}` are filled in with [`Strings::format()`].
+
+use failure::{err_msg, Error};
+use std::collections::BTreeMap;
+
+const ENGLISH: &[(&str, &str)] = &[
+    ("table-of-contents", "Table of Contents"),
+    ("notes", "Notes"),
+    ("references", "References"),
+    ("credits", "Credits"),
+    ("about-this-book", "About this book"),
+    (
+        "reading-time",
+        "{title} contains {words} words, which will take roughly {minutes} minutes to read.",
+    ),
+    ("chapter", "Chapter"),
+    ("words", "Words"),
+    ("minutes", "Minutes"),
+    ("copyright", "Copyright"),
+    ("back-to-reference", "Back to reference {number}"),
+    ("completed", "Completed"),
+    ("not-completed", "Not completed"),
+    ("watch-video", "Watch the video online"),
+    ("view-embed", "View the embedded content online"),
+    ("form-unavailable", "This form can't be used in an ebook."),
+    ("open-form", "Open it online"),
+];
+
+const FRENCH: &[(&str, &str)] = &[
+    ("table-of-contents", "Table des matières"),
+    ("notes", "Notes"),
+    ("references", "Références"),
+    ("credits", "Crédits"),
+    ("about-this-book", "À propos de ce livre"),
+    (
+        "reading-time",
+        "{title} contient {words} mots, soit environ {minutes} minutes de lecture.",
+    ),
+    ("chapter", "Chapitre"),
+    ("words", "Mots"),
+    ("minutes", "Minutes"),
+    ("back-to-reference", "Retour à la référence {number}"),
+    ("completed", "Terminé"),
+    ("not-completed", "Non terminé"),
+    ("watch-video", "Regarder la vidéo en ligne"),
+    ("view-embed", "Voir le contenu intégré en ligne"),
+    (
+        "form-unavailable",
+        "Ce formulaire ne peut pas être utilisé dans un livre numérique.",
+    ),
+    ("open-form", "Ouvrir en ligne"),
+];
+
+const GERMAN: &[(&str, &str)] = &[
+    ("table-of-contents", "Inhaltsverzeichnis"),
+    ("notes", "Anmerkungen"),
+    ("references", "Literatur"),
+    ("credits", "Nachweise"),
+    ("about-this-book", "Über dieses Buch"),
+    (
+        "reading-time",
+        "{title} enthält {words} Wörter, die Lesezeit beträgt etwa {minutes} Minuten.",
+    ),
+    ("chapter", "Kapitel"),
+    ("words", "Wörter"),
+    ("minutes", "Minuten"),
+    ("back-to-reference", "Zurück zum Verweis {number}"),
+    ("completed", "Erledigt"),
+    ("not-completed", "Nicht erledigt"),
+    ("watch-video", "Video online ansehen"),
+    ("view-embed", "Eingebettete Inhalte online ansehen"),
+    (
+        "form-unavailable",
+        "Dieses Formular kann in einem E-Book nicht verwendet werden.",
+    ),
+    ("open-form", "Online öffnen"),
+];
+
+const SPANISH: &[(&str, &str)] = &[
+    ("table-of-contents", "Índice"),
+    ("notes", "Notas"),
+    ("references", "Referencias"),
+    ("credits", "Créditos"),
+    ("about-this-book", "Acerca de este libro"),
+    (
+        "reading-time",
+        "{title} contiene {words} palabras, que se leen en unos {minutes} minutos.",
+    ),
+    ("chapter", "Capítulo"),
+    ("words", "Palabras"),
+    ("minutes", "Minutos"),
+    ("back-to-reference", "Volver a la referencia {number}"),
+    ("completed", "Completado"),
+    ("not-completed", "No completado"),
+    ("watch-video", "Ver el vídeo en línea"),
+    ("view-embed", "Ver el contenido incrustado en línea"),
+    (
+        "form-unavailable",
+        "Este formulario no se puede usar en un libro electrónico.",
+    ),
+    ("open-form", "Abrir en línea"),
+];
+
+const ITALIAN: &[(&str, &str)] = &[
+    ("table-of-contents", "Indice"),
+    ("notes", "Note"),
+    ("references", "Bibliografia"),
+    ("credits", "Crediti"),
+    ("about-this-book", "Informazioni su questo libro"),
+    (
+        "reading-time",
+        "{title} contiene {words} parole, che richiedono circa {minutes} minuti di lettura.",
+    ),
+    ("chapter", "Capitolo"),
+    ("words", "Parole"),
+    ("minutes", "Minuti"),
+    ("back-to-reference", "Torna al riferimento {number}"),
+    ("completed", "Completato"),
+    ("not-completed", "Non completato"),
+    ("watch-video", "Guarda il video online"),
+    ("view-embed", "Visualizza il contenuto incorporato online"),
+    (
+        "form-unavailable",
+        "Questo modulo non può essere usato in un ebook.",
+    ),
+    ("open-form", "Aprilo online"),
+];
+
+const PORTUGUESE: &[(&str, &str)] = &[
+    ("table-of-contents", "Índice"),
+    ("notes", "Notas"),
+    ("references", "Referências"),
+    ("credits", "Créditos"),
+    ("about-this-book", "Sobre este livro"),
+    (
+        "reading-time",
+        "{title} tem {words} palavras, que levam cerca de {minutes} minutos para ler.",
+    ),
+    ("chapter", "Capítulo"),
+    ("words", "Palavras"),
+    ("minutes", "Minutos"),
+    ("back-to-reference", "Voltar à referência {number}"),
+    ("completed", "Concluído"),
+    ("not-completed", "Não concluído"),
+    ("watch-video", "Assistir ao vídeo online"),
+    ("view-embed", "Ver o conteúdo incorporado online"),
+    (
+        "form-unavailable",
+        "Este formulário não pode ser usado em um e-book.",
+    ),
+    ("open-form", "Abrir online"),
+];
+
+const DUTCH: &[(&str, &str)] = &[
+    ("table-of-contents", "Inhoudsopgave"),
+    ("notes", "Noten"),
+    ("references", "Literatuur"),
+    ("credits", "Verantwoording"),
+    ("about-this-book", "Over dit boek"),
+    (
+        "reading-time",
+        "{title} bevat {words} woorden, goed voor ongeveer {minutes} minuten leestijd.",
+    ),
+    ("chapter", "Hoofdstuk"),
+    ("words", "Woorden"),
+    ("minutes", "Minuten"),
+    ("back-to-reference", "Terug naar verwijzing {number}"),
+    ("completed", "Voltooid"),
+    ("not-completed", "Niet voltooid"),
+    ("watch-video", "Bekijk de video online"),
+    ("view-embed", "Bekijk de ingesloten inhoud online"),
+    (
+        "form-unavailable",
+        "Dit formulier kan niet in een e-book worden gebruikt.",
+    ),
+    ("open-form", "Online openen"),
+];
+
+/// The bundled translations, keyed by primary language subtag.
+const TRANSLATIONS: &[(&str, &[(&str, &str)])] = &[
+    ("de", GERMAN),
+    ("es", SPANISH),
+    ("fr", FRENCH),
+    ("it", ITALIAN),
+    ("nl", DUTCH),
+    ("pt", PORTUGUESE),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Strings {
+    labels: BTreeMap<&'static str, String>,
+}
+
+impl Strings {
+    /// Look up the labels for a book written in `language`, applying the
+    /// overrides from `output.epub.strings`.
+    pub fn new(
+        language: Option<&str>,
+        overrides: &BTreeMap<String, String>,
+    ) -> Result<Strings, Error> {
+        let mut strings = Strings::default();
+
+        if let Some(translation) = language.and_then(translation) {
+            for &(key, label) in translation {
+                strings.labels.insert(key, label.to_string());
+            }
+        }
+
+        for (key, label) in overrides {
+            match ENGLISH.iter().find(|&&(known, _)| known == key.as_str()) {
+                Some(&(key, _)) => {
+                    strings.labels.insert(key, label.clone());
+                }
+                None => {
+                    let known: Vec<_> = ENGLISH.iter().map(|&(key, _)| key).collect();
+                    return Err(err_msg(format!(
+                        "Unknown label in output.epub.strings, \"{}\" (expected one of {})",
+                        key,
+                        known.join(", ")
+                    )));
+                }
+            }
+        }
+
+        Ok(strings)
+    }
+
+    pub fn get(&self, key: &str) -> &str {
+        self.labels.get(key).map_or("", String::as_str)
+    }
+
+    /// Get a label, replacing each `{name}` placeholder with its value.
+    pub fn format(&self, key: &str, values: &[(&str, &str)]) -> String {
+        values
+            .iter()
+            .fold(self.get(key).to_string(), |label, &(name, value)| {
+                label.replace(&format!("{{{}}}", name), value)
+            })
+    }
+
+    /// Every label, keyed with underscores instead of dashes so they can be
+    /// used from handlebars templates (e.g. `{{strings.about_this_book}}`).
+    pub fn template_data(&self) -> BTreeMap<String, &str> {
+        self.labels
+            .iter()
+            .map(|(key, label)| (key.replace('-', "_"), label.as_str()))
+            .collect()
+    }
+}
+
+impl Default for Strings {
+    fn default() -> Strings {
+        Strings {
+            labels: ENGLISH
+                .iter()
+                .map(|&(key, label)| (key, label.to_string()))
+                .collect(),
+        }
+    }
+}
+
+fn translation(language: &str) -> Option<&'static [(&'static str, &'static str)]> {
+    let primary = language
+        .split(['-', '_'])
+        .next()
+        .unwrap_or(language)
+        .to_lowercase();
+
+    TRANSLATIONS
+        .iter()
+        .find(|&&(code, _)| code == primary)
+        .map(|&(_, translation)| translation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn translations_are_picked_from_the_language() {
+        let overrides = BTreeMap::new();
+
+        let french = Strings::new(Some("fr-CA"), &overrides).unwrap();
+        let unknown = Strings::new(Some("tlh"), &overrides).unwrap();
+
+        assert_eq!(french.get("table-of-contents"), "Table des matières");
+        // untranslated labels fall back to English
+        assert_eq!(french.get("copyright"), "Copyright");
+        assert_eq!(unknown.get("notes"), "Notes");
+    }
+
+    #[test]
+    fn labels_can_be_overridden() {
+        let mut overrides = BTreeMap::new();
+        overrides.insert(String::from("notes"), String::from("Endnotes"));
+
+        let strings = Strings::new(Some("de"), &overrides).unwrap();
+
+        assert_eq!(strings.get("notes"), "Endnotes");
+        assert_eq!(strings.get("chapter"), "Kapitel");
+        assert_eq!(
+            strings.template_data()["about_this_book"],
+            "Über dieses Buch"
+        );
+    }
+
+    #[test]
+    fn unknown_labels_are_an_error() {
+        let mut overrides = BTreeMap::new();
+        overrides.insert(String::from("glossary"), String::from("Glossary"));
+
+        assert!(Strings::new(None, &overrides).is_err());
+    }
+
+    #[test]
+    fn placeholders_are_filled_in() {
+        let strings = Strings::default();
+
+        let got = strings.format("back-to-reference", &[("number", "3")]);
+
+        assert_eq!(got, "Back to reference 3");
+    }
+
+    #[test]
+    fn every_translation_uses_known_keys() {
+        for &(code, translation) in TRANSLATIONS {
+            for &(key, _) in translation {
+                assert!(
+                    ENGLISH.iter().any(|&(known, _)| known == key),
+                    "{} has an unknown key, {}",
+                    code,
+                    key
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! Design tokens (`output.epub.style`, or `epub-style` in a chapter's front
+//! matter) which are turned into CSS custom properties.
+//!
+//! The book's tokens are declared on `:root` at the top of the stylesheet
+//! and a chapter's go in a `style` attribute on its `<body>`, so they
+//! cascade into the bundled stylesheet as well as the user's own. The
+//! bundled stylesheet always gives a fallback, since plenty of reading
+//! systems don't support custom properties.
+
+use failure::{err_msg, Error};
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+
+/// The `:root` rule declaring the book's tokens, if it has any.
+pub fn root_rule(tokens: &BTreeMap<String, String>) -> Result<String, Error> {
+    if tokens.is_empty() {
+        return Ok(String::new());
+    }
+
+    let mut rule = String::from(":root {\n");
+    for (name, value) in tokens {
+        rule.push_str(&format!("    {};\n", declaration(name, value)?));
+    }
+    rule.push_str("}\n\n");
+
+    Ok(rule)
+}
+
+/// The tokens as an inline `style` attribute's value.
+pub fn inline(tokens: &BTreeMap<String, String>) -> Result<Option<String>, Error> {
+    if tokens.is_empty() {
+        return Ok(None);
+    }
+
+    let declarations = tokens
+        .iter()
+        .map(|(name, value)| declaration(name, value))
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    Ok(Some(declarations.join("; ")))
+}
+
+fn declaration(name: &str, value: &str) -> Result<String, Error> {
+    let name = name.trim_start_matches("--");
+    let valid_name = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid_name {
+        return Err(err_msg(format!(
+            "\"{}\" isn't a valid style token name, only letters, numbers, \"-\" and \"_\" \
+             can be used",
+            name
+        )));
+    }
+
+    let value = value.trim();
+    if value.is_empty() || value.contains(|c: char| ";{}<>\n".contains(c)) {
+        return Err(err_msg(format!(
+            "The style token \"{}\" has an invalid value, \"{}\"",
+            name, value
+        )));
+    }
+
+    Ok(format!("--{}: {}", name, value))
+}
+
+/// Accept numbers as well as strings, so `line-height = 1.5` doesn't need
+/// to be quoted.
+pub fn tokens<'de, D>(deserializer: D) -> Result<BTreeMap<String, String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Scalar {
+        String(String),
+        Integer(i64),
+        Float(f64),
+    }
+
+    let tokens = BTreeMap::<String, Scalar>::deserialize(deserializer)?;
+
+    Ok(tokens
+        .into_iter()
+        .map(|(name, value)| {
+            let value = match value {
+                Scalar::String(s) => s,
+                Scalar::Integer(i) => i.to_string(),
+                Scalar::Float(f) => f.to_string(),
+            };
+            (name, value)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|&(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn book_tokens_are_declared_on_the_root() {
+        let got = root_rule(&map(&[("accent-color", "#c0392b"), ("font-size", "110%")])).unwrap();
+
+        assert_eq!(
+            got,
+            ":root {\n    --accent-color: #c0392b;\n    --font-size: 110%;\n}\n\n"
+        );
+        assert_eq!(root_rule(&BTreeMap::new()).unwrap(), "");
+    }
+
+    #[test]
+    fn chapter_tokens_are_inlined() {
+        let got = inline(&map(&[("accent-color", "teal"), ("line-height", "1.5")])).unwrap();
+
+        assert_eq!(
+            got,
+            Some(String::from("--accent-color: teal; --line-height: 1.5"))
+        );
+    }
+
+    #[test]
+    fn values_cant_escape_the_declaration() {
+        assert!(inline(&map(&[("accent-color", "red; } body { display: none")])).is_err());
+        assert!(inline(&map(&[("accent color", "red")])).is_err());
+    }
+}
@@ -0,0 +1,137 @@
+//! Rendering tables so they stay readable on small screens.
+//!
+//! `pulldown-cmark` writes column alignment using the `align` attribute,
+//! which isn't valid in EPUB 3 content documents, so we render the table
+//! structure ourselves using classes instead. Every table is also wrapped in
+//! a `<div>` so the stylesheet can deal with tables that are too wide.
+
+use pulldown_cmark::{Alignment, Event, Tag};
+
+use config::{MarkdownConfig, WideTables};
+
+/// Render each table's structure as HTML, leaving the cell contents alone.
+pub fn transform<'a, I>(events: I, config: &MarkdownConfig) -> Vec<Event<'a>>
+where
+    I: IntoIterator<Item = Event<'a>>,
+{
+    let mut alignments = Vec::new();
+    let mut in_head = false;
+    let mut column = 0;
+    let mut out: Vec<Event<'a>> = Vec::new();
+
+    for event in events {
+        let event = match event {
+            Event::Start(Tag::Table(aligns)) => {
+                let html = format!(
+                    "<div class=\"{}\">\n<table>",
+                    wrapper_class(aligns.len(), config)
+                );
+                alignments = aligns;
+                Event::Html(html.into())
+            }
+            Event::End(Tag::Table(_)) => Event::Html("</tbody></table>\n</div>\n".into()),
+            Event::Start(Tag::TableHead) => {
+                in_head = true;
+                column = 0;
+                Event::Html("<thead><tr>".into())
+            }
+            Event::End(Tag::TableHead) => {
+                in_head = false;
+                Event::Html("</tr></thead><tbody>\n".into())
+            }
+            Event::Start(Tag::TableRow) => {
+                column = 0;
+                Event::Html("<tr>".into())
+            }
+            Event::End(Tag::TableRow) => Event::Html("</tr>\n".into()),
+            Event::Start(Tag::TableCell) => {
+                let element = if in_head { "th" } else { "td" };
+                let html = match alignment_class(alignments.get(column)) {
+                    Some(class) => format!("<{} class=\"{}\">", element, class),
+                    None => format!("<{}>", element),
+                };
+                Event::Html(html.into())
+            }
+            Event::End(Tag::TableCell) => {
+                column += 1;
+                // pulldown-cmark keeps the whitespace before the next `|`
+                if let Some(&mut Event::Text(ref mut text)) = out.last_mut() {
+                    *text = text.trim_end().to_string().into();
+                }
+                let element = if in_head { "th" } else { "td" };
+                Event::Html(format!("</{}>", element).into())
+            }
+            other => other,
+        };
+        out.push(event);
+    }
+
+    out
+}
+
+fn wrapper_class(columns: usize, config: &MarkdownConfig) -> String {
+    let mut class = String::from("table-wrapper");
+
+    if config.wide_table_columns > 0 && columns > config.wide_table_columns {
+        class.push_str(" wide ");
+        class.push_str(match config.wide_tables {
+            WideTables::Scale => "scale",
+            WideTables::Scroll => "scroll",
+        });
+    }
+
+    class
+}
+
+fn alignment_class(alignment: Option<&Alignment>) -> Option<&'static str> {
+    match alignment {
+        Some(&Alignment::Left) => Some("align-left"),
+        Some(&Alignment::Center) => Some("align-center"),
+        Some(&Alignment::Right) => Some("align-right"),
+        Some(&Alignment::None) | None => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use markdown;
+    use pulldown_cmark::html;
+
+    fn render(src: &str, config: &MarkdownConfig) -> String {
+        let mut buffer = String::new();
+        html::push_html(
+            &mut buffer,
+            transform(markdown::parser(src, config), config).into_iter(),
+        );
+        buffer
+    }
+
+    #[test]
+    fn alignment_uses_classes() {
+        let src = "| a | b | c | d |\n|:--|:-:|--:|---|\n| 1 | 2 | 3 | 4 |\n";
+
+        let got = render(src, &MarkdownConfig::default());
+
+        assert!(!got.contains("align=\""));
+        assert!(got.contains("<th class=\"align-left\">a</th>"));
+        assert!(got.contains("<th class=\"align-center\">b</th>"));
+        assert!(got.contains("<td class=\"align-right\">3</td>"));
+        assert!(got.contains("<td>4</td>"));
+        assert!(got.contains("<div class=\"table-wrapper\">"));
+    }
+
+    #[test]
+    fn wide_tables_are_marked() {
+        let src = "| a | b | c |\n|---|---|---|\n| 1 | 2 | 3 |\n";
+        let config = MarkdownConfig {
+            wide_table_columns: 2,
+            wide_tables: WideTables::Scroll,
+            ..Default::default()
+        };
+
+        let got = render(src, &config);
+
+        assert!(got.contains("<div class=\"table-wrapper wide scroll\">"));
+    }
+}
@@ -0,0 +1,149 @@
+//! Overriding the built-in templates and stylesheet from the book's theme
+//! directory, the same way the HTML renderer's `theme/` directory works.
+//!
+//! The directory (`theme/epub/` by default) may contain any of
+//!
+//! - `index.hbs`, the template each chapter is rendered into
+//! - `titlepage.hbs`, the title page template
+//! - `titlepage.xhtml`, a finished title page to use instead of rendering one
+//! - `copyright.hbs`, the copyright page template
+//! - `about.hbs`, the "About this book" page template
+//! - `master.css`, which replaces the default stylesheet
+//! - `night.css`, which replaces the default night mode stylesheet
+//! - `fonts/`, fonts which should be embedded in the book
+
+use failure::{Error, ResultExt};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use fonts;
+use {
+    DEFAULT_ABOUT_PAGE, DEFAULT_COPYRIGHT_PAGE, DEFAULT_CSS, DEFAULT_NIGHT_CSS, DEFAULT_TEMPLATE,
+    DEFAULT_TITLE_PAGE,
+};
+
+/// The theme directory used when `output.epub.theme` isn't set, relative to
+/// the book's root.
+pub const DEFAULT_THEME_DIR: &str = "theme/epub";
+
+/// The templates and stylesheet a book is rendered with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Theme {
+    pub chapter: String,
+    pub title_page: String,
+    pub title_page_xhtml: Option<String>,
+    pub copyright_page: String,
+    pub about_page: String,
+    pub stylesheet: String,
+    pub night_stylesheet: String,
+    pub fonts: Vec<PathBuf>,
+}
+
+impl Theme {
+    /// Load a theme, using the built-in defaults for anything the directory
+    /// doesn't override. The directory doesn't need to exist.
+    pub fn load(dir: &Path) -> Result<Theme, Error> {
+        let mut theme = Theme::default();
+
+        if !dir.is_dir() {
+            return Ok(theme);
+        }
+        debug!("Loading the theme from {}", dir.display());
+
+        if let Some(chapter) = read(dir, "index.hbs")? {
+            theme.chapter = chapter;
+        }
+        if let Some(title_page) = read(dir, "titlepage.hbs")? {
+            theme.title_page = title_page;
+        }
+        theme.title_page_xhtml = read(dir, "titlepage.xhtml")?;
+        if let Some(copyright_page) = read(dir, "copyright.hbs")? {
+            theme.copyright_page = copyright_page;
+        }
+        if let Some(about_page) = read(dir, "about.hbs")? {
+            theme.about_page = about_page;
+        }
+        if let Some(stylesheet) = read(dir, "master.css")? {
+            theme.stylesheet = stylesheet;
+        }
+        if let Some(night_stylesheet) = read(dir, "night.css")? {
+            theme.night_stylesheet = night_stylesheet;
+        }
+
+        let font_dir = dir.join("fonts");
+        if font_dir.is_dir() {
+            for entry in fs::read_dir(&font_dir)
+                .with_context(|_| format!("Unable to read {}", font_dir.display()))?
+            {
+                let path = entry?.path();
+                if path.is_file() && fonts::is_font(&path) {
+                    theme.fonts.push(path);
+                }
+            }
+            theme.fonts.sort();
+        }
+
+        Ok(theme)
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Theme {
+        Theme {
+            chapter: DEFAULT_TEMPLATE.to_string(),
+            title_page: DEFAULT_TITLE_PAGE.to_string(),
+            title_page_xhtml: None,
+            copyright_page: DEFAULT_COPYRIGHT_PAGE.to_string(),
+            about_page: DEFAULT_ABOUT_PAGE.to_string(),
+            stylesheet: DEFAULT_CSS.to_string(),
+            night_stylesheet: DEFAULT_NIGHT_CSS.to_string(),
+            fonts: Vec::new(),
+        }
+    }
+}
+
+fn read(dir: &Path, name: &str) -> Result<Option<String>, Error> {
+    let path = dir.join(name);
+
+    if !path.is_file() {
+        return Ok(None);
+    }
+
+    trace!("Overriding {} with {}", name, path.display());
+    let content =
+        fs::read_to_string(&path).with_context(|_| format!("Unable to read {}", path.display()))?;
+    Ok(Some(content))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::io::Write;
+    use tempdir::TempDir;
+
+    #[test]
+    fn a_missing_directory_uses_the_defaults() {
+        let got = Theme::load(Path::new("/path/to/nowhere")).unwrap();
+
+        assert_eq!(got, Theme::default());
+    }
+
+    #[test]
+    fn files_in_the_theme_override_the_defaults() {
+        let temp = TempDir::new("mdbook-epub").unwrap();
+        File::create(temp.path().join("master.css"))
+            .unwrap()
+            .write_all(b"body { color: red; }")
+            .unwrap();
+        fs::create_dir(temp.path().join("fonts")).unwrap();
+        File::create(temp.path().join("fonts/Serif.otf")).unwrap();
+        File::create(temp.path().join("fonts/README.md")).unwrap();
+
+        let got = Theme::load(temp.path()).unwrap();
+
+        assert_eq!(got.stylesheet, "body { color: red; }");
+        assert_eq!(got.chapter, DEFAULT_TEMPLATE);
+        assert_eq!(got.fonts, vec![temp.path().join("fonts/Serif.otf")]);
+    }
+}
@@ -0,0 +1,319 @@
+//! Transforming assets (resizing images, minifying SVGs, etc.) on their way
+//! into the book.
+//!
+//! Each asset is passed through a chain of `AssetTransformer`s before being
+//! packaged. The built-in transformers are enabled using the
+//! `output.epub.assets` table, and library users can add their own with
+//! `Generator::with_transformer()`.
+
+use failure::{self, Error, ResultExt};
+use image::{self, FilterType, GenericImageView, ImageFormat, ImageOutputFormat};
+use std::fmt::{self, Debug, Formatter};
+use std::path::PathBuf;
+
+use config::AssetConfig;
+use resources::Asset;
+
+/// An asset's contents, along with the name and media type it'll be given
+/// inside the book.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssetData {
+    /// The asset's path inside the book, relative to the `src/` directory.
+    ///
+    /// Chapters link to an asset by its original name, so changing this only
+    /// makes sense for assets which aren't referenced directly.
+    pub filename: PathBuf,
+    pub mimetype: String,
+    pub content: Vec<u8>,
+}
+
+/// Something which can modify an asset before it's packaged.
+pub trait AssetTransformer {
+    /// Transform an asset's data. `asset` is the original asset, as found on
+    /// disk.
+    fn transform(&self, asset: &Asset, data: AssetData) -> Result<AssetData, Error>;
+}
+
+impl<F> AssetTransformer for F
+where
+    F: Fn(&Asset, AssetData) -> Result<AssetData, Error>,
+{
+    fn transform(&self, asset: &Asset, data: AssetData) -> Result<AssetData, Error> {
+        self(asset, data)
+    }
+}
+
+/// The chain of transformers every asset is passed through, in order.
+#[derive(Default)]
+pub struct Transformers<'a>(Vec<Box<dyn AssetTransformer + 'a>>);
+
+impl<'a> Transformers<'a> {
+    /// Create the chain of built-in transformers listed in
+    /// `output.epub.assets.transformers`.
+    pub fn from_config(config: &AssetConfig) -> Result<Transformers<'a>, Error> {
+        let mut transformers = Transformers::default();
+
+        for name in &config.transformers {
+            match name.as_str() {
+                "resize-images" => transformers.push(ResizeImages {
+                    max_width: config.max_image_width,
+                    max_height: config.max_image_height,
+                }),
+                "minify-svg" => transformers.push(MinifySvg),
+                other => {
+                    return Err(failure::err_msg(format!(
+                        "Unknown asset transformer, \"{}\"",
+                        other
+                    )))
+                }
+            }
+        }
+
+        Ok(transformers)
+    }
+
+    pub fn push<T: AssetTransformer + 'a>(&mut self, transformer: T) {
+        self.0.push(Box::new(transformer));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Pass an asset through every transformer.
+    pub fn apply(&self, asset: &Asset, mut data: AssetData) -> Result<AssetData, Error> {
+        for transformer in &self.0 {
+            data = transformer.transform(asset, data)?;
+        }
+
+        Ok(data)
+    }
+}
+
+impl<'a> Debug for Transformers<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_tuple("Transformers").field(&self.0.len()).finish()
+    }
+}
+
+/// Scale PNG and JPEG images down so they fit within a maximum size,
+/// preserving their aspect ratio. Images which are already small enough are
+/// left alone.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ResizeImages {
+    pub max_width: Option<u32>,
+    pub max_height: Option<u32>,
+}
+
+impl AssetTransformer for ResizeImages {
+    fn transform(&self, asset: &Asset, mut data: AssetData) -> Result<AssetData, Error> {
+        let output_format = match image::guess_format(&data.content) {
+            Ok(ImageFormat::PNG) => ImageOutputFormat::PNG,
+            Ok(ImageFormat::JPEG) => ImageOutputFormat::JPEG(90),
+            _ => return Ok(data),
+        };
+
+        let img = image::load_from_memory(&data.content)
+            .with_context(|_| format!("Unable to decode {}", asset.filename.display()))?;
+        let (width, height) = img.dimensions();
+        let max_width = self.max_width.unwrap_or(width);
+        let max_height = self.max_height.unwrap_or(height);
+
+        if width <= max_width && height <= max_height {
+            return Ok(data);
+        }
+
+        debug!(
+            "Resizing {} from {}x{} to fit within {}x{}",
+            asset.filename.display(),
+            width,
+            height,
+            max_width,
+            max_height
+        );
+        let resized = img.resize(max_width, max_height, FilterType::Lanczos3);
+
+        let mut content = Vec::new();
+        resized
+            .write_to(&mut content, output_format)
+            .with_context(|_| format!("Unable to encode {}", asset.filename.display()))?;
+        data.content = content;
+
+        Ok(data)
+    }
+}
+
+/// Strip comments, `<metadata>` and the whitespace between tags from SVG
+/// images.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MinifySvg;
+
+impl AssetTransformer for MinifySvg {
+    fn transform(&self, asset: &Asset, mut data: AssetData) -> Result<AssetData, Error> {
+        if data.mimetype != "image/svg+xml" {
+            return Ok(data);
+        }
+
+        let svg = String::from_utf8(data.content)
+            .with_context(|_| format!("{} isn't valid UTF-8", asset.filename.display()))?;
+        data.content = minify_svg(&svg).into_bytes();
+
+        Ok(data)
+    }
+}
+
+fn minify_svg(svg: &str) -> String {
+    let svg = remove_between(svg, "<!--", "-->");
+    let svg = remove_between(&svg, "<metadata", "</metadata>");
+
+    let mut minified = String::with_capacity(svg.len());
+    let mut rest = svg.trim();
+    // how many <text> elements we're inside
+    let mut text_depth = 0;
+
+    // whitespace between two tags is insignificant, except inside a <text>
+    // element where it separates runs like `<tspan>a</tspan> <tspan>b</tspan>`
+    while let Some(ix) = rest.find('>') {
+        let tag = &rest[..ix + 1];
+        let tag = &tag[tag.rfind('<').unwrap_or(0)..];
+        if tag_name(tag) == "text" {
+            if tag.starts_with("</") {
+                text_depth -= 1;
+            } else if !tag.ends_with("/>") {
+                text_depth += 1;
+            }
+        }
+
+        minified.push_str(&rest[..ix + 1]);
+        rest = &rest[ix + 1..];
+
+        let trimmed = rest.trim_start();
+        if text_depth == 0 && trimmed.starts_with('<') {
+            rest = trimmed;
+        }
+    }
+    minified.push_str(rest);
+
+    minified
+}
+
+/// Get the name of the element a tag like `<text x="0">` or `</text>` is
+/// for.
+fn tag_name(tag: &str) -> &str {
+    let name = tag.trim_start_matches('<').trim_start_matches('/');
+    let end = name
+        .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+        .unwrap_or(name.len());
+
+    &name[..end]
+}
+
+/// Remove every section of `text` starting with `start` and ending with `end`.
+fn remove_between(text: &str, start: &str, end: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(ix) = rest.find(start) {
+        out.push_str(&rest[..ix]);
+
+        match rest[ix..].find(end) {
+            Some(end_ix) => rest = &rest[ix + end_ix + end.len()..],
+            None => {
+                rest = "";
+                break;
+            }
+        }
+    }
+    out.push_str(rest);
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, ImageBuffer};
+
+    fn asset(name: &str) -> Asset {
+        Asset::new(name, PathBuf::from("/").join(name))
+    }
+
+    #[test]
+    fn minify_an_svg() {
+        let src = r#"<?xml version="1.0"?>
+<!-- Created with a drawing program -->
+<svg xmlns="http://www.w3.org/2000/svg">
+    <metadata><rdf:RDF/></metadata>
+    <text x="0" y="10">Hello  World</text>
+</svg>
+"#;
+        let should_be = r#"<?xml version="1.0"?><svg xmlns="http://www.w3.org/2000/svg"><text x="0" y="10">Hello  World</text></svg>"#;
+
+        let got = minify_svg(src);
+
+        assert_eq!(got, should_be);
+    }
+
+    #[test]
+    fn whitespace_between_text_runs_is_kept() {
+        let src = "<svg>\n  <text><tspan>a</tspan> <tspan>b</tspan></text>\n  <rect/>\n</svg>";
+        let should_be = "<svg><text><tspan>a</tspan> <tspan>b</tspan></text><rect/></svg>";
+
+        let got = minify_svg(src);
+
+        assert_eq!(got, should_be);
+    }
+
+    #[test]
+    fn large_images_are_scaled_down() {
+        let img = DynamicImage::ImageRgb8(ImageBuffer::new(400, 200));
+        let mut content = Vec::new();
+        img.write_to(&mut content, ImageOutputFormat::PNG).unwrap();
+        let data = AssetData {
+            filename: PathBuf::from("big.png"),
+            mimetype: String::from("image/png"),
+            content,
+        };
+        let resize = ResizeImages {
+            max_width: Some(100),
+            max_height: None,
+        };
+
+        let got = resize.transform(&asset("big.png"), data).unwrap();
+
+        let resized = image::load_from_memory(&got.content).unwrap();
+        assert_eq!(resized.dimensions(), (100, 50));
+    }
+
+    #[test]
+    fn unknown_transformers_are_an_error() {
+        let config = AssetConfig {
+            transformers: vec![String::from("make-it-pretty")],
+            ..Default::default()
+        };
+
+        assert!(Transformers::from_config(&config).is_err());
+    }
+
+    #[test]
+    fn transformers_are_applied_in_order() {
+        let mut transformers = Transformers::default();
+        transformers.push(|_: &Asset, mut data: AssetData| {
+            data.content.push(b'a');
+            Ok(data)
+        });
+        transformers.push(|_: &Asset, mut data: AssetData| {
+            data.content.push(b'b');
+            Ok(data)
+        });
+        let data = AssetData {
+            filename: PathBuf::from("file.txt"),
+            mimetype: String::from("text/plain"),
+            content: Vec::new(),
+        };
+
+        let got = transformers.apply(&asset("file.txt"), data).unwrap();
+
+        assert_eq!(got.content, b"ab");
+    }
+}
@@ -0,0 +1,68 @@
+//! Generating one EPUB per translation of a book.
+//!
+//! Each entry in the `output.epub.translations` table is a language whose
+//! chapters live in their own source directory (`src/<lang>/` by default).
+//! The translated book is loaded exactly like the original, but with its
+//! own `book.src` and (optionally) title. The caller is responsible for
+//! rendering it with the translation's language.
+
+use failure::{Error, ResultExt};
+use mdbook::renderer::RenderContext;
+use mdbook::MDBook;
+use std::path::{Path, PathBuf};
+
+use config::TranslationConfig;
+use utils::ResultExt as SyncResultExt;
+
+/// Load the translation of a book into its own `RenderContext`.
+///
+/// Note that `mdbook` only runs preprocessors (e.g. `{{#include}}`) on the
+/// book it's building, so they won't have been applied to a translation.
+pub fn load(
+    ctx: &RenderContext,
+    language: &str,
+    translation: &TranslationConfig,
+) -> Result<RenderContext, Error> {
+    let mut config = ctx.config.clone();
+    config.book.src = match translation.src {
+        Some(ref src) => src.clone(),
+        None => config.book.src.join(language),
+    };
+    if let Some(ref title) = translation.title {
+        config.book.title = Some(title.clone());
+    }
+    if let Some(ref description) = translation.description {
+        config.book.description = Some(description.clone());
+    }
+
+    debug!(
+        "Loading the \"{}\" translation from {}",
+        language,
+        config.book.src.display()
+    );
+    let md = MDBook::load_with_config(&ctx.root, config)
+        .sync()
+        .with_context(|_| format!("Unable to load the \"{}\" translation", language))?;
+
+    let mut translated = RenderContext::new(&ctx.root, md.book, md.config, &ctx.destination);
+    translated.version = ctx.version.clone();
+
+    Ok(translated)
+}
+
+/// Get the filename a translation is written to, e.g. `book.fr.epub`.
+pub fn filename(epub: &Path, language: &str) -> PathBuf {
+    epub.with_extension(format!("{}.epub", language))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn translations_get_the_language_in_their_filename() {
+        let got = filename(Path::new("book/epub/My Book.epub"), "fr");
+
+        assert_eq!(got, Path::new("book/epub/My Book.fr.epub"));
+    }
+}
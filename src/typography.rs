@@ -0,0 +1,411 @@
+//! Opt-in typographic niceties, applied while the book is built because
+//! reading systems vary so much in which CSS they support: drop caps on each
+//! chapter's opening paragraph, French spacing around punctuation, and
+//! hyphenation hints.
+
+use failure::{Error, ResultExt};
+use pulldown_cmark::{Event, Tag};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use config::TypographyConfig;
+use opf;
+
+const SOFT_HYPHEN: char = '\u{ad}';
+const NO_BREAK_SPACE: char = '\u{a0}';
+const NARROW_NO_BREAK_SPACE: char = '\u{202f}';
+
+/// Words shorter than this are never hyphenated.
+const MIN_WORD_LENGTH: usize = 5;
+/// The fewest letters left before (and after) a hyphen.
+const LEFT_MIN: usize = 2;
+const RIGHT_MIN: usize = 3;
+
+/// The typographic processing a book has asked for.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Typography {
+    drop_caps: bool,
+    french_spacing: bool,
+    hyphenator: Option<Hyphenator>,
+}
+
+impl Typography {
+    /// Set up the requested processing. Hyphenation patterns are read
+    /// relative to the book's root directory.
+    pub fn new(
+        config: &TypographyConfig,
+        language: Option<&str>,
+        root: &Path,
+    ) -> Result<Typography, Error> {
+        let french = language
+            .is_some_and(|lang| lang == "fr" || lang.starts_with("fr-") || lang.starts_with("fr_"));
+
+        let hyphenator = match config.hyphenation_patterns {
+            Some(ref patterns) => {
+                let location = root.join(patterns);
+                let text = fs::read_to_string(&location).with_context(|_| {
+                    format!(
+                        "Unable to read the hyphenation patterns, {}",
+                        location.display()
+                    )
+                })?;
+                Some(Hyphenator::parse(&text))
+            }
+            None => None,
+        };
+
+        Ok(Typography {
+            drop_caps: config.drop_caps,
+            french_spacing: config.french_spacing.unwrap_or(french),
+            hyphenator,
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.drop_caps || self.french_spacing || self.hyphenator.is_some()
+    }
+
+    /// Apply every enabled transformation to a chapter.
+    pub fn transform<'a, I>(&self, events: I) -> Vec<Event<'a>>
+    where
+        I: IntoIterator<Item = Event<'a>>,
+    {
+        let mut events: Vec<Event<'a>> = events.into_iter().collect();
+        if !self.is_enabled() {
+            return events;
+        }
+
+        let mut in_code = false;
+        for event in &mut events {
+            match *event {
+                Event::Start(Tag::CodeBlock(_)) | Event::Start(Tag::Code) => in_code = true,
+                Event::End(Tag::CodeBlock(_)) | Event::End(Tag::Code) => in_code = false,
+                Event::Text(ref mut text) => {
+                    if in_code {
+                        continue;
+                    }
+
+                    let mut changed = text.to_string();
+                    if self.french_spacing {
+                        changed = french_spacing(&changed);
+                    }
+                    if let Some(ref hyphenator) = self.hyphenator {
+                        changed = hyphenator.hyphenate_text(&changed);
+                    }
+                    *text = changed.into();
+                }
+                _ => {}
+            }
+        }
+
+        if self.drop_caps {
+            drop_cap(&mut events);
+        }
+
+        events
+    }
+}
+
+/// Give the chapter's first top-level paragraph the `opening` class, and
+/// wrap its first letter (and any punctuation before it) in a `drop-cap`
+/// span.
+fn drop_cap<'a>(events: &mut Vec<Event<'a>>) {
+    let mut depth = 0;
+    let mut opening = None;
+
+    for (i, event) in events.iter().enumerate() {
+        match *event {
+            Event::Start(Tag::Paragraph) if depth == 0 => {
+                opening = Some(i);
+                break;
+            }
+            Event::Start(_) => depth += 1,
+            Event::End(_) => depth -= 1,
+            _ => {}
+        }
+    }
+
+    let start = match opening {
+        Some(start) => start,
+        None => return,
+    };
+    let (initial, rest) = match events.get(start + 1) {
+        Some(Event::Text(text)) => match split_initial(text) {
+            Some((initial, rest)) => (initial.to_string(), rest.to_string()),
+            None => return,
+        },
+        _ => return,
+    };
+
+    let end = events[start..]
+        .iter()
+        .position(|event| matches!(*event, Event::End(Tag::Paragraph)));
+    let end = match end {
+        Some(ix) => start + ix,
+        None => return,
+    };
+
+    events[end] = Event::Html("</p>\n".into());
+    events[start] = Event::Html("<p class=\"opening\">".into());
+    events[start + 1] = Event::Text(rest.into());
+    events.insert(
+        start + 1,
+        Event::InlineHtml(
+            format!("<span class=\"drop-cap\">{}</span>", opf::escape(&initial)).into(),
+        ),
+    );
+}
+
+/// Split a paragraph's text into its first letter (along with any opening
+/// quotes) and the rest.
+fn split_initial(text: &str) -> Option<(&str, &str)> {
+    let ix = text.char_indices().find(|&(_, c)| c.is_alphanumeric())?;
+    if !text[..ix.0]
+        .chars()
+        .all(|c| "\"'\u{201c}\u{2018}\u{ab}".contains(c))
+    {
+        return None;
+    }
+
+    let end = ix.0 + ix.1.len_utf8();
+    Some((&text[..end], &text[end..]))
+}
+
+/// Use the non-breaking spaces French typography puts before `:`, `;`, `!`
+/// and `?`, and inside guillemets.
+fn french_spacing(text: &str) -> String {
+    let mut spaced = String::with_capacity(text.len());
+    let chars: Vec<char> = text.chars().collect();
+
+    for (i, &c) in chars.iter().enumerate() {
+        let next = chars.get(i + 1).cloned();
+        let previous = if i > 0 { Some(chars[i - 1]) } else { None };
+
+        match c {
+            ' ' if next == Some(':') || next == Some('\u{bb}') => spaced.push(NO_BREAK_SPACE),
+            ' ' if next == Some(';') || next == Some('!') || next == Some('?') => {
+                spaced.push(NARROW_NO_BREAK_SPACE)
+            }
+            ' ' if previous == Some('\u{ab}') => spaced.push(NO_BREAK_SPACE),
+            other => spaced.push(other),
+        }
+    }
+
+    spaced
+}
+
+/// Hyphenation using Liang's algorithm (as used by TeX), with patterns from
+/// a file like those in the `hyph-utf8` project (e.g. `hyph-en-us.pat.txt`).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Hyphenator {
+    /// The priorities between each letter of a pattern, keyed by the
+    /// pattern's letters.
+    patterns: HashMap<String, Vec<u8>>,
+    longest: usize,
+}
+
+impl Hyphenator {
+    /// Parse a whitespace-separated list of patterns (e.g. `hy3ph`). TeX
+    /// comments and commands are skipped.
+    pub fn parse(text: &str) -> Hyphenator {
+        let mut hyphenator = Hyphenator::default();
+
+        for line in text.lines() {
+            let line = line.split('%').next().unwrap_or_default();
+
+            for pattern in line.split_whitespace() {
+                if pattern.contains(['\\', '{', '}']) {
+                    continue;
+                }
+
+                let mut letters = String::new();
+                let mut priorities = Vec::new();
+                let mut pending = 0;
+
+                for c in pattern.chars() {
+                    match c.to_digit(10) {
+                        Some(digit) => pending = digit as u8,
+                        None => {
+                            priorities.push(pending);
+                            pending = 0;
+                            letters.extend(c.to_lowercase());
+                        }
+                    }
+                }
+                priorities.push(pending);
+
+                hyphenator.longest = hyphenator.longest.max(letters.chars().count());
+                hyphenator.patterns.insert(letters, priorities);
+            }
+        }
+
+        hyphenator
+    }
+
+    /// Insert soft hyphens into every long enough word, leaving anything
+    /// which looks like a URL or email address alone.
+    pub fn hyphenate_text(&self, text: &str) -> String {
+        let mut hyphenated = String::with_capacity(text.len());
+        let mut token = String::new();
+
+        for c in text.chars() {
+            if c.is_whitespace() {
+                hyphenated.push_str(&self.hyphenate_token(&token));
+                token.clear();
+                hyphenated.push(c);
+            } else {
+                token.push(c);
+            }
+        }
+        hyphenated.push_str(&self.hyphenate_token(&token));
+
+        hyphenated
+    }
+
+    fn hyphenate_token(&self, token: &str) -> String {
+        if token.contains('/') || token.contains('@') {
+            return token.to_string();
+        }
+
+        let mut hyphenated = String::with_capacity(token.len());
+        let mut word = String::new();
+
+        for c in token.chars() {
+            if c.is_alphabetic() {
+                word.push(c);
+            } else {
+                hyphenated.push_str(&self.hyphenate(&word));
+                word.clear();
+                hyphenated.push(c);
+            }
+        }
+        hyphenated.push_str(&self.hyphenate(&word));
+
+        hyphenated
+    }
+
+    /// Insert soft hyphens into a single word.
+    pub fn hyphenate(&self, word: &str) -> String {
+        let letters: Vec<char> = word.chars().collect();
+        if letters.len() < MIN_WORD_LENGTH {
+            return word.to_string();
+        }
+
+        let dotted: Vec<char> = Some('.')
+            .into_iter()
+            .chain(word.chars().flat_map(char::to_lowercase))
+            .chain(Some('.'))
+            .collect();
+        if dotted.len() != letters.len() + 2 {
+            // lowercasing changed the length, so the positions won't line up
+            return word.to_string();
+        }
+
+        let mut points = vec![0; dotted.len() + 1];
+        for i in 0..dotted.len() {
+            let longest = (i + self.longest).min(dotted.len());
+            for j in i + 1..=longest {
+                let part: String = dotted[i..j].iter().collect();
+                if let Some(priorities) = self.patterns.get(&part) {
+                    for (k, &priority) in priorities.iter().enumerate() {
+                        points[i + k] = points[i + k].max(priority);
+                    }
+                }
+            }
+        }
+
+        let mut hyphenated = String::with_capacity(word.len() + 4);
+        for (k, &c) in letters.iter().enumerate() {
+            if k >= LEFT_MIN && letters.len() - k >= RIGHT_MIN && points[k + 1] % 2 == 1 {
+                hyphenated.push(SOFT_HYPHEN);
+            }
+            hyphenated.push(c);
+        }
+
+        hyphenated
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::{html, Parser};
+
+    /// The patterns Liang's thesis uses to hyphenate "hyphenation".
+    const PATTERNS: &str = "% a comment\nhy3ph he2n hena4 hen5at\n1na n2at 1tio 2io o2n\n";
+
+    fn render(src: &str, typography: &Typography) -> String {
+        let mut buffer = String::new();
+        html::push_html(
+            &mut buffer,
+            typography.transform(Parser::new(src)).into_iter(),
+        );
+        buffer
+    }
+
+    #[test]
+    fn hyphenate_with_patterns() {
+        let hyphenator = Hyphenator::parse(PATTERNS);
+
+        assert_eq!(
+            hyphenator.hyphenate("Hyphenation"),
+            "Hy\u{ad}phen\u{ad}ation"
+        );
+        assert_eq!(hyphenator.hyphenate("hyph"), "hyph");
+    }
+
+    #[test]
+    fn only_words_outside_code_are_hyphenated() {
+        let typography = Typography {
+            hyphenator: Some(Hyphenator::parse(PATTERNS)),
+            ..Default::default()
+        };
+
+        let got = render(
+            "hyphenation\n\n```\nhyphenation\n```\n\n`hyphenation`\n",
+            &typography,
+        );
+
+        assert_eq!(got.matches('\u{ad}').count(), 2);
+        assert!(got.contains("<code>hyphenation</code>"));
+    }
+
+    #[test]
+    fn french_punctuation_gets_non_breaking_spaces() {
+        let got = french_spacing("Quoi ? Il a dit : « bonjour » ; voilà !");
+
+        assert_eq!(
+            got,
+            "Quoi\u{202f}? Il a dit\u{a0}: «\u{a0}bonjour\u{a0}»\u{202f}; voilà\u{202f}!"
+        );
+    }
+
+    #[test]
+    fn the_opening_paragraph_gets_a_drop_cap() {
+        let typography = Typography {
+            drop_caps: true,
+            ..Default::default()
+        };
+        let src = "# Chapter 1\n\n> A quote\n\n\u{201c}Once upon a time.\n\nThe end.\n";
+
+        let got = render(src, &typography);
+
+        assert!(got.contains(
+            "<p class=\"opening\"><span class=\"drop-cap\">\u{201c}O</span>nce upon a time.</p>"
+        ));
+        assert_eq!(got.matches("drop-cap").count(), 1);
+        assert!(got.contains("<blockquote>\n<p>A quote</p>"));
+    }
+
+    #[test]
+    fn french_spacing_defaults_to_the_language() {
+        let config = TypographyConfig::default();
+
+        let french = Typography::new(&config, Some("fr-CA"), Path::new(".")).unwrap();
+        let english = Typography::new(&config, Some("en"), Path::new(".")).unwrap();
+
+        assert!(french.french_spacing);
+        assert!(!english.is_enabled());
+    }
+}
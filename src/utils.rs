@@ -1,5 +1,6 @@
 use failure::SyncFailure;
 use std::error::Error as StdError;
+use std::io::{self, Seek, SeekFrom, Write};
 
 pub trait ResultExt<T, E> {
     fn sync(self) -> Result<T, SyncFailure<E>>
@@ -17,3 +18,67 @@ impl<T, E> ResultExt<T, E> for Result<T, E> {
         self.map_err(SyncFailure::new)
     }
 }
+
+/// A writer which throws away everything written to it, while still
+/// supporting seeking (as required when writing a zip archive).
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct Discard {
+    position: u64,
+    length: u64,
+}
+
+impl Write for Discard {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.position += buf.len() as u64;
+        self.length = self.length.max(self.position);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for Discard {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_position = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(offset) => offset_by(self.length, offset),
+            SeekFrom::Current(offset) => offset_by(self.position, offset),
+        };
+
+        match new_position {
+            Some(n) => {
+                self.position = n;
+                Ok(n)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Attempted to seek before the start of the stream",
+            )),
+        }
+    }
+}
+
+fn offset_by(base: u64, offset: i64) -> Option<u64> {
+    if offset >= 0 {
+        base.checked_add(offset as u64)
+    } else {
+        base.checked_sub(offset.wrapping_neg() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn discard_keeps_track_of_its_position() {
+        let mut writer = Discard::default();
+
+        writer.write_all(b"Hello, World!").unwrap();
+        assert_eq!(writer.seek(SeekFrom::Current(-6)).unwrap(), 7);
+        assert_eq!(writer.seek(SeekFrom::End(0)).unwrap(), 13);
+        assert!(writer.seek(SeekFrom::Current(-20)).is_err());
+    }
+}
@@ -5,20 +5,23 @@ extern crate mdbook_epub;
 extern crate tempdir;
 
 use epub::doc::EpubDoc;
-use failure::{Error, SyncFailure};
+use failure::{err_msg, Error, SyncFailure};
 use mdbook::renderer::RenderContext;
 use mdbook::MDBook;
+use mdbook_epub::{Asset, ChapterInfo, Config, Generator, MetadataFormat};
+use std::fs::{self, File};
+use std::io::BufReader;
 use std::path::Path;
 use tempdir::TempDir;
 
 /// Convenience function for compiling the dummy book into an `EpubDoc`.
-fn generate_epub() -> Result<EpubDoc, Error> {
+fn generate_epub() -> Result<EpubDoc<BufReader<File>>, Error> {
     let (ctx, _md, temp) = create_dummy_book().unwrap();
     mdbook_epub::generate(&ctx)?;
     let output_file = mdbook_epub::output_filename(temp.path(), &ctx.config);
 
     let output_file = output_file.display().to_string();
-    EpubDoc::new(&output_file).map_err(Error::from)
+    EpubDoc::new(&output_file).map_err(|e| err_msg(e.to_string()))
 }
 
 #[test]
@@ -56,6 +59,135 @@ fn look_for_chapter_1_heading() {
     assert!(content.contains("<h1>Chapter 1</h1>"));
 }
 
+#[test]
+fn chapters_are_marked_as_body_matter() {
+    let mut doc = generate_epub().unwrap();
+
+    let path = Path::new("OEBPS").join("chapter_1.html");
+    let path = path.display().to_string();
+    let content = doc.get_resource_str_by_path(path).unwrap();
+
+    assert!(content.contains(r#"<body epub:type="bodymatter">"#));
+}
+
+#[test]
+fn style_tokens_are_declared_in_the_stylesheet() {
+    let mut doc = generate_epub().unwrap();
+
+    let path = Path::new("OEBPS").join("stylesheet.css");
+    let path = path.display().to_string();
+    let content = doc.get_resource_str_by_path(path).unwrap();
+
+    assert!(content.contains(":root {\n    --accent-color: #c0392b;\n    --line-height: 1.5;\n}"));
+    assert!(content.contains("var(--accent-color, #2E8DE0)"));
+}
+
+#[test]
+fn title_page_is_generated() {
+    let mut doc = generate_epub().unwrap();
+
+    let path = Path::new("OEBPS").join("title_page.xhtml");
+    let path = path.display().to_string();
+    let content = doc.get_resource_str_by_path(path).unwrap();
+
+    assert!(content.contains("Dummy Book"));
+}
+
+#[test]
+fn copyright_page_is_generated() {
+    let mut doc = generate_epub().unwrap();
+
+    let path = Path::new("OEBPS").join("copyright.xhtml");
+    let path = path.display().to_string();
+    let content = doc.get_resource_str_by_path(path).unwrap();
+
+    assert!(content.contains(r#"epub:type="copyright-page""#));
+    assert!(content.contains("2018 The Dummy Authors"));
+    assert!(content.contains("Released under the MIT license."));
+}
+
+#[test]
+fn about_page_is_generated() {
+    let mut doc = generate_epub().unwrap();
+
+    let path = Path::new("OEBPS").join("about.xhtml");
+    let path = path.display().to_string();
+    let content = doc.get_resource_str_by_path(path).unwrap();
+
+    assert!(content.contains("About this book"));
+    assert!(content.contains("Chapter 1"));
+}
+
+#[test]
+fn build_manifest_lists_chapters_and_assets() {
+    let (ctx, _md, temp) = create_dummy_book().unwrap();
+    mdbook_epub::generate(&ctx).unwrap();
+
+    let output_file = mdbook_epub::output_filename(temp.path(), &ctx.config);
+    let manifest = mdbook_epub::manifest_filename(&output_file);
+    let content = fs::read_to_string(manifest).unwrap();
+
+    assert!(content.contains("chapter_1.html"));
+    assert!(content.contains("rust-logo.png"));
+    assert!(content.contains("image/png"));
+    assert!(content.contains("\"reading_time\""));
+}
+
+#[test]
+fn cover_thumbnail_is_used_for_the_cover_meta() {
+    let (ctx, _md, temp) = create_dummy_book().unwrap();
+    mdbook_epub::generate(&ctx).unwrap();
+
+    let output_file = mdbook_epub::output_filename(temp.path(), &ctx.config);
+    let mut doc = EpubDoc::new(&output_file).unwrap();
+    let manifest = mdbook_epub::manifest_filename(&output_file);
+    let content = fs::read_to_string(manifest).unwrap();
+
+    // the <meta name="cover"> element is how older reading systems find it
+    let cover_id = doc.get_cover_id().unwrap();
+    assert!(doc.resources[&cover_id].0.ends_with("cover-thumbnail.png"));
+    assert!(doc.get_cover().is_ok());
+    assert!(content.contains("\"thumbnail\": \"cover-thumbnail.png\""));
+}
+
+#[test]
+fn credited_assets_get_a_credits_page() {
+    let (ctx, _md, temp) = create_dummy_book().unwrap();
+    mdbook_epub::generate(&ctx).unwrap();
+
+    let output_file = mdbook_epub::output_filename(temp.path(), &ctx.config);
+    let mut doc = EpubDoc::new(&output_file).unwrap();
+    let page = doc
+        .get_resource_str_by_path(
+            Path::new("OEBPS")
+                .join("credits.xhtml")
+                .display()
+                .to_string(),
+        )
+        .unwrap();
+    let manifest = mdbook_epub::manifest_filename(&output_file);
+    let manifest = fs::read_to_string(manifest).unwrap();
+
+    assert!(page.contains(
+        "<cite><a href=\"https://github.com/rust-lang/rust-artwork\">Rust logo</a></cite>"
+    ));
+    assert!(page.contains("https://creativecommons.org/licenses/by/4.0/"));
+    assert!(manifest.contains("\"author\": \"The Rust Project Developers\""));
+}
+
+#[test]
+fn onix_record_is_exported() {
+    let (ctx, _md, temp) = create_dummy_book().unwrap();
+    mdbook_epub::generate(&ctx).unwrap();
+
+    let output_file = mdbook_epub::output_filename(temp.path(), &ctx.config);
+    let record = mdbook_epub::metadata_filename(&output_file, MetadataFormat::Onix);
+    let content = fs::read_to_string(record).unwrap();
+
+    assert!(content.contains("<TitleText>Dummy Book</TitleText>"));
+    assert!(!content.contains("<RecordReference></RecordReference>"));
+}
+
 #[test]
 fn rendered_document_contains_all_chapter_files_and_assets() {
     let chapters = vec!["chapter_1.html", "rust-logo.png"];
@@ -70,6 +202,181 @@ fn rendered_document_contains_all_chapter_files_and_assets() {
     }
 }
 
+#[test]
+fn assets_with_spaces_and_accents_are_encoded() {
+    let mut doc = generate_epub().unwrap();
+
+    let got = doc.get_resource_by_path("OEBPS/rust logo \u{e9}.png");
+    assert!(got.is_ok());
+
+    let chapter = doc
+        .get_resource_str_by_path("OEBPS/chapter_1.html")
+        .unwrap();
+    assert!(chapter.contains(r#"src="rust%20logo%20%C3%A9.png""#));
+}
+
+#[test]
+fn sources_and_build_info_are_embedded() {
+    let mut doc = generate_epub().unwrap();
+
+    assert!(doc
+        .get_resource_by_path("OEBPS/sources/src/chapter_1.md")
+        .is_ok());
+    assert!(doc.get_resource_by_path("OEBPS/sources/book.toml").is_ok());
+    let info = doc
+        .get_resource_str_by_path("OEBPS/sources/build-info.json")
+        .unwrap();
+    assert!(info.contains("\"mdbook_epub_version\""));
+}
+
+#[test]
+fn epub2_navigation_is_kept_by_default() {
+    let mut doc = generate_epub().unwrap();
+
+    assert!(doc.get_resource_by_path("OEBPS/toc.ncx").is_ok());
+    assert!(doc.get_resource_by_path("OEBPS/nav.xhtml").is_ok());
+}
+
+#[test]
+fn night_mode_stylesheet_is_included() {
+    let mut doc = generate_epub().unwrap();
+
+    assert!(doc.get_resource_by_path("OEBPS/night.css").is_ok());
+    let stylesheet = doc
+        .get_resource_str_by_path("OEBPS/stylesheet.css")
+        .unwrap();
+    assert!(stylesheet.starts_with("@import url(\"night.css\") (prefers-color-scheme: dark);"));
+    let chapter = doc
+        .get_resource_str_by_path("OEBPS/chapter_1.html")
+        .unwrap();
+    assert!(chapter.contains(r#"class="night" title="Night" href="night.css""#));
+}
+
+#[test]
+fn chapters_can_link_their_own_stylesheets() {
+    let mut doc = generate_epub().unwrap();
+
+    assert!(doc.get_resource_by_path("OEBPS/wide.css").is_ok());
+
+    let chapter = doc
+        .get_resource_str_by_path("OEBPS/chapter_1.html")
+        .unwrap();
+    assert!(chapter.contains(r#"href="wide.css""#));
+}
+
+#[test]
+fn print_pages_are_in_the_page_list() {
+    let mut doc = generate_epub().unwrap();
+
+    let chapter = doc
+        .get_resource_str_by_path("OEBPS/chapter_1.html")
+        .unwrap();
+    assert!(chapter.contains(r#"epub:type="pagebreak" id="page-2""#));
+
+    let nav = doc.get_resource_str_by_path("OEBPS/nav.xhtml").unwrap();
+    assert!(nav.contains(r#"<nav epub:type="page-list""#));
+    assert!(nav.contains(r#"<a href="chapter_1.html#page-2">2</a>"#));
+}
+
+#[test]
+fn table_of_contents_is_numbered_by_default() {
+    let mut doc = generate_epub().unwrap();
+
+    let nav = doc.get_resource_str_by_path("OEBPS/nav.xhtml").unwrap();
+    assert!(nav.contains(">1. Chapter 1</a>"));
+}
+
+#[test]
+fn chapters_can_be_post_processed() {
+    let (ctx, _md, temp) = create_dummy_book().unwrap();
+    let output_file = temp.path().join("post-processed.epub");
+
+    {
+        let f = File::create(&output_file).unwrap();
+        Generator::new(&ctx, f)
+            .unwrap()
+            .with_postprocessor(|info: &ChapterInfo, xhtml: String| {
+                let watermark = format!("<!-- {} -->\n</body>", info.chapter.name);
+                Ok(xhtml.replace("</body>", &watermark))
+            })
+            .generate()
+            .unwrap();
+    }
+
+    let mut doc = EpubDoc::new(&output_file).unwrap();
+    let chapter = doc
+        .get_resource_str_by_path("OEBPS/chapter_1.html")
+        .unwrap();
+    assert!(chapter.contains("<!-- Chapter 1 -->"));
+}
+
+#[test]
+fn extra_assets_can_be_added() {
+    let (ctx, _md, temp) = create_dummy_book().unwrap();
+    let output_file = temp.path().join("extra-assets.epub");
+    let generated = temp.path().join("plot.svg");
+    fs::write(&generated, "<svg xmlns=\"http://www.w3.org/2000/svg\"/>").unwrap();
+
+    {
+        let f = File::create(&output_file).unwrap();
+        let report = Generator::new(&ctx, f)
+            .unwrap()
+            .with_asset(Asset::new("generated/plot.svg", &generated))
+            .generate()
+            .unwrap();
+        assert!(report
+            .assets
+            .iter()
+            .any(|asset| asset.path == Path::new("generated/plot.svg")));
+    }
+
+    let mut doc = EpubDoc::new(&output_file).unwrap();
+    let plot = doc
+        .get_resource_str_by_path("OEBPS/generated/plot.svg")
+        .unwrap();
+    assert!(plot.contains("<svg"));
+}
+
+#[test]
+fn dry_runs_return_the_report_without_writing_anything() {
+    let (mut ctx, _md, _temp) = create_dummy_book().unwrap();
+    ctx.config.set("output.epub.dry-run", true).unwrap();
+
+    let books = mdbook_epub::generate_with_progress(&ctx, |_| {}).unwrap();
+
+    assert_eq!(books.len(), 1);
+    assert!(books[0].dry_run);
+    assert!(books[0]
+        .report
+        .spine
+        .iter()
+        .any(|page| page == "chapter_1.html"));
+    assert!(!books[0].path.exists());
+    assert!(!mdbook_epub::manifest_filename(&books[0].path).exists());
+}
+
+#[test]
+fn found_assets_are_the_ones_which_get_packaged() {
+    let (ctx, _md, _temp) = create_dummy_book().unwrap();
+    let config = Config::from_render_context(&ctx).unwrap();
+
+    let found = mdbook_epub::find_assets(&ctx, &config).unwrap();
+
+    let mut found: Vec<_> = found.iter().map(|asset| asset.filename.clone()).collect();
+    found.sort();
+    found.dedup();
+    assert!(found.contains(&Path::new("wide.css").to_path_buf()));
+    let mut doc = generate_epub().unwrap();
+    for filename in &found {
+        let path = Path::new("OEBPS").join(filename);
+        assert!(
+            doc.get_resource_by_path(&path).is_ok(),
+            "{}",
+            path.display()
+        );
+    }
+}
+
 /// Use `MDBook::load()` to load the dummy book into memory, then set up the
 /// `RenderContext` for use the EPUB generator.
 fn create_dummy_book() -> Result<(RenderContext, MDBook, TempDir), Error> {